@@ -0,0 +1,271 @@
+use crate::{
+    AssetDescriptor, AssetIndex, AssetLocator, AssetPayload, AssetQuery, AssetStore,
+    AssetStoreError, AsyncAssetIndex, AsyncAssetPayload, AsyncAssetStore, ListAssetsError,
+    RetryPolicy,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::io::Read;
+use std::sync::Arc;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::io::{StreamReader, SyncIoBridge};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Exposes a synchronous index or store through the async traits. Calls run on tokio's
+/// blocking thread pool, so they never block the async worker threads.
+pub struct AsyncAdapter<T> {
+    inner: Arc<T>,
+}
+
+impl<T> AsyncAdapter<T> {
+    pub fn new(inner: T) -> Self {
+        AsyncAdapter {
+            inner: Arc::new(inner),
+        }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+}
+
+#[async_trait]
+impl<T> AsyncAssetIndex for AsyncAdapter<T>
+where
+    T: AssetIndex + Send + Sync + 'static,
+{
+    async fn list_assets(
+        &self,
+        query: &AssetQuery,
+    ) -> Result<Vec<AssetDescriptor>, ListAssetsError> {
+        let inner = self.inner.clone();
+        let query = query.clone();
+        tokio::task::spawn_blocking(move || inner.list_assets(&query))
+            .await
+            .map_err(|e| ListAssetsError::AssetIndexInternalError(e.to_string()))?
+    }
+}
+
+#[async_trait]
+impl<T> AsyncAssetStore for AsyncAdapter<T>
+where
+    T: AssetStore + Send + Sync + 'static,
+{
+    fn supports_locator(&self, locator: &AssetLocator) -> bool {
+        self.inner.supports_locator(locator)
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.inner.retry_policy()
+    }
+
+    async fn fetch_by_locator(
+        &self,
+        locator: &AssetLocator,
+        expected_hash: &str,
+    ) -> Result<AsyncAssetPayload, AssetStoreError> {
+        let inner = self.inner.clone();
+        let locator = locator.clone();
+        let expected_hash = expected_hash.to_owned();
+        run_blocking(move || inner.fetch_by_locator(&locator, &expected_hash))
+            .await
+            .map(into_async_payload)
+    }
+
+    async fn fetch_by_descriptor(
+        &self,
+        descriptor: &AssetDescriptor,
+    ) -> Result<AsyncAssetPayload, AssetStoreError> {
+        let inner = self.inner.clone();
+        let descriptor = descriptor.clone();
+        run_blocking(move || inner.fetch_by_descriptor(&descriptor))
+            .await
+            .map(into_async_payload)
+    }
+}
+
+/// Exposes an async index or store through the synchronous traits by blocking on the given
+/// runtime. Calls must be made from outside the runtime, e.g. from a thread started with
+/// `tokio::task::spawn_blocking`, since blocking on a runtime from within it panics.
+pub struct BlockingAdapter<T> {
+    inner: T,
+    runtime: Handle,
+}
+
+impl<T> BlockingAdapter<T> {
+    pub fn new(inner: T, runtime: Handle) -> Self {
+        BlockingAdapter { inner, runtime }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> AssetIndex for BlockingAdapter<T>
+where
+    T: AsyncAssetIndex,
+{
+    fn list_assets(&self, query: &AssetQuery) -> Result<Vec<AssetDescriptor>, ListAssetsError> {
+        self.runtime.block_on(self.inner.list_assets(query))
+    }
+}
+
+impl<T> AssetStore for BlockingAdapter<T>
+where
+    T: AsyncAssetStore,
+{
+    fn supports_locator(&self, locator: &AssetLocator) -> bool {
+        self.inner.supports_locator(locator)
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.inner.retry_policy()
+    }
+
+    fn fetch_by_locator(
+        &self,
+        locator: &AssetLocator,
+        expected_hash: &str,
+    ) -> Result<AssetPayload, AssetStoreError> {
+        self.runtime
+            .block_on(self.inner.fetch_by_locator(locator, expected_hash))
+            .map(|payload| into_blocking_payload(payload, self.runtime.clone()))
+    }
+
+    fn fetch_by_descriptor(
+        &self,
+        descriptor: &AssetDescriptor,
+    ) -> Result<AssetPayload, AssetStoreError> {
+        self.runtime
+            .block_on(self.inner.fetch_by_descriptor(descriptor))
+            .map(|payload| into_blocking_payload(payload, self.runtime.clone()))
+    }
+}
+
+/// Runs blocking work on tokio's blocking thread pool.
+pub(crate) async fn run_blocking<F, R>(work: F) -> Result<R, AssetStoreError>
+where
+    F: FnOnce() -> Result<R, AssetStoreError> + Send + 'static,
+    R: Send + 'static,
+{
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|e| AssetStoreError::AssetStoreInternalError(e.to_string()))?
+}
+
+/// Reads an async payload from blocking code. Must be read outside of the runtime, e.g.
+/// within `run_blocking`.
+pub(crate) fn into_blocking_payload(payload: AsyncAssetPayload, runtime: Handle) -> AssetPayload {
+    match payload {
+        AsyncAssetPayload::Bytes(buf) => AssetPayload::Bytes(buf),
+        AsyncAssetPayload::Stream(reader) => {
+            AssetPayload::Stream(Box::new(SyncIoBridge::new_with_handle(reader, runtime)))
+        }
+    }
+}
+
+/// Streams a synchronous payload to async readers. The payload is read on the blocking
+/// thread pool, a chunk at a time, and reading stops once the async reader is dropped.
+pub(crate) fn into_async_payload(payload: AssetPayload) -> AsyncAssetPayload {
+    let mut reader = match payload {
+        AssetPayload::Bytes(buf) => return AsyncAssetPayload::Bytes(buf),
+        AssetPayload::Stream(reader) => reader,
+    };
+    let (sender, receiver) = mpsc::channel::<std::io::Result<Bytes>>(4);
+    tokio::task::spawn_blocking(move || loop {
+        let mut buf = vec![0; CHUNK_SIZE];
+        let chunk = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => {
+                buf.truncate(read);
+                Ok(Bytes::from(buf))
+            }
+            Err(e) => Err(e),
+        };
+        let failed = chunk.is_err();
+        if sender.blocking_send(chunk).is_err() || failed {
+            break;
+        }
+    });
+    AsyncAssetPayload::Stream(Box::pin(StreamReader::new(ReceiverStream::new(receiver))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AsyncAdapter, BlockingAdapter};
+    use crate::{
+        AssetLocator, AssetPayload, AssetStore, AssetStoreError, AsyncAssetStore,
+        HashValidatingReader,
+    };
+    use std::io::Read;
+    use std::str::FromStr;
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    struct StreamingStore {
+        content: &'static str,
+    }
+
+    impl AssetStore for StreamingStore {
+        fn supports_locator(&self, _locator: &AssetLocator) -> bool {
+            true
+        }
+
+        fn fetch_by_locator(
+            &self,
+            _locator: &AssetLocator,
+            expected_hash: &str,
+        ) -> Result<AssetPayload, AssetStoreError> {
+            Ok(AssetPayload::Stream(Box::new(HashValidatingReader::new(
+                self.content.as_bytes(),
+                expected_hash,
+            ))))
+        }
+    }
+
+    fn locator() -> AssetLocator {
+        AssetLocator::new(reqwest::Url::from_str("https://example.com/hello").unwrap())
+    }
+
+    #[tokio::test]
+    async fn streams_sync_payloads_to_async_readers() {
+        let store = AsyncAdapter::new(StreamingStore { content: "hello" });
+        let payload = AsyncAssetStore::fetch_by_locator(&store, &locator(), HELLO_SHA256)
+            .await
+            .unwrap();
+        assert_eq!(payload.into_bytes().await.unwrap(), b"hello");
+
+        let store = AsyncAdapter::new(StreamingStore { content: "hullo" });
+        let payload = AsyncAssetStore::fetch_by_locator(&store, &locator(), HELLO_SHA256)
+            .await
+            .unwrap();
+        assert!(matches!(
+            payload.into_bytes().await,
+            Err(AssetStoreError::AssetHashMismatch { .. })
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn round_trips_through_both_adapters() {
+        let store = BlockingAdapter::new(
+            AsyncAdapter::new(StreamingStore { content: "hello" }),
+            tokio::runtime::Handle::current(),
+        );
+        let content = tokio::task::spawn_blocking(move || {
+            let mut content = String::new();
+            AssetStore::fetch_by_locator(&store, &locator(), HELLO_SHA256)
+                .unwrap()
+                .into_reader()
+                .read_to_string(&mut content)
+                .unwrap();
+            content
+        })
+        .await
+        .unwrap();
+        assert_eq!(content, "hello");
+    }
+}
@@ -1,10 +1,14 @@
 use crate::{AssetDescriptor, AssetQuery};
+use async_trait::async_trait;
+use std::time::Duration;
 use thiserror::Error;
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone)]
 pub enum ListAssetsError {
     #[error("Asset index is missing or unavailable.")]
     AssetIndexNotFound(Option<String>),
+    #[error("Asset index is temporarily unavailable. Details: {0}")]
+    AssetIndexUnavailable(String),
     #[error("Asset index refused access.")]
     AssetIndexAccessDenied(Option<String>),
     #[error("Failed to execute the query. Details: {0}")]
@@ -15,6 +19,69 @@ pub enum ListAssetsError {
     MisconfiguredIndex(String)
 }
 
+impl ListAssetsError {
+    /// Whether the error means the index couldn't be reached or failed on its side with a
+    /// server error, as opposed to rejecting the query, the caller or its configuration.
+    pub(crate) fn is_outage(&self) -> bool {
+        matches!(self, ListAssetsError::AssetIndexUnavailable(_))
+    }
+
+    /// The error of an offline cache asked for a query it holds no results for.
+    pub(crate) fn not_cached_offline() -> Self {
+        ListAssetsError::AssetIndexNotFound(Some(
+            "The cache is offline and holds no results for the query.".to_owned(),
+        ))
+    }
+}
+
+/// Whether a cache answered a query with results within its max age.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Freshness {
+    Fresh,
+    /// The results are past the cache's max age, and were served because the inner index
+    /// failed or the cache is offline. Holds the age of the results.
+    Stale(Duration),
+}
+
+impl Freshness {
+    /// The freshness of cached results of the given age, or `None` if they are too old to
+    /// be served. Results past `max_age` are only served if they expired less than
+    /// `max_staleness` ago, or whatever their age when `max_staleness` is `None`.
+    pub(crate) fn of(
+        age: Duration,
+        max_age: Duration,
+        max_staleness: Option<Duration>,
+    ) -> Option<Freshness> {
+        if age < max_age {
+            Some(Freshness::Fresh)
+        } else if max_staleness.is_none_or(|staleness| age < max_age.saturating_add(staleness)) {
+            Some(Freshness::Stale(age))
+        } else {
+            None
+        }
+    }
+}
+
 pub trait AssetIndex {
     fn list_assets(&self, query: &AssetQuery) -> Result<Vec<AssetDescriptor>, ListAssetsError>;
 }
+
+/// The async counterpart of `AssetIndex`. Indexes that talk to the network implement both
+/// traits; `AsyncAdapter` and `BlockingAdapter` bridge indexes that only implement one.
+#[async_trait]
+pub trait AsyncAssetIndex: Send + Sync {
+    async fn list_assets(&self, query: &AssetQuery) -> Result<Vec<AssetDescriptor>, ListAssetsError>;
+}
+
+#[async_trait]
+impl<T> AsyncAssetIndex for Box<T>
+where
+    T: AsyncAssetIndex + ?Sized,
+{
+    async fn list_assets(
+        &self,
+        query: &AssetQuery,
+    ) -> Result<Vec<AssetDescriptor>, ListAssetsError> {
+        (**self).list_assets(query).await
+    }
+}
\ No newline at end of file
@@ -0,0 +1,99 @@
+use crate::{AssetDescriptor, AssetPayload, AssetStoreError, ContentHasher, HashAlgorithm, SemVer};
+use std::io::{ErrorKind, Read, Write};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum PublishAssetError {
+    #[error("Version {version} of asset '{name}' has already been published.")]
+    AssetAlreadyExists { name: String, version: String },
+    #[error("The asset name '{0}' can't be published. Names must not be empty or contain '/', '\\', '*' or control characters.")]
+    InvalidAssetName(String),
+    #[error("Failed to read the asset content. Details: {0}")]
+    PayloadError(AssetStoreError),
+    #[error("Publisher refused access.")]
+    AccessDenied(Option<String>),
+    #[error("Failed to publish the asset. Details: {0}")]
+    PublisherInternalError(String),
+    #[error("The publisher was not configured properly. Details: {0}")]
+    MisconfiguredPublisher(String),
+}
+
+impl From<std::io::Error> for PublishAssetError {
+    fn from(e: std::io::Error) -> Self {
+        PublishAssetError::PublisherInternalError(e.to_string())
+    }
+}
+
+/// The write side of an asset index and store: publishing an asset makes its content
+/// available and adds it to the index.
+pub trait AssetPublisher {
+    /// Publishes the payload as the given version of the named asset. The size and
+    /// content hash are computed from the payload. Publishing a name and version that
+    /// already exists fails with `AssetAlreadyExists`.
+    fn publish(
+        &self,
+        name: &str,
+        version: &SemVer,
+        payload: AssetPayload,
+    ) -> Result<AssetDescriptor, PublishAssetError>;
+}
+
+/// Asset names become part of storage paths (`name/version/...`) and must be usable as
+/// exact name constraints, so path separators and wildcards aren't allowed.
+pub fn validate_asset_name(name: &str) -> Result<(), PublishAssetError> {
+    if name.is_empty()
+        || name == "."
+        || name == ".."
+        || name
+            .chars()
+            .any(|c| c == '/' || c == '\\' || c == '*' || c.is_control())
+    {
+        Err(PublishAssetError::InvalidAssetName(name.to_owned()))
+    } else {
+        Ok(())
+    }
+}
+
+/// Copies the payload into the writer, returning the content hash and size of what was
+/// written. Publishers always record SHA-256 hashes.
+pub(crate) fn copy_and_hash(
+    payload: AssetPayload,
+    writer: &mut impl Write,
+) -> Result<(String, usize), PublishAssetError> {
+    let mut reader = payload.into_reader();
+    let mut hasher = ContentHasher::new(HashAlgorithm::Sha256);
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let read = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(PublishAssetError::PayloadError(e.into())),
+        };
+        hasher.update(&buf[..read]);
+        writer
+            .write_all(&buf[..read])
+            .map_err(|e| PublishAssetError::PublisherInternalError(e.to_string()))?;
+        size += read;
+    }
+    writer
+        .flush()
+        .map_err(|e| PublishAssetError::PublisherInternalError(e.to_string()))?;
+    Ok((hasher.finalize(), size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate_asset_name;
+
+    #[test]
+    fn asset_name_validation() {
+        assert!(validate_asset_name("simple_test").is_ok());
+        assert!(validate_asset_name("simple.test-2").is_ok());
+
+        for name in ["", ".", "..", "a/b", "a\\b", "a*", "a\nb"] {
+            assert!(validate_asset_name(name).is_err(), "{name:?}");
+        }
+    }
+}
@@ -1,6 +1,16 @@
-use crate::{AssetDescriptor, AssetLocator};
-use sha2::{Digest, Sha256};
+use crate::content_hash::{parse_content_hash, ContentHasher};
+use crate::failover::{fetch_with_failover, fetch_with_failover_async, FailedAttempt};
+use crate::{redact_url, AssetDescriptor, AssetLocator, RetryPolicy};
+use async_trait::async_trait;
+use std::io::{Cursor, Read};
+use std::pin::Pin;
+use std::task::{Context, Poll};
 use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+/// Streamed payloads of assets up to this size are read and verified by
+/// `fetch_by_descriptor` before it returns them.
+const VERIFIED_STREAM_MAX_SIZE: usize = 8 * 1024 * 1024;
 
 #[derive(Error, Debug)]
 pub enum AssetStoreError {
@@ -10,14 +20,115 @@ pub enum AssetStoreError {
     UnsupportedScheme(String),
     #[error("The asset's hash doesn't match the expected hash. Expected: {expected} Actual: {actual}")]
     AssetHashMismatch {expected:String, actual:String},
+    #[error("The hash algorithm '{0}' isn't supported.")]
+    UnsupportedHashAlgorithm(String),
     #[error("Failed to retrieve asset. Details: {0}")]
     AssetStoreInternalError(String),
+    #[error("The store is temporarily unavailable. Details: {0}")]
+    AssetStoreUnavailable(String),
+    #[error("The store refused access. Details: {0}")]
+    AssetStoreAccessDenied(String),
     #[error("The store was not configured properly. Details: {0}")]
-    MisconfiguredStore(String)
+    MisconfiguredStore(String),
+    #[error("The store may not serve the file {0}.")]
+    PathNotAllowed(String),
+    #[error("None of the asset's locators could be fetched. Attempts: {}", describe_attempts(.0))]
+    AllLocatorsFailed(Vec<FailedAttempt>)
+}
+
+fn describe_attempts(attempts: &[FailedAttempt]) -> String {
+    attempts
+        .iter()
+        .map(|attempt| attempt.to_string())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+impl AssetStoreError {
+    /// Whether the request may succeed when it's made again later.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, AssetStoreError::AssetStoreUnavailable(_))
+    }
+}
+
+/// Whether a response status means the request may succeed when it's made again later.
+pub(crate) fn is_transient_status(status: reqwest::StatusCode) -> bool {
+    status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+}
+
+impl From<reqwest::Error> for AssetStoreError {
+    fn from(mut e: reqwest::Error) -> Self {
+        if let Some(url) = e.url_mut() {
+            *url = redact_url(url);
+        }
+        if e.is_timeout() || e.is_connect() || e.status().is_some_and(is_transient_status) {
+            AssetStoreError::AssetStoreUnavailable(e.to_string())
+        } else if matches!(
+            e.status(),
+            Some(reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN)
+        ) {
+            AssetStoreError::AssetStoreAccessDenied(e.to_string())
+        } else {
+            AssetStoreError::AssetStoreInternalError(e.to_string())
+        }
+    }
+}
+
+impl From<std::io::Error> for AssetStoreError {
+    fn from(e: std::io::Error) -> Self {
+        // Errors raised by a HashValidatingReader travel through std::io as the inner error.
+        let message = e.to_string();
+        let transient = matches!(
+            e.kind(),
+            std::io::ErrorKind::TimedOut
+                | std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::UnexpectedEof
+        );
+        match e.into_inner().map(|inner| inner.downcast::<AssetStoreError>()) {
+            Some(Ok(store_error)) => *store_error,
+            // Reading a response body fails with the underlying reqwest error.
+            Some(Err(inner)) => match inner.downcast::<reqwest::Error>() {
+                Ok(request_error) => AssetStoreError::from(*request_error),
+                Err(_) if transient => AssetStoreError::AssetStoreUnavailable(message),
+                Err(_) => AssetStoreError::AssetStoreInternalError(message),
+            },
+            None if transient => AssetStoreError::AssetStoreUnavailable(message),
+            None => AssetStoreError::AssetStoreInternalError(message),
+        }
+    }
 }
 
+/// The content of an asset.
+///
+/// `Stream` payloads are read lazily, so they never have to fit in memory. Stores that
+/// produce a stream wrap it in a `HashValidatingReader`, which means the hash is only
+/// checked once the stream has been read to the end.
 pub enum AssetPayload {
     Bytes(Vec<u8>),
+    Stream(Box<dyn Read + Send>),
+}
+
+impl AssetPayload {
+    pub fn into_reader(self) -> Box<dyn Read + Send> {
+        match self {
+            AssetPayload::Bytes(buf) => Box::new(Cursor::new(buf)),
+            AssetPayload::Stream(reader) => reader,
+        }
+    }
+
+    /// Reads the whole payload into memory. Hash validation errors raised while
+    /// reading a stream are returned as `AssetHashMismatch`.
+    pub fn into_bytes(self) -> Result<Vec<u8>, AssetStoreError> {
+        match self {
+            AssetPayload::Bytes(buf) => Ok(buf),
+            AssetPayload::Stream(mut reader) => {
+                let mut buf = vec![];
+                reader.read_to_end(&mut buf)?;
+                Ok(buf)
+            }
+        }
+    }
 }
 
 pub trait AssetStore {
@@ -29,26 +140,415 @@ pub trait AssetStore {
         expected_hash: &str,
     ) -> Result<AssetPayload, AssetStoreError>;
 
+    /// How `fetch_by_descriptor` retries locators that fail with a transient error.
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::default()
+    }
+
+    /// Fetches the asset from the first of its supported locators that works, trying them
+    /// by priority. Transient errors are retried according to the retry policy, any other
+    /// error, including a hash mismatch, moves on to the next locator. When every locator
+    /// fails, the error lists each attempt.
+    ///
+    /// Streams of assets up to 8 MiB are read into memory to check their hash before they
+    /// are returned. Larger streams are returned as they are and only checked once they
+    /// have been read, so a mismatch in them doesn't fail over to the next locator. The
+    /// filesystem cache checks every download while saving it, and fails over whatever
+    /// the size.
     fn fetch_by_descriptor(
         &self,
         descriptor: &AssetDescriptor,
     ) -> Result<AssetPayload, AssetStoreError> {
-        for locator in descriptor.locators.iter() {
-            if self.supports_locator(locator) {
-                return self.fetch_by_locator(locator, &descriptor.content_hash);
+        let locators: Vec<&AssetLocator> = descriptor
+            .locators_by_priority()
+            .into_iter()
+            .filter(|locator| self.supports_locator(locator))
+            .collect();
+        fetch_with_failover(locators, &self.retry_policy(), |locator| {
+            let payload = self.fetch_by_locator(locator, &descriptor.content_hash)?;
+            verify_small_stream(payload, descriptor.size)
+        })
+    }
+}
+
+/// The content of an asset fetched through an `AsyncAssetStore`. Stores that produce a
+/// stream wrap it in an `AsyncHashValidatingReader`.
+pub enum AsyncAssetPayload {
+    Bytes(Vec<u8>),
+    Stream(Pin<Box<dyn AsyncRead + Send>>),
+}
+
+impl AsyncAssetPayload {
+    pub fn into_reader(self) -> Pin<Box<dyn AsyncRead + Send>> {
+        match self {
+            AsyncAssetPayload::Bytes(buf) => Box::pin(Cursor::new(buf)),
+            AsyncAssetPayload::Stream(reader) => reader,
+        }
+    }
+
+    /// Reads the whole payload into memory. Hash validation errors raised while
+    /// reading a stream are returned as `AssetHashMismatch`.
+    pub async fn into_bytes(self) -> Result<Vec<u8>, AssetStoreError> {
+        match self {
+            AsyncAssetPayload::Bytes(buf) => Ok(buf),
+            AsyncAssetPayload::Stream(mut reader) => {
+                let mut buf = vec![];
+                reader.read_to_end(&mut buf).await?;
+                Ok(buf)
             }
         }
-        Err(AssetStoreError::NoSupportedLocator)
     }
 }
 
-pub fn validate_hash(content: &Vec<u8>, expected_hash: &str) -> Result<(), AssetStoreError> {
-    let mut hasher = Sha256::new();
+/// The async counterpart of `AssetStore`.
+#[async_trait]
+pub trait AsyncAssetStore: Send + Sync {
+    fn supports_locator(&self, locator: &AssetLocator) -> bool;
+
+    async fn fetch_by_locator(
+        &self,
+        locator: &AssetLocator,
+        expected_hash: &str,
+    ) -> Result<AsyncAssetPayload, AssetStoreError>;
+
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::default()
+    }
+
+    /// See `AssetStore::fetch_by_descriptor`.
+    async fn fetch_by_descriptor(
+        &self,
+        descriptor: &AssetDescriptor,
+    ) -> Result<AsyncAssetPayload, AssetStoreError> {
+        let locators: Vec<&AssetLocator> = descriptor
+            .locators_by_priority()
+            .into_iter()
+            .filter(|locator| self.supports_locator(locator))
+            .collect();
+        fetch_with_failover_async(locators, &self.retry_policy(), |locator| async move {
+            let payload = self
+                .fetch_by_locator(locator, &descriptor.content_hash)
+                .await?;
+            verify_small_stream_async(payload, descriptor.size).await
+        })
+        .await
+    }
+}
+
+/// Reads the stream of a small asset to the end, which checks its hash. Should the stream
+/// turn out larger than the descriptor claimed, what was read so far is put back in front
+/// of the rest, which is checked as it's read.
+fn verify_small_stream(
+    payload: AssetPayload,
+    size: usize,
+) -> Result<AssetPayload, AssetStoreError> {
+    let reader = match payload {
+        AssetPayload::Stream(reader) if size <= VERIFIED_STREAM_MAX_SIZE => reader,
+        payload => return Ok(payload),
+    };
+    let mut head = reader.take(VERIFIED_STREAM_MAX_SIZE as u64 + 1);
+    let mut buf = vec![];
+    head.read_to_end(&mut buf)?;
+    if buf.len() <= VERIFIED_STREAM_MAX_SIZE {
+        return Ok(AssetPayload::Bytes(buf));
+    }
+    Ok(AssetPayload::Stream(Box::new(Read::chain(
+        Cursor::new(buf),
+        head.into_inner(),
+    ))))
+}
+
+/// The async counterpart of `verify_small_stream`.
+async fn verify_small_stream_async(
+    payload: AsyncAssetPayload,
+    size: usize,
+) -> Result<AsyncAssetPayload, AssetStoreError> {
+    let reader = match payload {
+        AsyncAssetPayload::Stream(reader) if size <= VERIFIED_STREAM_MAX_SIZE => reader,
+        payload => return Ok(payload),
+    };
+    let mut head = reader.take(VERIFIED_STREAM_MAX_SIZE as u64 + 1);
+    let mut buf = vec![];
+    head.read_to_end(&mut buf).await?;
+    if buf.len() <= VERIFIED_STREAM_MAX_SIZE {
+        return Ok(AsyncAssetPayload::Bytes(buf));
+    }
+    Ok(AsyncAssetPayload::Stream(Box::pin(AsyncReadExt::chain(
+        Cursor::new(buf),
+        head.into_inner(),
+    ))))
+}
+
+/// Checks the content against an algorithm tagged content hash. Untagged hashes are
+/// treated as SHA-256.
+pub fn validate_hash(content: &[u8], expected_hash: &str) -> Result<(), AssetStoreError> {
+    let (algorithm, _) = parse_content_hash(expected_hash)?;
+    let mut hasher = ContentHasher::new(algorithm);
     hasher.update(content);
-    let hash_hex = hex::encode(hasher.finalize());
-    if expected_hash != hash_hex {
-        Err(AssetStoreError::AssetHashMismatch { expected: expected_hash.to_string(), actual: hash_hex })
-    } else {
-        Ok(())
+    hasher.verify(expected_hash)
+}
+
+/// Hashes the bytes of the inner reader as they are read. When the inner reader is
+/// exhausted the hash is compared to the expected hash, and a mismatch is reported as an
+/// `std::io::Error` of kind `InvalidData` wrapping `AssetStoreError::AssetHashMismatch`.
+pub struct HashValidatingReader<R>
+where
+    R: Read,
+{
+    inner: R,
+    hasher: Result<Option<ContentHasher>, Option<AssetStoreError>>,
+    expected_hash: String,
+}
+
+impl<R> HashValidatingReader<R>
+where
+    R: Read,
+{
+    pub fn new(inner: R, expected_hash: &str) -> Self {
+        HashValidatingReader {
+            inner,
+            hasher: parse_content_hash(expected_hash)
+                .map(|(algorithm, _)| Some(ContentHasher::new(algorithm)))
+                .map_err(Some),
+            expected_hash: expected_hash.to_owned(),
+        }
+    }
+}
+
+impl<R> Read for HashValidatingReader<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let to_io_error = |e| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+        let hasher = match self.hasher.as_mut() {
+            Ok(hasher) => hasher,
+            // The expected hash can't be checked, so don't hand out any content.
+            Err(e) => {
+                return Err(match e.take() {
+                    Some(e) => to_io_error(e),
+                    None => std::io::Error::from(std::io::ErrorKind::InvalidData),
+                })
+            }
+        };
+        let read = self.inner.read(buf)?;
+        if read > 0 {
+            if let Some(hasher) = hasher.as_mut() {
+                hasher.update(&buf[..read]);
+            }
+        } else if !buf.is_empty() {
+            if let Some(hasher) = hasher.take() {
+                hasher.verify(&self.expected_hash).map_err(to_io_error)?;
+            }
+        }
+        Ok(read)
+    }
+}
+
+/// The async counterpart of `HashValidatingReader`.
+pub struct AsyncHashValidatingReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    inner: R,
+    hasher: Result<Option<ContentHasher>, Option<AssetStoreError>>,
+    expected_hash: String,
+}
+
+impl<R> AsyncHashValidatingReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    pub fn new(inner: R, expected_hash: &str) -> Self {
+        AsyncHashValidatingReader {
+            inner,
+            hasher: parse_content_hash(expected_hash)
+                .map(|(algorithm, _)| Some(ContentHasher::new(algorithm)))
+                .map_err(Some),
+            expected_hash: expected_hash.to_owned(),
+        }
+    }
+}
+
+impl<R> AsyncRead for AsyncHashValidatingReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let to_io_error = |e| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+        let this = self.get_mut();
+        let hasher = match this.hasher.as_mut() {
+            Ok(hasher) => hasher,
+            // The expected hash can't be checked, so don't hand out any content.
+            Err(e) => {
+                return Poll::Ready(Err(match e.take() {
+                    Some(e) => to_io_error(e),
+                    None => std::io::Error::from(std::io::ErrorKind::InvalidData),
+                }))
+            }
+        };
+        let filled_before = buf.filled().len();
+        let had_capacity = buf.remaining() > 0;
+        match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Ready(Ok(())) => {
+                let read = &buf.filled()[filled_before..];
+                if !read.is_empty() {
+                    if let Some(hasher) = hasher.as_mut() {
+                        hasher.update(read);
+                    }
+                } else if had_capacity {
+                    if let Some(hasher) = hasher.take() {
+                        hasher.verify(&this.expected_hash).map_err(to_io_error)?;
+                    }
+                }
+                Poll::Ready(Ok(()))
+            }
+            other => other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AssetPayload, AssetStoreError, AsyncAssetPayload, AsyncHashValidatingReader,
+        HashValidatingReader, VERIFIED_STREAM_MAX_SIZE,
+    };
+    use crate::{AssetDescriptor, AssetLocator, AssetStore, AsyncAssetStore, SemVer};
+    use async_trait::async_trait;
+    use std::io::Read;
+    use std::str::FromStr;
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    /// Streams "hello", except from the corrupt host.
+    struct StreamingStore {}
+
+    impl StreamingStore {
+        fn content(locator: &AssetLocator) -> &'static [u8] {
+            match locator.url.host_str() {
+                Some("corrupt.example.com") => b"hullo",
+                _ => b"hello",
+            }
+        }
+    }
+
+    impl AssetStore for StreamingStore {
+        fn supports_locator(&self, _locator: &AssetLocator) -> bool {
+            true
+        }
+
+        fn fetch_by_locator(
+            &self,
+            locator: &AssetLocator,
+            expected_hash: &str,
+        ) -> Result<AssetPayload, AssetStoreError> {
+            Ok(AssetPayload::Stream(Box::new(HashValidatingReader::new(
+                Self::content(locator),
+                expected_hash,
+            ))))
+        }
+    }
+
+    #[async_trait]
+    impl AsyncAssetStore for StreamingStore {
+        fn supports_locator(&self, _locator: &AssetLocator) -> bool {
+            true
+        }
+
+        async fn fetch_by_locator(
+            &self,
+            locator: &AssetLocator,
+            expected_hash: &str,
+        ) -> Result<AsyncAssetPayload, AssetStoreError> {
+            Ok(AsyncAssetPayload::Stream(Box::pin(
+                AsyncHashValidatingReader::new(Self::content(locator), expected_hash),
+            )))
+        }
+    }
+
+    fn descriptor(size: usize, hosts: &[&str]) -> AssetDescriptor {
+        AssetDescriptor::new(
+            "hello",
+            SemVer::from_str("1.0.0").unwrap(),
+            HELLO_SHA256,
+            size,
+            hosts
+                .iter()
+                .map(|host| {
+                    AssetLocator::new(
+                        reqwest::Url::parse(&format!("https://{host}/hello")).unwrap(),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn hash_validating_reader_accepts_matching_content() {
+        let mut reader = HashValidatingReader::new("hello".as_bytes(), HELLO_SHA256);
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"hello");
+    }
+
+    #[test]
+    fn hash_validating_reader_rejects_mismatched_content() {
+        let payload = AssetPayload::Stream(Box::new(HashValidatingReader::new(
+            "hullo".as_bytes(),
+            HELLO_SHA256,
+        )));
+        match payload.into_bytes() {
+            Err(AssetStoreError::AssetHashMismatch { expected, actual: _ }) => {
+                assert_eq!(expected, HELLO_SHA256)
+            }
+            Err(e) => panic!("Unexpected error: {:?}", e),
+            Ok(_) => panic!("Expected a hash mismatch"),
+        }
+    }
+
+    #[tokio::test]
+    async fn async_hash_validating_reader_checks_content() {
+        let payload = AsyncAssetPayload::Stream(Box::pin(AsyncHashValidatingReader::new(
+            "hello".as_bytes(),
+            HELLO_SHA256,
+        )));
+        assert_eq!(payload.into_bytes().await.unwrap(), b"hello");
+
+        let payload = AsyncAssetPayload::Stream(Box::pin(AsyncHashValidatingReader::new(
+            "hullo".as_bytes(),
+            HELLO_SHA256,
+        )));
+        assert!(matches!(
+            payload.into_bytes().await,
+            Err(AssetStoreError::AssetHashMismatch { .. })
+        ));
+    }
+    #[test]
+    fn fails_over_on_mismatched_small_streams() {
+        let mirrored = descriptor(5, &["corrupt.example.com", "mirror.example.com"]);
+        let payload = AssetStore::fetch_by_descriptor(&StreamingStore {}, &mirrored).unwrap();
+        assert_eq!(payload.into_bytes().unwrap(), b"hello");
+
+        // Large streams are returned unread, so the mismatch only shows once they are read.
+        let large = descriptor(VERIFIED_STREAM_MAX_SIZE + 1, &["corrupt.example.com"]);
+        let payload = AssetStore::fetch_by_descriptor(&StreamingStore {}, &large).unwrap();
+        assert!(matches!(
+            payload.into_bytes(),
+            Err(AssetStoreError::AssetHashMismatch { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn fails_over_on_mismatched_small_async_streams() {
+        let mirrored = descriptor(5, &["corrupt.example.com", "mirror.example.com"]);
+        let payload = AsyncAssetStore::fetch_by_descriptor(&StreamingStore {}, &mirrored)
+            .await
+            .unwrap();
+        assert_eq!(payload.into_bytes().await.unwrap(), b"hello");
     }
 }
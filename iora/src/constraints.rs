@@ -1,3 +1,4 @@
+use std::cmp::Ordering;
 use std::str::FromStr;
 
 use thiserror::Error;
@@ -31,6 +32,22 @@ impl NameConstraint {
             NameConstraint::Contains(substring) => name.contains(substring),
         }
     }
+
+    /// Whether every name this constraint matches is also matched by `broader`.
+    pub fn is_covered_by(&self, broader: &NameConstraint) -> bool {
+        match (self, broader) {
+            (NameConstraint::ExactMatch(name), _) => broader.matches(name),
+            (NameConstraint::StartsWith(prefix), NameConstraint::StartsWith(broader_prefix)) => {
+                prefix.starts_with(broader_prefix.as_str())
+            }
+            (_, NameConstraint::StartsWith(broader_prefix)) => broader_prefix.is_empty(),
+            (
+                NameConstraint::StartsWith(term) | NameConstraint::Contains(term),
+                NameConstraint::Contains(substring),
+            ) => term.contains(substring.as_str()),
+            (_, NameConstraint::ExactMatch(_)) => false,
+        }
+    }
 }
 
 impl FromStr for NameConstraint {
@@ -50,16 +67,25 @@ impl FromStr for NameConstraint {
     }
 }
 
-impl ToString for NameConstraint {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for NameConstraint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::ExactMatch(term) => term.to_owned(),
-            Self::Contains(term) => "*".to_owned() + term + "*",
-            Self::StartsWith(term) => term.to_owned() + "*",
+            Self::ExactMatch(term) => write!(f, "{term}"),
+            Self::Contains(term) => write!(f, "*{term}*"),
+            Self::StartsWith(term) => write!(f, "{term}*"),
         }
     }
 }
 
+/// A version requirement.
+///
+/// The string form follows the Cargo/npm requirement syntax: comparators such as `1.2.3`,
+/// `=1.2`, `>=1.0`, `<2`, `^1.2`, `~1.2.3`, `1.x` and `*` are combined with whitespace (or
+/// commas) to require all of them, and with `||` to accept any of the alternatives. The
+/// legacy `min,max` range form is still accepted, and `Between` is written that way so
+/// that services which only know the legacy syntax keep understanding it. Parsing expands
+/// operators into the primitive variants below, so `to_string` produces text that parses
+/// back into the same tree.
 #[derive(Clone, Debug, Hash, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
 pub enum VersionConstraint {
     ExactMatch(SemVer),
@@ -67,61 +93,331 @@ pub enum VersionConstraint {
     MatchMajorAndMinorVersionOnly((u32, u32)),
     MinVersion(SemVer),
     Between((SemVer, SemVer)),
+    Any,
+    GreaterThan(SemVer),
+    LessThan(SemVer),
+    MaxVersion(SemVer),
+    AllOf(Vec<VersionConstraint>),
+    AnyOf(Vec<VersionConstraint>),
+}
+
+enum PartialVersion {
+    Major(u32),
+    MajorAndMinor(u32, u32),
+    Full(SemVer),
+}
+
+impl PartialVersion {
+    fn parse(s: &str) -> Option<Self> {
+        if let Ok(full_sem_ver) = SemVer::from_str(s) {
+            return Some(PartialVersion::Full(full_sem_ver));
+        }
+        match regexes::parse_partial_version_constraint(s) {
+            (Some(major), Some(minor)) => Some(PartialVersion::MajorAndMinor(major, minor)),
+            (Some(major), None) => Some(PartialVersion::Major(major)),
+            _ => None,
+        }
+    }
+
+    fn lower_bound(&self) -> SemVer {
+        match self {
+            PartialVersion::Major(major) => SemVer::new(*major, 0, 0, None, None),
+            PartialVersion::MajorAndMinor(major, minor) => {
+                SemVer::new(*major, *minor, 0, None, None)
+            }
+            PartialVersion::Full(version) => version.clone(),
+        }
+    }
+
+    /// The first release above every version this partial version describes.
+    fn next_release(&self) -> Option<SemVer> {
+        match self {
+            PartialVersion::Major(major) => {
+                Some(SemVer::new(major.checked_add(1)?, 0, 0, None, None))
+            }
+            PartialVersion::MajorAndMinor(major, minor) => {
+                Some(SemVer::new(*major, minor.checked_add(1)?, 0, None, None))
+            }
+            PartialVersion::Full(_) => None,
+        }
+    }
+
+    /// The first release that is no longer compatible according to the caret operator.
+    fn next_caret_release(&self) -> Option<SemVer> {
+        match self {
+            PartialVersion::Major(_) => self.next_release(),
+            PartialVersion::MajorAndMinor(0, _) => self.next_release(),
+            PartialVersion::MajorAndMinor(major, _) => PartialVersion::Major(*major).next_release(),
+            PartialVersion::Full(v) if v.major > 0 => PartialVersion::Major(v.major).next_release(),
+            PartialVersion::Full(v) if v.minor > 0 => {
+                PartialVersion::MajorAndMinor(0, v.minor).next_release()
+            }
+            PartialVersion::Full(v) => Some(SemVer::new(0, 0, v.patch.checked_add(1)?, None, None)),
+        }
+    }
+
+    /// The first release that is no longer compatible according to the tilde operator.
+    fn next_tilde_release(&self) -> Option<SemVer> {
+        match self {
+            PartialVersion::Full(v) => {
+                PartialVersion::MajorAndMinor(v.major, v.minor).next_release()
+            }
+            _ => self.next_release(),
+        }
+    }
+}
+
+impl VersionConstraint {
+    /// Expands a single comparator, such as `^1.2` or `<=3`, into primitive constraints.
+    fn parse_comparator(s: &str) -> Option<Vec<VersionConstraint>> {
+        if matches!(s, "*" | "x" | "X") {
+            return Some(vec![VersionConstraint::Any]);
+        }
+        let (operator, version) = match regexes::parse_version_comparator(s) {
+            (operator, Some(version)) => (operator, PartialVersion::parse(&version)?),
+            _ => return None,
+        };
+        let constraints = match (operator.as_deref(), version) {
+            (None | Some("="), PartialVersion::Full(v)) => vec![VersionConstraint::ExactMatch(v)],
+            (None | Some("="), PartialVersion::MajorAndMinor(major, minor)) => {
+                vec![VersionConstraint::MatchMajorAndMinorVersionOnly((
+                    major, minor,
+                ))]
+            }
+            (None | Some("="), PartialVersion::Major(major)) => {
+                vec![VersionConstraint::MatchMajorVersionOnly(major)]
+            }
+            (Some(">="), v) => vec![VersionConstraint::MinVersion(v.lower_bound())],
+            (Some(">"), PartialVersion::Full(v)) => vec![VersionConstraint::GreaterThan(v)],
+            (Some(">"), v) => vec![VersionConstraint::MinVersion(v.next_release()?)],
+            (Some("<"), v) => vec![VersionConstraint::LessThan(v.lower_bound())],
+            (Some("<="), PartialVersion::Full(v)) => vec![VersionConstraint::MaxVersion(v)],
+            (Some("<="), v) => vec![VersionConstraint::LessThan(v.next_release()?)],
+            (Some("^"), v) => vec![
+                VersionConstraint::MinVersion(v.lower_bound()),
+                VersionConstraint::LessThan(v.next_caret_release()?),
+            ],
+            (Some("~"), v) => vec![
+                VersionConstraint::MinVersion(v.lower_bound()),
+                VersionConstraint::LessThan(v.next_tilde_release()?),
+            ],
+            _ => return None,
+        };
+        Some(constraints)
+    }
+
+    /// Parses a set of comparators that must all match.
+    fn parse_comparator_set(s: &str) -> Option<VersionConstraint> {
+        // The legacy range syntax: 'min,max' or 'min,'.
+        if let Some((min, max)) = s.split_once(',') {
+            if let Ok(min_ver) = SemVer::from_str(min.trim()) {
+                if max.trim().is_empty() {
+                    return Some(VersionConstraint::MinVersion(min_ver));
+                }
+                if let Ok(max_ver) = SemVer::from_str(max.trim()) {
+                    return Some(VersionConstraint::Between((min_ver, max_ver)));
+                }
+            }
+        }
+
+        let mut comparators = vec![];
+        let mut pending_operator = String::new();
+        for token in s.split(|c: char| c.is_whitespace() || c == ',') {
+            if token.is_empty() {
+                continue;
+            }
+            // Allow whitespace between an operator and its version, e.g. '>= 1.2'.
+            if token.chars().all(|c| "^~<>=".contains(c)) {
+                if !pending_operator.is_empty() {
+                    return None;
+                }
+                pending_operator = token.to_owned();
+                continue;
+            }
+            let comparator = pending_operator.clone() + token;
+            pending_operator.clear();
+            comparators.extend(Self::parse_comparator(&comparator)?);
+        }
+        if !pending_operator.is_empty() {
+            return None;
+        }
+
+        if comparators.len() > 1 {
+            comparators.retain(|c| *c != VersionConstraint::Any);
+        }
+        match comparators.as_slice() {
+            [] => None,
+            [single] => Some(single.clone()),
+            [VersionConstraint::MinVersion(min), VersionConstraint::LessThan(max)] => {
+                Some(VersionConstraint::Between((min.clone(), max.clone())))
+            }
+            _ => Some(VersionConstraint::AllOf(comparators)),
+        }
+    }
 }
 
 impl FromStr for VersionConstraint {
     type Err = ConstraintParsingError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.is_empty() {
+        if s.trim().is_empty() {
             return Err(ConstraintParsingError::EmptyVersionConstraint);
         }
-        if s.contains(',') {
-            let parts: Vec<_> = s.split(',').collect();
-            if parts.len() > 1 {
-                if let (Ok(min_ver), Ok(max_ver)) =
-                    (SemVer::from_str(parts[0]), SemVer::from_str(parts[1]))
-                {
-                    return Ok(VersionConstraint::Between((min_ver, max_ver)));
+        let mut alternatives = vec![];
+        for alternative in s.split("||") {
+            match Self::parse_comparator_set(alternative) {
+                Some(constraint) => alternatives.push(constraint),
+                None => {
+                    return Err(
+                        ConstraintParsingError::UnrecognizedVersionConstraintStructure(
+                            s.to_owned(),
+                        ),
+                    )
                 }
-            } else if let Ok(min_ver) = SemVer::from_str(parts[0]) {
-                return Ok(VersionConstraint::MinVersion(min_ver));
             }
         }
-        if let Ok(full_sem_ver) = SemVer::from_str(s) {
-            return Ok(VersionConstraint::ExactMatch(full_sem_ver));
-        }
-        match regexes::parse_partial_version_constraint(s) {
-            (Some(major), Some(minor)) => Ok(VersionConstraint::MatchMajorAndMinorVersionOnly((
-                major, minor,
-            ))),
-            (Some(major), None) => Ok(VersionConstraint::MatchMajorVersionOnly(major)),
-            _ => Err(ConstraintParsingError::UnrecognizedVersionConstraintStructure(s.to_owned())),
+        if alternatives.len() == 1 {
+            Ok(alternatives.remove(0))
+        } else {
+            Ok(VersionConstraint::AnyOf(alternatives))
         }
     }
 }
 
-impl ToString for VersionConstraint {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for VersionConstraint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Between((min, max)) => min.to_string() + "," + &max.to_string(),
-            Self::ExactMatch(target) => target.to_string(),
-            Self::MinVersion(target) => target.to_string(),
-            Self::MatchMajorAndMinorVersionOnly((major, minor)) => format!("{major}.{minor}"),
-            Self::MatchMajorVersionOnly(major) => format!("{major}"),
+            Self::Between((min, max)) => write!(f, "{min},{max}"),
+            Self::ExactMatch(target) => write!(f, "{target}"),
+            Self::MinVersion(target) => write!(f, ">={target}"),
+            Self::MatchMajorAndMinorVersionOnly((major, minor)) => write!(f, "{major}.{minor}"),
+            Self::MatchMajorVersionOnly(major) => write!(f, "{major}"),
+            Self::Any => write!(f, "*"),
+            Self::GreaterThan(target) => write!(f, ">{target}"),
+            Self::LessThan(target) => write!(f, "<{target}"),
+            Self::MaxVersion(target) => write!(f, "<={target}"),
+            Self::AllOf(constraints) => write!(f, "{}", join_constraints(constraints, " ")),
+            Self::AnyOf(constraints) => write!(f, "{}", join_constraints(constraints, " || ")),
         }
     }
 }
 
+fn join_constraints(constraints: &[VersionConstraint], separator: &str) -> String {
+    constraints
+        .iter()
+        .map(|c| match c {
+            // The legacy range form can't be combined with other comparators.
+            VersionConstraint::Between((min, max)) if separator == " " => {
+                format!(">={min} <{max}")
+            }
+            c => c.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
 impl VersionConstraint {
+    /// Whether the version satisfies the constraint. As with Cargo and npm, a prerelease
+    /// only satisfies a range when one of the range's comparators names a prerelease of the
+    /// same major, minor and patch version, so `^1.2` doesn't match `2.0.0-alpha`, while
+    /// `>=1.2.3-alpha <2` matches `1.2.3-beta` but not `1.5.0-alpha`.
     pub fn matches(&self, version: &SemVer) -> bool {
+        match self {
+            VersionConstraint::AllOf(constraints) => {
+                constraints
+                    .iter()
+                    .all(|c| c.matches_ignoring_prereleases(version))
+                    && Self::admits_prerelease(constraints, version)
+            }
+            VersionConstraint::AnyOf(constraints) => constraints.iter().any(|c| c.matches(version)),
+            _ => {
+                self.matches_ignoring_prereleases(version)
+                    && Self::admits_prerelease(std::slice::from_ref(self), version)
+            }
+        }
+    }
+
+    fn is_range(&self) -> bool {
+        matches!(
+            self,
+            VersionConstraint::MinVersion(_)
+                | VersionConstraint::Between(_)
+                | VersionConstraint::GreaterThan(_)
+                | VersionConstraint::LessThan(_)
+                | VersionConstraint::MaxVersion(_)
+        )
+    }
+
+    fn bounds(&self) -> Vec<&SemVer> {
+        match self {
+            VersionConstraint::ExactMatch(v)
+            | VersionConstraint::MinVersion(v)
+            | VersionConstraint::GreaterThan(v)
+            | VersionConstraint::LessThan(v)
+            | VersionConstraint::MaxVersion(v) => vec![v],
+            VersionConstraint::Between((min, max)) => vec![min, max],
+            _ => vec![],
+        }
+    }
+
+    /// Whether a set of comparators that must all match lets the version through, if it's
+    /// a prerelease.
+    fn admits_prerelease(constraints: &[VersionConstraint], version: &SemVer) -> bool {
+        if version.prerelease.is_none() || !constraints.iter().any(|c| c.is_range()) {
+            return true;
+        }
+        constraints.iter().flat_map(|c| c.bounds()).any(|bound| {
+            bound.prerelease.is_some()
+                && (bound.major, bound.minor, bound.patch)
+                    == (version.major, version.minor, version.patch)
+        })
+    }
+
+    fn matches_ignoring_prereleases(&self, version: &SemVer) -> bool {
         match self {
             VersionConstraint::ExactMatch(target) => version == target,
-            VersionConstraint::MinVersion(target) => version >= target,
+            VersionConstraint::MinVersion(target) => {
+                version.cmp_precedence(target) != Ordering::Less
+            }
             VersionConstraint::MatchMajorVersionOnly(target) => version.major == *target,
             VersionConstraint::MatchMajorAndMinorVersionOnly(target) => {
                 version.major == target.0 && version.minor == target.1
             }
-            VersionConstraint::Between((min, max)) => version >= min && version < max,
+            VersionConstraint::Between((min, max)) => {
+                version.cmp_precedence(min) != Ordering::Less
+                    && version.cmp_precedence(max) == Ordering::Less
+            }
+            VersionConstraint::Any => true,
+            VersionConstraint::GreaterThan(target) => {
+                version.cmp_precedence(target) == Ordering::Greater
+            }
+            VersionConstraint::LessThan(target) => version.cmp_precedence(target) == Ordering::Less,
+            VersionConstraint::MaxVersion(target) => {
+                version.cmp_precedence(target) != Ordering::Greater
+            }
+            VersionConstraint::AllOf(_) | VersionConstraint::AnyOf(_) => self.matches(version),
+        }
+    }
+
+    /// Whether every version this constraint matches is also matched by `broader`. Only
+    /// returns true when that can be shown without enumerating versions, so some covered
+    /// constraints are reported as not covered.
+    pub fn is_covered_by(&self, broader: &VersionConstraint) -> bool {
+        if self == broader {
+            return true;
+        }
+        match (self, broader) {
+            (_, VersionConstraint::Any) => true,
+            (VersionConstraint::ExactMatch(version), _) => broader.matches(version),
+            (VersionConstraint::AnyOf(alternatives), _) => {
+                alternatives.iter().all(|c| c.is_covered_by(broader))
+            }
+            (_, VersionConstraint::AnyOf(alternatives)) => {
+                alternatives.iter().any(|c| self.is_covered_by(c))
+            }
+            (VersionConstraint::MatchMajorAndMinorVersionOnly((major, _)), _) => {
+                *broader == VersionConstraint::MatchMajorVersionOnly(*major)
+            }
+            _ => false,
         }
     }
 }
@@ -160,6 +456,58 @@ impl AssetQuery {
     }
 }
 
+impl AssetQuery {
+    /// Whether every asset this query matches is also matched by `broader`, so that the
+    /// results of this query can be taken from the results of `broader`.
+    pub fn is_covered_by(&self, broader: &AssetQuery) -> bool {
+        let versions_covered = match (&self.version_constraint, &broader.version_constraint) {
+            (_, None) => true,
+            (Some(version), Some(broader_version)) => version.is_covered_by(broader_version),
+            (None, Some(broader_version)) => *broader_version == VersionConstraint::Any,
+        };
+        versions_covered && self.name_constraint.is_covered_by(&broader.name_constraint)
+    }
+
+    /// The path prefix that narrows a store laid out as `name/version/...` down to the
+    /// entries that could match this query, if there is one.
+    pub(crate) fn layout_prefix(&self) -> Option<String> {
+        match (&self.name_constraint, &self.version_constraint) {
+            (NameConstraint::ExactMatch(name), Some(VersionConstraint::ExactMatch(version))) => {
+                Some(format!("{name}/{version}/"))
+            }
+            (
+                NameConstraint::ExactMatch(name),
+                Some(VersionConstraint::MatchMajorVersionOnly(major)),
+            ) => Some(format!("{name}/{major}.")),
+            (
+                NameConstraint::ExactMatch(name),
+                Some(VersionConstraint::MatchMajorAndMinorVersionOnly((major, minor))),
+            ) => Some(format!("{name}/{major}.{minor}.")),
+            (NameConstraint::ExactMatch(name), _) => Some(format!("{name}/")),
+            (NameConstraint::StartsWith(prefix), _) => Some(prefix.to_owned()),
+            (NameConstraint::Contains(_), _) => None,
+        }
+    }
+
+    /// Whether a path in a store laid out as `name/version/...` could hold an asset that
+    /// matches this query. Paths that don't follow the layout are kept.
+    pub(crate) fn may_match_layout_path(&self, path: &str) -> bool {
+        let mut segments = path.splitn(3, '/');
+        let (Some(name), Some(version), Some(_)) =
+            (segments.next(), segments.next(), segments.next())
+        else {
+            return true;
+        };
+        if !self.name_constraint.matches(name) {
+            return false;
+        }
+        match (&self.version_constraint, SemVer::from_str(version)) {
+            (Some(constraint), Ok(version)) => constraint.matches(&version),
+            _ => true,
+        }
+    }
+}
+
 impl From<NameConstraint> for AssetQuery {
     fn from(nc: NameConstraint) -> Self {
         AssetQuery {
@@ -180,7 +528,7 @@ impl From<(NameConstraint, Option<VersionConstraint>)> for AssetQuery {
 
 #[cfg(test)]
 mod tests {
-    use crate::{ConstraintParsingError, NameConstraint, SemVer, VersionConstraint};
+    use crate::{AssetQuery, ConstraintParsingError, NameConstraint, SemVer, VersionConstraint};
     use std::str::FromStr;
 
     #[test]
@@ -209,6 +557,45 @@ mod tests {
         assert!(!NameConstraint::Contains("assert".to_string()).matches("asset.name"));
     }
 
+    #[test]
+    fn query_coverage() {
+        let query = |name: &str, version: Option<&str>| {
+            AssetQuery::new_from_strings(name, &version.map(|v| v.to_owned())).unwrap()
+        };
+        assert!(query("foo", None).is_covered_by(&query("foo*", None)));
+        assert!(query("foo.bar*", None).is_covered_by(&query("foo*", None)));
+        assert!(query("foo*", None).is_covered_by(&query("*oo*", None)));
+        assert!(query("*foo.bar*", None).is_covered_by(&query("*bar*", None)));
+        assert!(query("foo", Some("1.2")).is_covered_by(&query("foo*", None)));
+        assert!(query("foo", Some("1.2")).is_covered_by(&query("foo", Some("1"))));
+        assert!(query("foo", Some("1.2.3")).is_covered_by(&query("foo", Some("^1.2"))));
+        assert!(query("foo", Some("1.x || 3.x")).is_covered_by(&query("foo", Some("1 || 3 || 4"))));
+
+        assert!(!query("foo*", None).is_covered_by(&query("foo", None)));
+        assert!(!query("*foo*", None).is_covered_by(&query("foo*", None)));
+        assert!(!query("bar", None).is_covered_by(&query("foo*", None)));
+        assert!(!query("foo", None).is_covered_by(&query("foo", Some("1"))));
+        assert!(!query("foo", Some("1")).is_covered_by(&query("foo", Some("1.2"))));
+        assert!(!query("foo", Some("2.0.0")).is_covered_by(&query("foo", Some("^1.2"))));
+    }
+
+    #[test]
+    fn layout_path_matching() {
+        let query = |name: &str, version: Option<&str>| {
+            AssetQuery::new_from_strings(name, &version.map(|v| v.to_owned())).unwrap()
+        };
+        assert!(query("foo", None).may_match_layout_path("foo/1.0.0/asset"));
+        assert!(query("*oo*", Some("^1.2")).may_match_layout_path("foo/1.3.0/asset"));
+        assert!(!query("foo", None).may_match_layout_path("bar/1.0.0/asset"));
+        assert!(!query("foo", Some("^1.2")).may_match_layout_path("foo/2.0.0/asset"));
+        assert!(!query("foo", Some("^1.2")).may_match_layout_path("foo/1.2.0-beta/asset"));
+
+        // Paths outside the layout, or with versions that don't parse, might still match.
+        assert!(query("foo", Some("1")).may_match_layout_path("foo/latest/asset"));
+        assert!(query("foo", None).may_match_layout_path("foo.tar.gz"));
+        assert!(query("foo", None).may_match_layout_path("bar/asset"));
+    }
+
     #[test]
     fn version_exact_match() {
         assert!(
@@ -236,9 +623,13 @@ mod tests {
                 .matches(&SemVer::from_str("34.5.7").unwrap())
         );
         assert!(
-            VersionConstraint::MinVersion(SemVer::from_str("34.5.6").unwrap())
+            !VersionConstraint::MinVersion(SemVer::from_str("34.5.6").unwrap())
                 .matches(&SemVer::from_str("34.5.6-prerelease").unwrap())
         );
+        assert!(
+            VersionConstraint::MinVersion(SemVer::from_str("34.5.6").unwrap())
+                .matches(&SemVer::from_str("34.5.6+build").unwrap())
+        );
         assert!(
             !VersionConstraint::MinVersion(SemVer::from_str("34.5.7").unwrap())
                 .matches(&SemVer::from_str("34.5.6").unwrap())
@@ -274,6 +665,173 @@ mod tests {
         assert!(!r.matches(&SemVer::from_str("23.56.0").unwrap()));
         assert!(!r.matches(&SemVer::from_str("23.6.1").unwrap()));
         assert!(!r.matches(&SemVer::from_str("24.0.0").unwrap()));
+        assert!(!r.matches(&SemVer::from_str("23.56.1-beta").unwrap()));
+        assert!(!r.matches(&SemVer::from_str("24.0.0+build").unwrap()));
+    }
+
+    fn v(s: &str) -> SemVer {
+        SemVer::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn version_constraint_from_str() {
+        let table = [
+            ("1.2.3", VersionConstraint::ExactMatch(v("1.2.3"))),
+            (
+                "=1.2.3-beta",
+                VersionConstraint::ExactMatch(v("1.2.3-beta")),
+            ),
+            ("1", VersionConstraint::MatchMajorVersionOnly(1)),
+            ("1.x", VersionConstraint::MatchMajorVersionOnly(1)),
+            (
+                "1.2",
+                VersionConstraint::MatchMajorAndMinorVersionOnly((1, 2)),
+            ),
+            (
+                "=1.2.*",
+                VersionConstraint::MatchMajorAndMinorVersionOnly((1, 2)),
+            ),
+            ("*", VersionConstraint::Any),
+            (">=1.2", VersionConstraint::MinVersion(v("1.2.0"))),
+            (">1.2", VersionConstraint::MinVersion(v("1.3.0"))),
+            (">1.2.3", VersionConstraint::GreaterThan(v("1.2.3"))),
+            ("<2", VersionConstraint::LessThan(v("2.0.0"))),
+            ("<=2", VersionConstraint::LessThan(v("3.0.0"))),
+            ("<=2.0.1", VersionConstraint::MaxVersion(v("2.0.1"))),
+            ("^1.2", VersionConstraint::Between((v("1.2.0"), v("2.0.0")))),
+            (
+                "^1.2.3",
+                VersionConstraint::Between((v("1.2.3"), v("2.0.0"))),
+            ),
+            (
+                "^0.2.3",
+                VersionConstraint::Between((v("0.2.3"), v("0.3.0"))),
+            ),
+            (
+                "^0.0.3",
+                VersionConstraint::Between((v("0.0.3"), v("0.0.4"))),
+            ),
+            ("^0", VersionConstraint::Between((v("0.0.0"), v("1.0.0")))),
+            (
+                "~1.2.3",
+                VersionConstraint::Between((v("1.2.3"), v("1.3.0"))),
+            ),
+            ("~1", VersionConstraint::Between((v("1.0.0"), v("2.0.0")))),
+            (
+                ">=1.0 <2.0",
+                VersionConstraint::Between((v("1.0.0"), v("2.0.0"))),
+            ),
+            (
+                ">= 1.0, < 2.0",
+                VersionConstraint::Between((v("1.0.0"), v("2.0.0"))),
+            ),
+            (
+                "1.0.0,2.0.0",
+                VersionConstraint::Between((v("1.0.0"), v("2.0.0"))),
+            ),
+            ("1.0.0,", VersionConstraint::MinVersion(v("1.0.0"))),
+            (
+                "^1.2 <1.5",
+                VersionConstraint::AllOf(vec![
+                    VersionConstraint::MinVersion(v("1.2.0")),
+                    VersionConstraint::LessThan(v("2.0.0")),
+                    VersionConstraint::LessThan(v("1.5.0")),
+                ]),
+            ),
+            (
+                "1.x || >=3.1.4 <4",
+                VersionConstraint::AnyOf(vec![
+                    VersionConstraint::MatchMajorVersionOnly(1),
+                    VersionConstraint::Between((v("3.1.4"), v("4.0.0"))),
+                ]),
+            ),
+        ];
+        assert_eq!(
+            VersionConstraint::Between((v("1.0.0"), v("2.0.0"))).to_string(),
+            "1.0.0,2.0.0"
+        );
+        let nested = VersionConstraint::AllOf(vec![
+            VersionConstraint::Between((v("1.0.0"), v("2.0.0"))),
+            VersionConstraint::LessThan(v("1.5.0")),
+        ]);
+        assert_eq!(nested.to_string(), ">=1.0.0 <2.0.0 <1.5.0");
+        assert_eq!(
+            VersionConstraint::from_str(&nested.to_string()).unwrap(),
+            VersionConstraint::AllOf(vec![
+                VersionConstraint::MinVersion(v("1.0.0")),
+                VersionConstraint::LessThan(v("2.0.0")),
+                VersionConstraint::LessThan(v("1.5.0")),
+            ])
+        );
+
+        for (text, expected) in table {
+            let parsed = VersionConstraint::from_str(text).unwrap();
+            assert_eq!(parsed, expected, "{text}");
+            assert_eq!(
+                VersionConstraint::from_str(&parsed.to_string()).unwrap(),
+                parsed,
+                "{text} round trip"
+            );
+        }
+
+        for text in ["1.2.3.4", ">=", "^", "1 ||", "|| 1", "<>1", "v1", "1.x.3"] {
+            match VersionConstraint::from_str(text) {
+                Err(ConstraintParsingError::UnrecognizedVersionConstraintStructure(_)) => {}
+                r => panic!("Unexpected result for {text}: {:?}", r),
+            }
+        }
+        assert!(matches!(
+            VersionConstraint::from_str(" "),
+            Err(ConstraintParsingError::EmptyVersionConstraint)
+        ));
+    }
+
+    #[test]
+    fn version_requirement_prereleases() {
+        let table = [
+            ("^1.2", "2.0.0-alpha", false),
+            ("^1.2", "1.5.0-alpha", false),
+            ("<2", "2.0.0-alpha", false),
+            ("<2", "1.0.0-alpha", false),
+            ("<=2", "3.0.0-rc.1", false),
+            (">=1.0", "1.5.0-alpha", false),
+            (">=1.2.3-alpha <2", "1.2.3-beta", true),
+            (">=1.2.3-alpha <2", "1.2.3", true),
+            (">=1.2.3-alpha <2", "1.5.0-alpha", false),
+            ("^1.2.3-beta", "1.2.3-rc.1", true),
+            ("^1.2.3-beta", "1.2.4-rc.1", false),
+            ("<2.0.0-rc.1", "2.0.0-alpha", true),
+            ("^1.2.3-beta || ^3", "3.0.0-rc.1", false),
+            ("=1.2.3-beta", "1.2.3-beta", true),
+        ];
+        for (text, version, expected) in table {
+            let constraint = VersionConstraint::from_str(text).unwrap();
+            assert_eq!(
+                constraint.matches(&v(version)),
+                expected,
+                "{text} {version}"
+            );
+        }
+    }
+
+    #[test]
+    fn version_composite() {
+        let c = VersionConstraint::from_str("^1.2 <1.5 || 3.x || *").unwrap();
+        assert!(c.matches(&v("7.0.0")));
+
+        let c = VersionConstraint::from_str("^1.2 <1.5 || 3.x").unwrap();
+        assert!(c.matches(&v("1.2.0")));
+        assert!(c.matches(&v("1.4.9")));
+        assert!(!c.matches(&v("1.5.0")));
+        assert!(!c.matches(&v("1.1.9")));
+        assert!(c.matches(&v("3.9.0")));
+        assert!(!c.matches(&v("4.0.0")));
+
+        let c = VersionConstraint::from_str(">1.2.3 <=1.2.5").unwrap();
+        assert!(!c.matches(&v("1.2.3")));
+        assert!(c.matches(&v("1.2.4")));
+        assert!(c.matches(&v("1.2.5")));
+        assert!(!c.matches(&v("1.2.6")));
     }
 
     #[test]
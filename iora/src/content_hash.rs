@@ -0,0 +1,174 @@
+use crate::AssetStoreError;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+
+/// The digest algorithms a descriptor's content hash can be tagged with.
+///
+/// Content hashes are written as `<algorithm>:<hex digest>`, e.g. `sha256:2cf2…`. Hashes
+/// without a tag predate the tagged format and are read as SHA-256.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    pub fn tag(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha512 => "sha512",
+        }
+    }
+
+    fn from_tag(tag: &str) -> Option<Self> {
+        match tag.to_ascii_lowercase().as_str() {
+            "sha1" => Some(HashAlgorithm::Sha1),
+            "sha256" => Some(HashAlgorithm::Sha256),
+            "sha512" => Some(HashAlgorithm::Sha512),
+            _ => None,
+        }
+    }
+}
+
+/// Splits a content hash into its algorithm and hex digest.
+pub fn parse_content_hash(content_hash: &str) -> Result<(HashAlgorithm, &str), AssetStoreError> {
+    match content_hash.split_once(':') {
+        Some((tag, digest)) => match HashAlgorithm::from_tag(tag) {
+            Some(algorithm) => Ok((algorithm, digest)),
+            None => Err(AssetStoreError::UnsupportedHashAlgorithm(tag.to_owned())),
+        },
+        None => Ok((HashAlgorithm::Sha256, content_hash)),
+    }
+}
+
+/// Formats a hex digest as an algorithm tagged content hash.
+pub fn format_content_hash(algorithm: HashAlgorithm, digest: &str) -> String {
+    format!("{}:{}", algorithm.tag(), digest)
+}
+
+/// Computes a content hash incrementally.
+#[derive(Clone)]
+pub enum ContentHasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+impl ContentHasher {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Sha1 => ContentHasher::Sha1(Sha1::new()),
+            HashAlgorithm::Sha256 => ContentHasher::Sha256(Sha256::new()),
+            HashAlgorithm::Sha512 => ContentHasher::Sha512(Sha512::new()),
+        }
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        match self {
+            ContentHasher::Sha1(_) => HashAlgorithm::Sha1,
+            ContentHasher::Sha256(_) => HashAlgorithm::Sha256,
+            ContentHasher::Sha512(_) => HashAlgorithm::Sha512,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            ContentHasher::Sha1(hasher) => hasher.update(data),
+            ContentHasher::Sha256(hasher) => hasher.update(data),
+            ContentHasher::Sha512(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Returns the hex digest of everything hashed so far.
+    pub fn finalize_hex(self) -> String {
+        match self {
+            ContentHasher::Sha1(hasher) => hex::encode(hasher.finalize()),
+            ContentHasher::Sha256(hasher) => hex::encode(hasher.finalize()),
+            ContentHasher::Sha512(hasher) => hex::encode(hasher.finalize()),
+        }
+    }
+
+    /// Returns the algorithm tagged content hash of everything hashed so far.
+    pub fn finalize(self) -> String {
+        let algorithm = self.algorithm();
+        format_content_hash(algorithm, &self.finalize_hex())
+    }
+
+    /// Compares the digest with the expected content hash, which must use the same
+    /// algorithm this hasher was created with.
+    pub(crate) fn verify(self, expected_hash: &str) -> Result<(), AssetStoreError> {
+        let tagged = expected_hash.contains(':');
+        let (_, expected_digest) = parse_content_hash(expected_hash)?;
+        let algorithm = self.algorithm();
+        let actual_digest = self.finalize_hex();
+        if expected_digest.eq_ignore_ascii_case(&actual_digest) {
+            Ok(())
+        } else {
+            Err(AssetStoreError::AssetHashMismatch {
+                expected: expected_hash.to_owned(),
+                actual: if tagged {
+                    format_content_hash(algorithm, &actual_digest)
+                } else {
+                    actual_digest
+                },
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_content_hash, ContentHasher, HashAlgorithm};
+    use crate::{validate_hash, AssetStoreError};
+
+    const HELLO_SHA1: &str = "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d";
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+    const HELLO_SHA512: &str = "9b71d224bd62f3785d96d46ad3ea3d73319bfbc2890caadae2dff72519673ca72323c3d99ba5c11d7c7acc6e14b8c5da0c4663475c2e5c3adef46f73bcdec043";
+
+    #[test]
+    fn parse_tagged_and_legacy_hashes() {
+        assert_eq!(
+            parse_content_hash("sha1:abc").unwrap(),
+            (HashAlgorithm::Sha1, "abc")
+        );
+        assert_eq!(
+            parse_content_hash("SHA512:abc").unwrap(),
+            (HashAlgorithm::Sha512, "abc")
+        );
+        assert_eq!(
+            parse_content_hash("abc").unwrap(),
+            (HashAlgorithm::Sha256, "abc")
+        );
+        assert!(matches!(
+            parse_content_hash("md5:abc"),
+            Err(AssetStoreError::UnsupportedHashAlgorithm(tag)) if tag == "md5"
+        ));
+    }
+
+    #[test]
+    fn validate_dispatches_on_algorithm() {
+        let content = b"hello".to_vec();
+        validate_hash(&content, HELLO_SHA256).unwrap();
+        validate_hash(&content, &format!("sha256:{HELLO_SHA256}")).unwrap();
+        validate_hash(&content, &format!("sha1:{HELLO_SHA1}")).unwrap();
+        validate_hash(&content, &format!("sha512:{HELLO_SHA512}")).unwrap();
+        validate_hash(&content, &format!("sha1:{}", HELLO_SHA1.to_uppercase())).unwrap();
+
+        match validate_hash(&content, &format!("sha1:{HELLO_SHA256}")) {
+            Err(AssetStoreError::AssetHashMismatch { actual, .. }) => {
+                assert_eq!(actual, format!("sha1:{HELLO_SHA1}"))
+            }
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn hasher_produces_tagged_hash() {
+        let mut hasher = ContentHasher::new(HashAlgorithm::Sha256);
+        hasher.update(b"hel");
+        hasher.update(b"lo");
+        assert_eq!(hasher.finalize(), format!("sha256:{HELLO_SHA256}"));
+    }
+}
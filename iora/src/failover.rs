@@ -0,0 +1,263 @@
+use crate::{redact_url, AssetLocator, AssetStoreError};
+use std::future::Future;
+use std::time::Duration;
+use tracing::{event, Level};
+
+/// How `fetch_by_descriptor` retries a locator that failed with a transient error, such as
+/// a timeout or a 503, before it moves on to the next locator. The delay between attempts
+/// doubles after every retry, up to the maximum backoff.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Tries every locator once.
+    pub fn no_retries() -> Self {
+        RetryPolicy::default().with_max_attempts(1)
+    }
+
+    /// The number of attempts per locator, including the first one.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    pub fn with_initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// The delay before the given retry, counting from 1.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// An attempt to fetch an asset from one of its locators that failed.
+#[derive(Debug)]
+pub struct FailedAttempt {
+    /// The locator's URL, without the credentials `redact_url` removes.
+    pub url: reqwest::Url,
+    /// The attempt for this locator, counting from 1.
+    pub attempt: u32,
+    pub error: AssetStoreError,
+}
+
+impl std::fmt::Display for FailedAttempt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (attempt {}): {}", self.url, self.attempt, self.error)
+    }
+}
+
+/// Collects the failed attempts of a fetch and decides what to do next.
+struct Attempts {
+    failed: Vec<FailedAttempt>,
+}
+
+impl Attempts {
+    /// Records a failure and returns whether the locator should be retried.
+    fn record(&mut self, locator: &AssetLocator, attempt: u32, error: AssetStoreError) -> bool {
+        let url = redact_url(&locator.url);
+        event!(
+            Level::WARN,
+            url = url.as_str(),
+            attempt,
+            error = error.to_string(),
+            "fetching an asset failed"
+        );
+        let transient = error.is_transient();
+        self.failed.push(FailedAttempt {
+            url,
+            attempt,
+            error,
+        });
+        transient
+    }
+
+    /// A single failure is returned as it is, several are reported together.
+    fn into_error(mut self) -> AssetStoreError {
+        match self.failed.len() {
+            0 => AssetStoreError::NoSupportedLocator,
+            1 => self.failed.pop().unwrap().error,
+            _ => AssetStoreError::AllLocatorsFailed(self.failed),
+        }
+    }
+}
+
+/// Fetches from each locator in turn until one succeeds. Transient errors are retried
+/// according to the policy, any other error moves on to the next locator.
+pub(crate) fn fetch_with_failover<'a, T, L, F>(
+    locators: L,
+    policy: &RetryPolicy,
+    mut fetch: F,
+) -> Result<T, AssetStoreError>
+where
+    L: IntoIterator<Item = &'a AssetLocator>,
+    F: FnMut(&'a AssetLocator) -> Result<T, AssetStoreError>,
+{
+    let mut attempts = Attempts { failed: vec![] };
+    for locator in locators {
+        for attempt in 1..=policy.max_attempts {
+            if attempt > 1 {
+                std::thread::sleep(policy.backoff(attempt - 1));
+            }
+            match fetch(locator) {
+                Ok(result) => return Ok(result),
+                Err(e) => {
+                    if !attempts.record(locator, attempt, e) {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    Err(attempts.into_error())
+}
+
+/// The async counterpart of `fetch_with_failover`.
+pub(crate) async fn fetch_with_failover_async<'a, T, L, F, Fut>(
+    locators: L,
+    policy: &RetryPolicy,
+    mut fetch: F,
+) -> Result<T, AssetStoreError>
+where
+    L: IntoIterator<Item = &'a AssetLocator>,
+    F: FnMut(&'a AssetLocator) -> Fut,
+    Fut: Future<Output = Result<T, AssetStoreError>>,
+{
+    let mut attempts = Attempts { failed: vec![] };
+    for locator in locators {
+        for attempt in 1..=policy.max_attempts {
+            if attempt > 1 {
+                tokio::time::sleep(policy.backoff(attempt - 1)).await;
+            }
+            match fetch(locator).await {
+                Ok(result) => return Ok(result),
+                Err(e) => {
+                    if !attempts.record(locator, attempt, e) {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    Err(attempts.into_error())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fetch_with_failover, RetryPolicy};
+    use crate::{AssetLocator, AssetStoreError};
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    fn locators() -> Vec<AssetLocator> {
+        [
+            "https://primary.example.com/a",
+            "https://mirror.example.com/a",
+        ]
+        .iter()
+        .map(|url| AssetLocator::new(reqwest::Url::from_str(url).unwrap()))
+        .collect()
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let policy = RetryPolicy::default()
+            .with_initial_backoff(Duration::from_millis(100))
+            .with_max_backoff(Duration::from_millis(500));
+        let backoffs: Vec<_> = (1..=4).map(|retry| policy.backoff(retry)).collect();
+        assert_eq!(
+            backoffs,
+            [100, 200, 400, 500].map(Duration::from_millis).to_vec()
+        );
+    }
+
+    #[test]
+    fn retries_transient_errors_and_moves_on_after_others() {
+        let locators = locators();
+        let policy = RetryPolicy::default()
+            .with_max_attempts(3)
+            .with_initial_backoff(Duration::from_millis(1));
+        let mut calls = vec![];
+        let result = fetch_with_failover(&locators, &policy, |locator| {
+            calls.push(locator.url.host_str().unwrap().to_owned());
+            match (locator.url.host_str(), calls.len()) {
+                (Some("primary.example.com"), 1) => Err(AssetStoreError::AssetStoreUnavailable(
+                    "timed out".to_owned(),
+                )),
+                (Some("primary.example.com"), _) => Err(AssetStoreError::AssetHashMismatch {
+                    expected: "a".to_owned(),
+                    actual: "b".to_owned(),
+                }),
+                _ => Ok(calls.len()),
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(
+            calls,
+            [
+                "primary.example.com",
+                "primary.example.com",
+                "mirror.example.com"
+            ]
+        );
+    }
+
+    #[test]
+    fn aggregates_every_failed_attempt() {
+        let locators = locators();
+        let policy = RetryPolicy::default()
+            .with_max_attempts(2)
+            .with_initial_backoff(Duration::from_millis(1));
+        let result: Result<(), _> = fetch_with_failover(&locators, &policy, |_| {
+            Err(AssetStoreError::AssetStoreUnavailable("503".to_owned()))
+        });
+        match result {
+            Err(AssetStoreError::AllLocatorsFailed(attempts)) => {
+                let attempts: Vec<_> = attempts
+                    .iter()
+                    .map(|a| (a.url.host_str().unwrap(), a.attempt))
+                    .collect();
+                assert_eq!(
+                    attempts,
+                    [
+                        ("primary.example.com", 1),
+                        ("primary.example.com", 2),
+                        ("mirror.example.com", 1),
+                        ("mirror.example.com", 2)
+                    ]
+                );
+            }
+            _ => panic!("Expected every attempt to be reported"),
+        }
+
+        let result: Result<(), _> = fetch_with_failover(&locators[..1], &policy, |_| {
+            Err(AssetStoreError::UnsupportedScheme("https".to_owned()))
+        });
+        assert!(matches!(result, Err(AssetStoreError::UnsupportedScheme(_))));
+        let result: Result<(), _> = fetch_with_failover(&locators[..0], &policy, |_| Ok(()));
+        assert!(matches!(result, Err(AssetStoreError::NoSupportedLocator)));
+    }
+}
@@ -0,0 +1,431 @@
+use crate::filesystem::file_lock::FileLock;
+use crate::filesystem::{ASSET_FILE_NAME, DESCRIPTOR_FILE_NAME};
+use crate::{
+    format_content_hash, AssetDescriptor, AssetIndex, AssetLocator, AssetQuery, ContentHasher,
+    HashAlgorithm, ListAssetsError, SemVer,
+};
+use reqwest::Url;
+use std::collections::HashMap;
+use std::fs::{File, Metadata};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+use tracing::{event, Level};
+
+/// Hash sidecars are named after the asset file with the algorithm as an extension, e.g.
+/// `asset.tar.gz.sha256`, strongest first.
+const HASH_SIDECARS: [HashAlgorithm; 3] = [
+    HashAlgorithm::Sha512,
+    HashAlgorithm::Sha256,
+    HashAlgorithm::Sha1,
+];
+
+/// A hash computed by the index, and the state of the file it was computed from.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+struct ManifestEntry {
+    size: u64,
+    modified: SystemTime,
+    content_hash: String,
+}
+
+/// Manifest entries, keyed by the asset file's path relative to the root.
+type Manifest = HashMap<String, ManifestEntry>;
+
+/// Indexes assets that sit in a local directory, or on a network share, laid out as
+/// `name/version/...`. Each version folder holds a single asset file, or an `asset` file
+/// among others. Hashes are read from the `descriptor.json` that
+/// [crate::filesystem::FilesystemAssetPublisher] writes next to the asset, or from a hash
+/// sidecar such as `asset.sha256` as written by `sha256sum`. Without either, the index
+/// computes the SHA-256 of the file on every scan, unless a manifest is kept.
+///
+/// Descriptors locate assets with `file://` URLs.
+#[derive(Debug)]
+pub struct DirectoryAssetIndex {
+    root: PathBuf,
+    manifest_path: Option<PathBuf>,
+}
+
+impl DirectoryAssetIndex {
+    pub fn new(root: &Path) -> Result<Self, ListAssetsError> {
+        if !root.is_absolute() {
+            return Err(ListAssetsError::MisconfiguredIndex(
+                "The directory must be an absolute path.".to_owned(),
+            ));
+        }
+        Ok(DirectoryAssetIndex {
+            root: root.to_owned(),
+            manifest_path: None,
+        })
+    }
+
+    /// Records computed hashes in a manifest file, so that rescans only hash files that
+    /// are new or changed since. Files are considered unchanged while their size and
+    /// modification time are.
+    pub fn with_manifest(mut self, manifest_path: &Path) -> Self {
+        self.manifest_path = Some(manifest_path.to_owned());
+        self
+    }
+
+    /// Describes every asset matching the query, adding the hashes it had to compute to
+    /// `computed`.
+    fn scan(
+        &self,
+        query: &AssetQuery,
+        manifest: &Manifest,
+        computed: &mut Manifest,
+    ) -> Result<Vec<AssetDescriptor>, ListAssetsError> {
+        let mut descriptors = vec![];
+        for (name, name_path) in subdirectories(&self.root)? {
+            if !query.name_constraint.matches(&name) {
+                continue;
+            }
+            for (version, version_path) in subdirectories(&name_path)? {
+                let version = match SemVer::from_str(&version) {
+                    Ok(version) => version,
+                    Err(_) => continue,
+                };
+                if let Some(version_constraint) = &query.version_constraint {
+                    if !version_constraint.matches(&version) {
+                        continue;
+                    }
+                }
+                descriptors.extend(self.describe(
+                    &name,
+                    version,
+                    &version_path,
+                    manifest,
+                    computed,
+                )?);
+            }
+        }
+        Ok(descriptors)
+    }
+
+    fn describe(
+        &self,
+        name: &str,
+        version: SemVer,
+        version_path: &Path,
+        manifest: &Manifest,
+        computed: &mut Manifest,
+    ) -> Result<Option<AssetDescriptor>, ListAssetsError> {
+        let asset_path = match find_asset(version_path)? {
+            Some(asset_path) => asset_path,
+            None => return Ok(None),
+        };
+        let metadata = std::fs::metadata(&asset_path).map_err(io_error)?;
+        let content_hash = match recorded_hash(version_path, &asset_path) {
+            Some(content_hash) => content_hash,
+            None => self.computed_hash(&asset_path, &metadata, manifest, computed)?,
+        };
+        let url = Url::from_file_path(&asset_path).map_err(|_| {
+            ListAssetsError::AssetIndexInternalError(format!(
+                "The path {} could not be converted to a URL.",
+                asset_path.display()
+            ))
+        })?;
+        Ok(Some(AssetDescriptor::new(
+            name,
+            version,
+            &content_hash,
+            metadata.len() as usize,
+            vec![AssetLocator::new(url)],
+        )))
+    }
+
+    /// Hashes the file, unless the manifest holds the hash of the file as it is now.
+    fn computed_hash(
+        &self,
+        asset_path: &Path,
+        metadata: &Metadata,
+        manifest: &Manifest,
+        computed: &mut Manifest,
+    ) -> Result<String, ListAssetsError> {
+        let key = asset_path
+            .strip_prefix(&self.root)
+            .unwrap_or(asset_path)
+            .to_string_lossy()
+            .replace('\\', "/");
+        let modified = metadata.modified().map_err(io_error)?;
+        if let Some(entry) = manifest.get(&key) {
+            if entry.size == metadata.len() && entry.modified == modified {
+                return Ok(entry.content_hash.clone());
+            }
+        }
+
+        let content_hash = hash_file(asset_path).map_err(io_error)?;
+        computed.insert(
+            key,
+            ManifestEntry {
+                size: metadata.len(),
+                modified,
+                content_hash: content_hash.clone(),
+            },
+        );
+        Ok(content_hash)
+    }
+
+    fn read_manifest(&self) -> Manifest {
+        self.manifest_path
+            .as_ref()
+            .and_then(|path| File::open(path).ok())
+            .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok())
+            .unwrap_or_default()
+    }
+
+    /// Adds the computed hashes to the manifest. Other processes may be scanning too, so
+    /// the manifest is re-read under an exclusive lock and replaced atomically. A manifest
+    /// that can't be updated only costs rehashing on the next scan.
+    fn update_manifest(&self, computed: Manifest) {
+        let manifest_path = match &self.manifest_path {
+            Some(manifest_path) if !computed.is_empty() => manifest_path,
+            _ => return,
+        };
+        let mut lock_path = manifest_path.clone().into_os_string();
+        lock_path.push(".lock");
+        let result = FileLock::exclusive(Path::new(&lock_path)).and_then(|_lock| {
+            let mut manifest = self.read_manifest();
+            manifest.extend(computed);
+            save_manifest(manifest_path, &manifest)
+        });
+        if let Err(e) = result {
+            event!(
+                Level::ERROR,
+                error = e.to_string(),
+                "failed to update the manifest"
+            );
+        }
+    }
+}
+
+fn save_manifest(manifest_path: &Path, manifest: &Manifest) -> std::io::Result<()> {
+    let folder = match manifest_path.parent() {
+        Some(folder) if !folder.as_os_str().is_empty() => folder,
+        _ => Path::new("."),
+    };
+    let mut writer = BufWriter::new(tempfile::NamedTempFile::new_in(folder)?);
+    serde_json::to_writer_pretty(&mut writer, manifest)?;
+    writer.flush()?;
+    let staged = writer.into_inner().map_err(|e| e.into_error())?;
+    staged.persist(manifest_path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+fn io_error(e: std::io::Error) -> ListAssetsError {
+    match e.kind() {
+        ErrorKind::NotFound => ListAssetsError::AssetIndexNotFound(Some(e.to_string())),
+        ErrorKind::PermissionDenied => ListAssetsError::AssetIndexAccessDenied(Some(e.to_string())),
+        _ => ListAssetsError::AssetIndexInternalError(e.to_string()),
+    }
+}
+
+/// The folders within a folder, by name. Hidden folders and names that aren't valid
+/// UTF-8 are skipped.
+fn subdirectories(path: &Path) -> Result<Vec<(String, PathBuf)>, ListAssetsError> {
+    let mut folders = vec![];
+    for entry in std::fs::read_dir(path).map_err(io_error)? {
+        let entry = entry.map_err(io_error)?;
+        let is_folder = std::fs::metadata(entry.path()).is_ok_and(|metadata| metadata.is_dir());
+        match entry.file_name().into_string() {
+            Ok(name) if is_folder && !name.starts_with('.') => folders.push((name, entry.path())),
+            _ => {}
+        }
+    }
+    folders.sort();
+    Ok(folders)
+}
+
+fn is_sidecar(file_name: &str) -> bool {
+    file_name == DESCRIPTOR_FILE_NAME
+        || HASH_SIDECARS
+            .iter()
+            .any(|algorithm| file_name.ends_with(&format!(".{}", algorithm.tag())))
+}
+
+/// The asset file of a version folder, if it is unambiguous.
+fn find_asset(version_path: &Path) -> Result<Option<PathBuf>, ListAssetsError> {
+    let mut candidates = vec![];
+    for entry in std::fs::read_dir(version_path).map_err(io_error)? {
+        let entry = entry.map_err(io_error)?;
+        let is_file = std::fs::metadata(entry.path()).is_ok_and(|metadata| metadata.is_file());
+        match entry.file_name().to_str() {
+            Some(name) if is_file && !name.starts_with('.') && !is_sidecar(name) => {
+                candidates.push(entry.path())
+            }
+            _ => {}
+        }
+    }
+    match candidates.len() {
+        0 => Ok(None),
+        1 => Ok(candidates.pop()),
+        _ => {
+            let asset_path = version_path.join(ASSET_FILE_NAME);
+            if candidates.contains(&asset_path) {
+                Ok(Some(asset_path))
+            } else {
+                event!(
+                    Level::WARN,
+                    path = version_path.display().to_string(),
+                    "skipping a version folder that holds several files"
+                );
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// The hash recorded next to the asset, in a descriptor or a hash sidecar.
+fn recorded_hash(version_path: &Path, asset_path: &Path) -> Option<String> {
+    let descriptor = File::open(version_path.join(DESCRIPTOR_FILE_NAME))
+        .ok()
+        .and_then(|file| serde_json::from_reader::<_, AssetDescriptor>(BufReader::new(file)).ok());
+    if let Some(descriptor) = descriptor {
+        return Some(descriptor.content_hash);
+    }
+
+    HASH_SIDECARS.iter().find_map(|algorithm| {
+        let mut sidecar_path = asset_path.as_os_str().to_owned();
+        sidecar_path.push(format!(".{}", algorithm.tag()));
+        let sidecar = std::fs::read_to_string(sidecar_path).ok()?;
+        // Sidecars written by `sha256sum` and friends follow the digest with the file name.
+        let digest = sidecar.split_whitespace().next()?;
+        digest
+            .chars()
+            .all(|c| c.is_ascii_hexdigit())
+            .then(|| format_content_hash(*algorithm, &digest.to_ascii_lowercase()))
+    })
+}
+
+fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = ContentHasher::new(HashAlgorithm::Sha256);
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        match file.read(&mut buf)? {
+            0 => return Ok(hasher.finalize()),
+            read => hasher.update(&buf[..read]),
+        }
+    }
+}
+
+impl AssetIndex for DirectoryAssetIndex {
+    fn list_assets(&self, query: &AssetQuery) -> Result<Vec<AssetDescriptor>, ListAssetsError> {
+        let manifest = self.read_manifest();
+        let mut computed = Manifest::new();
+        let descriptors = self.scan(query, &manifest, &mut computed)?;
+        self.update_manifest(computed);
+        Ok(descriptors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DirectoryAssetIndex, Manifest};
+    use crate::filesystem::FilesystemAssetPublisher;
+    use crate::{AssetIndex, AssetPayload, AssetPublisher, AssetQuery, ListAssetsError, SemVer};
+    use std::fs::{create_dir_all, write, File};
+    use std::path::Path;
+    use std::str::FromStr;
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+    const HELLO_SHA1: &str = "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d";
+
+    fn add_file(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        create_dir_all(path.parent().unwrap()).unwrap();
+        write(path, content).unwrap();
+    }
+
+    fn list(
+        index: &DirectoryAssetIndex,
+        name: &str,
+        version: Option<&str>,
+    ) -> Vec<(String, String)> {
+        let query = AssetQuery::new_from_strings(name, &version.map(|v| v.to_owned())).unwrap();
+        index
+            .list_assets(&query)
+            .unwrap()
+            .into_iter()
+            .map(|d| (format!("{}@{}", d.name, d.version), d.content_hash))
+            .collect()
+    }
+
+    #[test]
+    fn indexes_a_directory_tree() {
+        let root = tempfile::tempdir().unwrap();
+        FilesystemAssetPublisher::new(root.path())
+            .unwrap()
+            .publish(
+                "hello",
+                &SemVer::from_str("1.0.0").unwrap(),
+                AssetPayload::Bytes(b"hello".to_vec()),
+            )
+            .unwrap();
+        add_file(root.path(), "hello/2.0.0/hello.txt", "hello");
+        add_file(
+            root.path(),
+            "hello/2.0.0/hello.txt.sha1",
+            &format!("{HELLO_SHA1}  hello.txt\n"),
+        );
+        add_file(root.path(), "hello/3.0.0/hello.txt", "hello");
+        add_file(root.path(), "hello/not-a-version/hello.txt", "hello");
+        add_file(root.path(), "hello/4.0.0/one.txt", "one");
+        add_file(root.path(), "hello/4.0.0/two.txt", "two");
+        add_file(root.path(), "world/1.0.0/world.txt", "world");
+
+        let index = DirectoryAssetIndex::new(root.path()).unwrap();
+        assert_eq!(
+            list(&index, "hello", None),
+            vec![
+                ("hello@1.0.0".to_owned(), format!("sha256:{HELLO_SHA256}")),
+                ("hello@2.0.0".to_owned(), format!("sha1:{HELLO_SHA1}")),
+                ("hello@3.0.0".to_owned(), format!("sha256:{HELLO_SHA256}")),
+            ]
+        );
+        assert_eq!(list(&index, "*o*", Some("^1")).len(), 2);
+
+        let query = AssetQuery::new_from_strings("hello", &Some("3.0.0".to_owned())).unwrap();
+        let descriptor = &index.list_assets(&query).unwrap()[0];
+        assert_eq!(descriptor.size, 5);
+        assert_eq!(
+            descriptor.locators[0].url.to_file_path().unwrap(),
+            root.path().join("hello/3.0.0/hello.txt")
+        );
+
+        assert!(matches!(
+            DirectoryAssetIndex::new(&root.path().join("missing"))
+                .unwrap()
+                .list_assets(&query),
+            Err(ListAssetsError::AssetIndexNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn manifest_makes_rescans_incremental() {
+        let root = tempfile::tempdir().unwrap();
+        let manifest_path = root.path().join("manifest.json");
+        add_file(root.path(), "assets/hello/1.0.0/hello.txt", "hello");
+        let index = DirectoryAssetIndex::new(&root.path().join("assets"))
+            .unwrap()
+            .with_manifest(&manifest_path);
+        assert_eq!(
+            list(&index, "hello", None)[0].1,
+            format!("sha256:{HELLO_SHA256}")
+        );
+
+        // The manifest is trusted while the file is unchanged, so a doctored entry shows
+        // that the file wasn't hashed again.
+        let mut manifest: Manifest =
+            serde_json::from_reader(File::open(&manifest_path).unwrap()).unwrap();
+        manifest
+            .get_mut("hello/1.0.0/hello.txt")
+            .unwrap()
+            .content_hash = "sha256:00".to_owned();
+        serde_json::to_writer(File::create(&manifest_path).unwrap(), &manifest).unwrap();
+        assert_eq!(list(&index, "hello", None)[0].1, "sha256:00");
+
+        add_file(root.path(), "assets/hello/1.0.0/hello.txt", "hello, world");
+        assert_ne!(list(&index, "hello", None)[0].1, "sha256:00");
+    }
+}
@@ -0,0 +1,182 @@
+use crate::adapters::run_blocking;
+use crate::{
+    AssetLocator, AssetPayload, AssetStore, AssetStoreError, AsyncAssetPayload, AsyncAssetStore,
+    AsyncHashValidatingReader, HashValidatingReader,
+};
+use async_trait::async_trait;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Fetches assets located by `file` URLs. Only files under one of the allowed roots are
+/// served, so that a descriptor can't point the store at arbitrary files on the machine.
+/// Paths are resolved before they are checked, so `..` segments and symbolic links can't
+/// escape the roots. A store without allowed roots serves no files at all.
+#[derive(Clone, Debug, Default)]
+pub struct FileAssetStore {
+    allowed_roots: Vec<PathBuf>,
+}
+
+impl FileAssetStore {
+    pub fn new() -> Self {
+        FileAssetStore::default()
+    }
+
+    /// Allows the files under `root` to be served.
+    pub fn with_allowed_root(mut self, root: &Path) -> Self {
+        if !self.allowed_roots.iter().any(|allowed| allowed == root) {
+            self.allowed_roots.push(root.to_owned());
+        }
+        self
+    }
+
+    /// The path of the file a locator points at, if the store may serve it. The URL is
+    /// percent-decoded, so `file:///assets/hello%20world` is `/assets/hello world`.
+    fn resolve(&self, locator: &AssetLocator) -> Result<PathBuf, AssetStoreError> {
+        if locator.url.scheme() != "file" {
+            return Err(AssetStoreError::UnsupportedScheme(
+                locator.url.scheme().to_owned(),
+            ));
+        }
+        let path = locator.url.to_file_path().map_err(|_| {
+            AssetStoreError::AssetStoreInternalError(format!(
+                "The URL {} doesn't point at a local file.",
+                locator.url
+            ))
+        })?;
+        let path = path.canonicalize()?;
+        let allowed = self
+            .allowed_roots
+            .iter()
+            .filter_map(|root| root.canonicalize().ok())
+            .any(|root| path.starts_with(root));
+        if allowed {
+            Ok(path)
+        } else {
+            Err(AssetStoreError::PathNotAllowed(path.display().to_string()))
+        }
+    }
+
+    fn open(&self, locator: &AssetLocator) -> Result<File, AssetStoreError> {
+        Ok(File::open(self.resolve(locator)?)?)
+    }
+}
+
+impl AssetStore for FileAssetStore {
+    fn supports_locator(&self, locator: &AssetLocator) -> bool {
+        locator.url.scheme() == "file"
+    }
+
+    fn fetch_by_locator(
+        &self,
+        locator: &AssetLocator,
+        expected_hash: &str,
+    ) -> Result<AssetPayload, AssetStoreError> {
+        Ok(AssetPayload::Stream(Box::new(HashValidatingReader::new(
+            self.open(locator)?,
+            expected_hash,
+        ))))
+    }
+}
+
+#[async_trait]
+impl AsyncAssetStore for FileAssetStore {
+    fn supports_locator(&self, locator: &AssetLocator) -> bool {
+        locator.url.scheme() == "file"
+    }
+
+    async fn fetch_by_locator(
+        &self,
+        locator: &AssetLocator,
+        expected_hash: &str,
+    ) -> Result<AsyncAssetPayload, AssetStoreError> {
+        let store = self.clone();
+        let locator = locator.clone();
+        let file = run_blocking(move || store.open(&locator)).await?;
+        Ok(AsyncAssetPayload::Stream(Box::pin(
+            AsyncHashValidatingReader::new(tokio::fs::File::from_std(file), expected_hash),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileAssetStore;
+    use crate::{AssetLocator, AssetStore, AssetStoreError};
+    use reqwest::Url;
+    use std::fs::{create_dir_all, write};
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    fn locator(url: &str) -> AssetLocator {
+        AssetLocator::new(Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn serves_files_under_allowed_roots() {
+        let root = tempfile::tempdir().unwrap();
+        let allowed = root.path().join("allowed");
+        create_dir_all(allowed.join("hello world")).unwrap();
+        write(allowed.join("hello world/hello.txt"), "hello").unwrap();
+        write(root.path().join("secret.txt"), "hello").unwrap();
+        let store = FileAssetStore::new().with_allowed_root(&allowed);
+
+        let url = Url::from_file_path(allowed.join("hello world/hello.txt")).unwrap();
+        assert!(url.as_str().contains("hello%20world"));
+        let payload = store
+            .fetch_by_locator(&AssetLocator::new(url), HELLO_SHA256)
+            .unwrap();
+        assert_eq!(payload.into_bytes().unwrap(), b"hello");
+
+        // The URL parser resolves `..` segments, but an encoded slash only becomes one
+        // when the URL is decoded to a path.
+        let escaping = format!(
+            "{}/..%2Fsecret.txt",
+            Url::from_file_path(&allowed).unwrap().as_str()
+        );
+        assert!(matches!(
+            store.fetch_by_locator(&locator(&escaping), HELLO_SHA256),
+            Err(AssetStoreError::PathNotAllowed(_))
+        ));
+        assert!(matches!(
+            FileAssetStore::new().fetch_by_locator(
+                &AssetLocator::new(Url::from_file_path(root.path().join("secret.txt")).unwrap()),
+                HELLO_SHA256
+            ),
+            Err(AssetStoreError::PathNotAllowed(_))
+        ));
+        assert!(matches!(
+            store.fetch_by_locator(&locator("https://example.com/hello.txt"), HELLO_SHA256),
+            Err(AssetStoreError::UnsupportedScheme(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symbolic_links_cant_escape_the_roots() {
+        let root = tempfile::tempdir().unwrap();
+        let allowed = root.path().join("allowed");
+        create_dir_all(&allowed).unwrap();
+        write(root.path().join("secret.txt"), "hello").unwrap();
+        std::os::unix::fs::symlink(root.path().join("secret.txt"), allowed.join("link.txt"))
+            .unwrap();
+        let store = FileAssetStore::new().with_allowed_root(&allowed);
+        let url = Url::from_file_path(allowed.join("link.txt")).unwrap();
+        assert!(matches!(
+            store.fetch_by_locator(&AssetLocator::new(url), HELLO_SHA256),
+            Err(AssetStoreError::PathNotAllowed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn serves_files_asynchronously() {
+        let root = tempfile::tempdir().unwrap();
+        write(root.path().join("hello.txt"), "hello").unwrap();
+        let store = FileAssetStore::new().with_allowed_root(root.path());
+        let url = Url::from_file_path(root.path().join("hello.txt")).unwrap();
+        let payload =
+            crate::AsyncAssetStore::fetch_by_locator(&store, &AssetLocator::new(url), HELLO_SHA256)
+                .await
+                .unwrap();
+        assert_eq!(payload.into_bytes().await.unwrap(), b"hello");
+    }
+}
@@ -0,0 +1,119 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+use std::path::Path;
+
+/// An advisory lock on a file, shared between processes. The lock is released when the
+/// value is dropped, or by the operating system if the process dies while holding it.
+#[derive(Debug)]
+pub(crate) struct FileLock {
+    file: File,
+}
+
+impl FileLock {
+    /// Blocks until the lock is acquired. The lock file is created if it doesn't exist.
+    pub(crate) fn exclusive(path: &Path) -> io::Result<Self> {
+        let file = Self::open(path)?;
+        file.lock()?;
+        Ok(FileLock { file })
+    }
+
+    /// Like `exclusive`, for lock files that their holder removes with `remove`. Should the
+    /// file have been removed while waiting for the lock, the lock is taken on the file that
+    /// replaced it instead, so that two holders never lock different files of the same name.
+    pub(crate) fn exclusive_removable(path: &Path) -> io::Result<Self> {
+        loop {
+            let lock = Self::exclusive(path)?;
+            if lock.is_at(path) {
+                return Ok(lock);
+            }
+        }
+    }
+
+    /// The non-blocking counterpart of `exclusive_removable`.
+    pub(crate) fn try_exclusive_removable(path: &Path) -> io::Result<Option<Self>> {
+        loop {
+            match Self::try_exclusive(path)? {
+                Some(lock) if !lock.is_at(path) => continue,
+                lock => return Ok(lock),
+            }
+        }
+    }
+
+    /// Removes a lock file taken with `exclusive_removable`, then releases the lock. Where
+    /// open files can't be removed, the lock file stays in place.
+    pub(crate) fn remove(self, path: &Path) {
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[cfg(unix)]
+    fn is_at(&self, path: &Path) -> bool {
+        use std::os::unix::fs::MetadataExt;
+        match (self.file.metadata(), std::fs::metadata(path)) {
+            (Ok(held), Ok(current)) => held.dev() == current.dev() && held.ino() == current.ino(),
+            _ => false,
+        }
+    }
+
+    /// Windows doesn't let a file be created in place of a removed one that's still open.
+    #[cfg(not(unix))]
+    fn is_at(&self, _path: &Path) -> bool {
+        true
+    }
+
+    /// Returns `None` instead of blocking when another handle holds the lock.
+    pub(crate) fn try_exclusive(path: &Path) -> io::Result<Option<Self>> {
+        let file = Self::open(path)?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(FileLock { file })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(e)) => Err(e),
+        }
+    }
+
+    fn open(path: &Path) -> io::Result<File> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileLock;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn removed_locks_are_taken_on_their_replacement() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("asset.lock");
+        let first = FileLock::exclusive_removable(&path).unwrap();
+
+        let (acquired, on_acquired) = mpsc::channel();
+        let (release, on_release) = mpsc::channel::<()>();
+        let waiting_path = path.clone();
+        let waiter = std::thread::spawn(move || {
+            let _second = FileLock::exclusive_removable(&waiting_path).unwrap();
+            acquired.send(()).unwrap();
+            on_release.recv().unwrap();
+        });
+        // Give the waiter time to block on the file that's about to be removed.
+        std::thread::sleep(Duration::from_millis(100));
+        first.remove(&path);
+
+        on_acquired.recv().unwrap();
+        assert!(FileLock::try_exclusive(&path).unwrap().is_none());
+        release.send(()).unwrap();
+        waiter.join().unwrap();
+        assert!(FileLock::try_exclusive(&path).unwrap().is_some());
+    }
+}
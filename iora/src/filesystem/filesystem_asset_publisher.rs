@@ -0,0 +1,165 @@
+use crate::asset_publisher::copy_and_hash;
+use crate::filesystem::{ASSET_FILE_NAME, DESCRIPTOR_FILE_NAME};
+use crate::{
+    validate_asset_name, AssetDescriptor, AssetLocator, AssetPayload, AssetPublisher,
+    PublishAssetError, SemVer,
+};
+use reqwest::Url;
+use std::fs::{create_dir, create_dir_all, remove_dir_all, File};
+use std::io::{BufWriter, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// Publishes assets to a directory using the `name/version/asset` layout. The index entry
+/// for each asset is written next to it as `descriptor.json`.
+pub struct FilesystemAssetPublisher {
+    storage_path: PathBuf,
+}
+
+impl FilesystemAssetPublisher {
+    pub fn new(storage_path: &Path) -> Result<Self, PublishAssetError> {
+        if !storage_path.is_absolute() {
+            Err(PublishAssetError::MisconfiguredPublisher(
+                "Storage root must be an absolute path.".to_owned(),
+            ))
+        } else {
+            Ok(FilesystemAssetPublisher {
+                storage_path: storage_path.to_owned(),
+            })
+        }
+    }
+
+    fn write_asset(
+        &self,
+        version_path: &Path,
+        name: &str,
+        version: &SemVer,
+        payload: AssetPayload,
+    ) -> Result<AssetDescriptor, PublishAssetError> {
+        let asset_path = version_path.join(ASSET_FILE_NAME);
+        let url = Url::from_file_path(&asset_path).map_err(|_| {
+            PublishAssetError::MisconfiguredPublisher(
+                "Asset path could not be converted to a URL.".to_owned(),
+            )
+        })?;
+        let mut writer = BufWriter::new(File::create(&asset_path)?);
+        let (content_hash, size) = copy_and_hash(payload, &mut writer)?;
+        let descriptor = AssetDescriptor::new(
+            name,
+            version.clone(),
+            &content_hash,
+            size,
+            vec![AssetLocator::new(url)],
+        );
+
+        let descriptor_writer =
+            BufWriter::new(File::create(version_path.join(DESCRIPTOR_FILE_NAME))?);
+        serde_json::to_writer_pretty(descriptor_writer, &descriptor)
+            .map_err(|e| PublishAssetError::PublisherInternalError(e.to_string()))?;
+        Ok(descriptor)
+    }
+}
+
+impl AssetPublisher for FilesystemAssetPublisher {
+    fn publish(
+        &self,
+        name: &str,
+        version: &SemVer,
+        payload: AssetPayload,
+    ) -> Result<AssetDescriptor, PublishAssetError> {
+        validate_asset_name(name)?;
+        let asset_path = self.storage_path.join(name);
+        create_dir_all(&asset_path)?;
+
+        // Creating the version folder claims the version, so concurrent publishers can't
+        // both succeed.
+        let version_path = asset_path.join(version.to_string());
+        if let Err(e) = create_dir(&version_path) {
+            return Err(if e.kind() == ErrorKind::AlreadyExists {
+                PublishAssetError::AssetAlreadyExists {
+                    name: name.to_owned(),
+                    version: version.to_string(),
+                }
+            } else {
+                e.into()
+            });
+        }
+
+        let result = self.write_asset(&version_path, name, version, payload);
+        if result.is_err() {
+            let _ = remove_dir_all(&version_path);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FilesystemAssetPublisher;
+    use crate::{
+        AssetDescriptor, AssetPayload, AssetPublisher, AssetStoreError, HashValidatingReader,
+        PublishAssetError, SemVer,
+    };
+    use std::fs::File;
+    use std::str::FromStr;
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn publish_writes_content_and_descriptor() {
+        let root = tempfile::tempdir().unwrap();
+        let publisher = FilesystemAssetPublisher::new(root.path()).unwrap();
+        let version = SemVer::from_str("1.0.0").unwrap();
+        let descriptor = publisher
+            .publish(
+                "hello",
+                &version,
+                AssetPayload::Stream(Box::new("hello".as_bytes())),
+            )
+            .unwrap();
+        assert_eq!(descriptor.content_hash, format!("sha256:{HELLO_SHA256}"));
+        assert_eq!(descriptor.size, 5);
+        assert_eq!(
+            descriptor.locators[0].url.to_file_path().unwrap(),
+            root.path().join("hello/1.0.0/asset")
+        );
+        assert_eq!(
+            std::fs::read(root.path().join("hello/1.0.0/asset")).unwrap(),
+            b"hello"
+        );
+        let saved: AssetDescriptor = serde_json::from_reader(
+            File::open(root.path().join("hello/1.0.0/descriptor.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(saved.content_hash, descriptor.content_hash);
+
+        assert!(matches!(
+            publisher.publish("hello", &version, AssetPayload::Bytes(b"other".to_vec())),
+            Err(PublishAssetError::AssetAlreadyExists { .. })
+        ));
+        assert!(matches!(
+            publisher.publish("../hello", &version, AssetPayload::Bytes(vec![])),
+            Err(PublishAssetError::InvalidAssetName(_))
+        ));
+    }
+
+    #[test]
+    fn failed_publish_can_be_retried() {
+        let root = tempfile::tempdir().unwrap();
+        let publisher = FilesystemAssetPublisher::new(root.path()).unwrap();
+        let version = SemVer::from_str("1.0.0").unwrap();
+        let corrupt = AssetPayload::Stream(Box::new(HashValidatingReader::new(
+            "hullo".as_bytes(),
+            HELLO_SHA256,
+        )));
+        assert!(matches!(
+            publisher.publish("hello", &version, corrupt),
+            Err(PublishAssetError::PayloadError(
+                AssetStoreError::AssetHashMismatch { .. }
+            ))
+        ));
+        assert!(!root.path().join("hello/1.0.0").exists());
+        assert!(publisher
+            .publish("hello", &version, AssetPayload::Bytes(b"hello".to_vec()))
+            .is_ok());
+    }
+}
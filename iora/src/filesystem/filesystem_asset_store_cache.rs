@@ -1,23 +1,86 @@
+use crate::content_hash::parse_content_hash;
+use crate::filesystem::file_lock::FileLock;
+use crate::filesystem::FileAssetStore;
+use crate::filesystem::ASSET_FILE_NAME;
+use crate::adapters::{into_blocking_payload, run_blocking};
+use crate::failover::{fetch_with_failover, fetch_with_failover_async};
 use crate::{
-    validate_hash, AssetDescriptor, AssetLocator, AssetPayload, AssetStore, AssetStoreError,
+    AssetDescriptor, AssetLocator, AssetPayload, AssetStore, AssetStoreError, AsyncAssetPayload,
+    AsyncAssetStore, HashValidatingReader, SemVer,
 };
+use async_trait::async_trait;
 use reqwest::Url;
-use std::fs::{create_dir_all, File, OpenOptions};
-use std::io::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fs::{create_dir_all, read_dir, remove_dir, remove_file, File};
+use std::io::{copy, sink, BufWriter, Seek};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{event, Level};
 
-pub struct FilesystemAssetStoreCache<TInnerStore>
-where
-    TInnerStore: AssetStore,
-{
-    storage_path: PathBuf,
+const LAST_ACCESS_FILE_NAME: &str = "last_access";
+const PINNED_FILE_NAME: &str = "pinned";
+const REFERENCE_FILE_NAME: &str = "reference";
+const STAGING_FILE_PREFIX: &str = ".asset-";
+const LOCK_FILE_NAME: &str = ".lock";
+const OBJECTS_FOLDER_NAME: &str = ".objects";
+/// How long a staging file must have gone unmodified before it's considered abandoned.
+const STAGING_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+/// How the cache lays out asset content on disk.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CacheLayout {
+    /// Content is stored at `name/version/asset`.
+    #[default]
+    NameAndVersion,
+    /// Content is stored once per content hash at `.objects/algorithm/digest`, and
+    /// `name/version/reference` holds the content hash the version resolves to. Identical
+    /// content published under several names or versions is only stored once, and a
+    /// republished version can't be confused with the content it replaced.
+    ContentAddressed,
+}
+
+/// An asset held by the cache, as seen by `prune`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CachedAsset {
+    pub name: String,
+    pub version: SemVer,
+    pub size: u64,
+    pub last_access: SystemTime,
+    pub pinned: bool,
+}
+
+/// What a call to `prune` removed from the cache. With the content-addressed layout, content
+/// shared by several assets is only freed once the last of them is removed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PruneReport {
+    pub removed: Vec<CachedAsset>,
+    pub freed_bytes: u64,
+    pub remaining_bytes: u64,
+}
+
+/// Caches the assets of an inner store on disk. The cache is an async store when the inner
+/// store is; its file system work then runs on tokio's blocking thread pool.
+///
+/// `file` locators are served in place by a [FileAssetStore] rather than copied into the
+/// cache. By default only the cache's own files are served, such as those located by
+/// `save_asset`.
+pub struct FilesystemAssetStoreCache<TInnerStore> {
+    storage: CacheStorage,
+    file_store: FileAssetStore,
     inner_store: TInnerStore,
 }
 
-impl<TInnerStore> FilesystemAssetStoreCache<TInnerStore>
-where
-    TInnerStore: AssetStore,
-{
+/// The on-disk side of the cache, which doesn't depend on the inner store, so that it can be
+/// cloned onto blocking threads.
+#[derive(Clone)]
+struct CacheStorage {
+    storage_path: PathBuf,
+    max_size_bytes: Option<u64>,
+    layout: CacheLayout,
+}
+
+impl<TInnerStore> FilesystemAssetStoreCache<TInnerStore> {
     pub fn new(storage_path: &Path, inner_store: TInnerStore) -> Result<Self, AssetStoreError> {
         if !storage_path.is_absolute() {
             Err(AssetStoreError::MisconfiguredStore(
@@ -25,53 +88,560 @@ where
             ))
         } else {
             Ok(FilesystemAssetStoreCache {
-                storage_path: storage_path.to_owned(),
+                storage: CacheStorage {
+                    storage_path: storage_path.to_owned(),
+                    max_size_bytes: None,
+                    layout: CacheLayout::default(),
+                },
+                file_store: FileAssetStore::new().with_allowed_root(storage_path),
                 inner_store,
             })
         }
     }
 
-    fn get_local_path_for_descriptor(&self, descriptor: &AssetDescriptor) -> PathBuf {
-        self.storage_path
-            .join(&descriptor.name)
-            .join(descriptor.version.to_string())
-            .join("asset")
+    /// Bounds the total size of the cached assets. Whenever an asset is added the least
+    /// recently used assets are evicted until the cache fits the quota again. Pinned
+    /// assets and the asset that was just added are never evicted, so the quota can be
+    /// exceeded if they don't fit on their own. An eviction that fails is logged, and
+    /// doesn't fail the fetch.
+    pub fn with_max_size_bytes(mut self, max_size_bytes: u64) -> Self {
+        self.storage.max_size_bytes = Some(max_size_bytes);
+        self
+    }
+
+    /// Both layouts can't share a storage path, so switching an existing cache to another
+    /// layout requires a new storage path.
+    pub fn with_layout(mut self, layout: CacheLayout) -> Self {
+        self.storage.layout = layout;
+        self
+    }
+
+    /// Serves `file` locators with the given store, e.g. to allow the files of a
+    /// [crate::filesystem::DirectoryAssetIndex]. The cache's own files stay allowed.
+    pub fn with_file_store(mut self, file_store: FileAssetStore) -> Self {
+        self.file_store = file_store.with_allowed_root(&self.storage.storage_path);
+        self
+    }
+
+    /// Protects an asset from eviction. The asset doesn't need to be in the cache yet.
+    pub fn pin(&self, name: &str, version: &SemVer) -> Result<(), AssetStoreError> {
+        self.storage.pin(name, version)
+    }
+
+    pub fn unpin(&self, name: &str, version: &SemVer) -> Result<(), AssetStoreError> {
+        self.storage.unpin(name, version)
+    }
+
+    pub fn is_pinned(&self, name: &str, version: &SemVer) -> bool {
+        self.storage.is_pinned(name, version)
+    }
+
+    /// Evicts least recently used assets until the cache fits its quota. Only staging files
+    /// left behind by interrupted downloads are removed when no quota was configured.
+    pub fn prune(&self) -> Result<PruneReport, AssetStoreError> {
+        self.storage.prune()
     }
 
+    /// Evicts least recently used assets until the cache holds at most `max_size_bytes`.
+    /// Staging files left behind by interrupted downloads are removed as well.
+    pub fn prune_to_size(&self, max_size_bytes: u64) -> Result<PruneReport, AssetStoreError> {
+        self.storage.prune_to(max_size_bytes, None)
+    }
+
+    /// Lists the assets in the cache. Folders that don't follow the cache's layout are
+    /// ignored.
+    pub fn list_cached_assets(&self) -> Result<Vec<CachedAsset>, AssetStoreError> {
+        self.storage.list_cached_assets()
+    }
+
+    /// Streams the payload to its place in the cache. Stream payloads are written as they
+    /// are read, so the asset never has to be held in memory. The content is staged in a
+    /// temporary file next to its final path, checked against the descriptor's hash, and
+    /// only then renamed into place, so a reader never sees a partial or corrupt asset even
+    /// if the process dies mid-write. If the payload fails to read or doesn't match the hash
+    /// the temporary file is removed.
     pub fn save_asset(
         &self,
         descriptor: &AssetDescriptor,
-        payload: &AssetPayload,
-    ) -> Option<AssetLocator> {
-        let file_path = self.get_local_path_for_descriptor(descriptor);
-        if let Some(folder) = file_path.parent() {
-            if !folder.exists() && create_dir_all(folder).is_err() {
-                return None;
+        payload: AssetPayload,
+    ) -> Result<AssetLocator, AssetStoreError> {
+        self.storage.save_asset(descriptor, payload)
+    }
+}
+
+impl CacheStorage {
+    fn get_local_path_for_descriptor(
+        &self,
+        descriptor: &AssetDescriptor,
+    ) -> Result<PathBuf, AssetStoreError> {
+        match self.layout {
+            CacheLayout::NameAndVersion => Ok(self
+                .get_local_folder(&descriptor.name, &descriptor.version)
+                .join(ASSET_FILE_NAME)),
+            CacheLayout::ContentAddressed => self.get_object_path(&descriptor.content_hash),
+        }
+    }
+
+    fn get_local_folder(&self, name: &str, version: &SemVer) -> PathBuf {
+        self.storage_path.join(name).join(version.to_string())
+    }
+
+    fn get_object_path(&self, content_hash: &str) -> Result<PathBuf, AssetStoreError> {
+        let (algorithm, digest) = parse_content_hash(content_hash)?;
+        // The digest becomes a file name, so anything but hex digits is rejected.
+        if digest.is_empty() || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(AssetStoreError::AssetStoreInternalError(format!(
+                "Malformed content hash '{content_hash}'."
+            )));
+        }
+        Ok(self
+            .storage_path
+            .join(OBJECTS_FOLDER_NAME)
+            .join(algorithm.tag())
+            .join(digest.to_ascii_lowercase()))
+    }
+
+    /// The lock taken while an asset's content is fetched. With the content-addressed layout
+    /// it guards the content, so assets that share content are never downloaded twice.
+    fn get_lock_path(&self, descriptor: &AssetDescriptor) -> Result<PathBuf, AssetStoreError> {
+        match self.layout {
+            CacheLayout::NameAndVersion => Ok(self
+                .get_local_folder(&descriptor.name, &descriptor.version)
+                .join(LOCK_FILE_NAME)),
+            CacheLayout::ContentAddressed => {
+                Ok(object_lock_path(&self.get_object_path(&descriptor.content_hash)?))
             }
         }
+    }
+
+    fn pin(&self, name: &str, version: &SemVer) -> Result<(), AssetStoreError> {
+        let folder = self.get_local_folder(name, version);
+        create_dir_all(&folder)?;
+        File::create(folder.join(PINNED_FILE_NAME))?;
+        Ok(())
+    }
+
+    fn unpin(&self, name: &str, version: &SemVer) -> Result<(), AssetStoreError> {
+        match remove_file(self.get_local_folder(name, version).join(PINNED_FILE_NAME)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn is_pinned(&self, name: &str, version: &SemVer) -> bool {
+        self.get_local_folder(name, version).join(PINNED_FILE_NAME).exists()
+    }
+
+    fn prune(&self) -> Result<PruneReport, AssetStoreError> {
+        match self.max_size_bytes {
+            Some(max_size_bytes) => self.prune_to(max_size_bytes, None),
+            None => Ok(PruneReport {
+                freed_bytes: self.remove_stale_staging_files()?,
+                remaining_bytes: content_size(&self.list_entries()?),
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn prune_to(
+        &self,
+        max_size_bytes: u64,
+        keep: Option<&AssetDescriptor>,
+    ) -> Result<PruneReport, AssetStoreError> {
+        let mut entries = self.list_entries()?;
+        entries.sort_by_key(|(asset, _)| asset.last_access);
+        let mut report = PruneReport {
+            freed_bytes: self.remove_stale_staging_files()?,
+            remaining_bytes: content_size(&entries),
+            ..Default::default()
+        };
+        if self.layout == CacheLayout::ContentAddressed {
+            report.freed_bytes += self.remove_unreferenced_objects(&entries)?;
+        }
+        let mut references = HashMap::<PathBuf, usize>::new();
+        for (_, content_path) in entries.iter() {
+            *references.entry(content_path.clone()).or_default() += 1;
+        }
 
-        if !file_path.is_absolute() {
-            return None;
+        for (asset, content_path) in entries {
+            if report.remaining_bytes <= max_size_bytes {
+                break;
+            }
+            let kept = keep.is_some_and(|d| d.name == asset.name && d.version == asset.version);
+            if asset.pinned || kept {
+                continue;
+            }
+            let folder = self.get_local_folder(&asset.name, &asset.version);
+            if !remove_entry_folder(&folder)? {
+                continue;
+            }
+            let freed = match self.layout {
+                CacheLayout::NameAndVersion => asset.size,
+                CacheLayout::ContentAddressed => {
+                    let remaining_references = references.entry(content_path.clone()).or_default();
+                    *remaining_references -= 1;
+                    if *remaining_references == 0 && remove_object(&content_path)? {
+                        asset.size
+                    } else {
+                        0
+                    }
+                }
+            };
+            report.remaining_bytes -= freed;
+            report.freed_bytes += freed;
+            report.removed.push(asset);
         }
+        Ok(report)
+    }
 
-        let new_url = Url::from_file_path(&file_path);
-        let file = OpenOptions::new()
-            .create_new(true)
-            .write(true)
-            .open(file_path);
+    /// Removes content no asset refers to any more, for instance because the process that
+    /// downloaded it died before recording the reference. Returns the number of bytes freed.
+    fn remove_unreferenced_objects(
+        &self,
+        entries: &[(CachedAsset, PathBuf)],
+    ) -> Result<u64, AssetStoreError> {
+        let referenced: HashSet<&PathBuf> = entries.iter().map(|(_, path)| path).collect();
+        let objects_folder = self.storage_path.join(OBJECTS_FOLDER_NAME);
+        if !objects_folder.exists() {
+            return Ok(0);
+        }
+        let mut freed = 0;
+        for algorithm_entry in read_dir(objects_folder)? {
+            let algorithm_entry = algorithm_entry?;
+            if !algorithm_entry.file_type()?.is_dir() {
+                continue;
+            }
+            for object_entry in read_dir(algorithm_entry.path())? {
+                let object_entry = object_entry?;
+                let path = object_entry.path();
+                let is_object = object_entry
+                    .file_name()
+                    .to_str()
+                    .is_some_and(|name| name.chars().all(|c| c.is_ascii_hexdigit()));
+                if !is_object || referenced.contains(&path) {
+                    continue;
+                }
+                let size = object_entry.metadata()?.len();
+                if remove_object(&path)? {
+                    freed += size;
+                }
+            }
+        }
+        Ok(freed)
+    }
 
-        if let (Ok(new_url), Ok(mut file)) = (new_url, file) {
-            match payload {
-                AssetPayload::Bytes(buf) => {
-                    if let Ok(()) = file.write_all(buf) {
-                        return Some(AssetLocator { url: new_url });
+    /// Removes the staging files of downloads that were interrupted, for instance because the
+    /// process died, which would otherwise never be removed nor counted against the quota.
+    /// Staging files next to an asset are only removed while holding the asset's lock;
+    /// those next to content-addressed objects can't be told apart, so they're left alone
+    /// until they've gone unmodified for the grace period. Returns the number of bytes freed.
+    fn remove_stale_staging_files(&self) -> Result<u64, AssetStoreError> {
+        if !self.storage_path.exists() {
+            return Ok(0);
+        }
+        let mut freed = 0;
+        for name_entry in read_dir(&self.storage_path)? {
+            let name_entry = name_entry?;
+            if !name_entry.file_type()?.is_dir() {
+                continue;
+            }
+            let is_objects_folder = name_entry.file_name() == OBJECTS_FOLDER_NAME;
+            for entry in read_dir(name_entry.path())? {
+                let entry = entry?;
+                if !entry.file_type()?.is_dir() {
+                    continue;
+                }
+                let folder = entry.path();
+                let stale = stale_staging_files(&folder)?;
+                if stale.is_empty() {
+                    continue;
+                }
+                let lock_path = folder.join(LOCK_FILE_NAME);
+                let _lock = match is_objects_folder {
+                    true => None,
+                    false => match FileLock::try_exclusive_removable(&lock_path)? {
+                        Some(lock) => Some(lock),
+                        None => continue,
+                    },
+                };
+                for (path, size) in stale {
+                    match remove_file(path) {
+                        Ok(()) => freed += size,
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                        Err(e) => return Err(e.into()),
                     }
                 }
             }
         }
+        Ok(freed)
+    }
+
+    fn list_cached_assets(&self) -> Result<Vec<CachedAsset>, AssetStoreError> {
+        Ok(self
+            .list_entries()?
+            .into_iter()
+            .map(|(asset, _)| asset)
+            .collect())
+    }
+
+    /// Lists the assets in the cache along with the path of the file holding their content.
+    fn list_entries(&self) -> Result<Vec<(CachedAsset, PathBuf)>, AssetStoreError> {
+        let mut assets = vec![];
+        if !self.storage_path.exists() {
+            return Ok(assets);
+        }
+        for name_entry in read_dir(&self.storage_path)? {
+            let name_entry = name_entry?;
+            if !name_entry.file_type()?.is_dir() || name_entry.file_name() == OBJECTS_FOLDER_NAME {
+                continue;
+            }
+            for version_entry in read_dir(name_entry.path())? {
+                let version_entry = version_entry?;
+                let folder = version_entry.path();
+                let version = match version_entry.file_name().to_str().map(SemVer::from_str) {
+                    Some(Ok(version)) => version,
+                    _ => continue,
+                };
+                let content_path = match self.layout {
+                    CacheLayout::NameAndVersion => folder.join(ASSET_FILE_NAME),
+                    CacheLayout::ContentAddressed => {
+                        let reference = std::fs::read_to_string(folder.join(REFERENCE_FILE_NAME));
+                        match reference.map(|hash| self.get_object_path(hash.trim())) {
+                            Ok(Ok(path)) => path,
+                            _ => continue,
+                        }
+                    }
+                };
+                let metadata = match content_path.metadata() {
+                    Ok(metadata) if metadata.is_file() => metadata,
+                    _ => continue,
+                };
+                assets.push((
+                    CachedAsset {
+                        name: name_entry.file_name().to_string_lossy().into_owned(),
+                        version,
+                        size: metadata.len(),
+                        last_access: read_last_access(&folder)
+                            .or_else(|| metadata.modified().ok())
+                            .unwrap_or(UNIX_EPOCH),
+                        pinned: folder.join(PINNED_FILE_NAME).exists(),
+                    },
+                    content_path,
+                ));
+            }
+        }
+        Ok(assets)
+    }
 
-        None
+    /// Opens the cached asset if it exists and its content matches the descriptor's size
+    /// and hash. The content is read once to check it, and the file is returned rewound,
+    /// so a corrupt entry is noticed before anything is served from it.
+    fn open_verified(&self, descriptor: &AssetDescriptor) -> Result<Option<File>, AssetStoreError> {
+        let asset_path = self.get_local_path_for_descriptor(descriptor)?;
+        let mut file = match File::open(asset_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if file.metadata()?.len() != descriptor.size as u64 {
+            return Ok(None);
+        }
+        let mut reader = HashValidatingReader::new(&mut file, &descriptor.content_hash);
+        match copy(&mut reader, &mut sink()).map_err(AssetStoreError::from) {
+            Ok(_) => {}
+            Err(AssetStoreError::AssetHashMismatch { .. }) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        file.rewind()?;
+        self.record_access(descriptor);
+        Ok(Some(file))
     }
+
+    /// Takes the lock on the asset's content, then checks whether another process brought
+    /// the asset into the cache while this one was waiting. Only one process downloads a
+    /// given asset; the others find it in place once they get the lock. An entry that fails
+    /// verification is dropped, so that it's fetched again while the lock is held.
+    fn lock_entry(
+        &self,
+        descriptor: &AssetDescriptor,
+    ) -> Result<(FileLock, Option<File>), AssetStoreError> {
+        let lock_path = self.get_lock_path(descriptor)?;
+        let lock = loop {
+            if let Some(folder) = lock_path.parent() {
+                create_dir_all(folder)?;
+            }
+            match FileLock::exclusive_removable(&lock_path) {
+                // Eviction removes the folder along with the lock file.
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                lock => break lock?,
+            }
+        };
+        if let Some(file) = self.open_verified(descriptor)? {
+            return Ok((lock, Some(file)));
+        }
+        if let Err(e) = remove_file(self.get_local_path_for_descriptor(descriptor)?) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(e.into());
+            }
+        }
+        Ok((lock, None))
+    }
+
+    /// Saves the payload and opens the saved asset.
+    fn save_and_open(
+        &self,
+        descriptor: &AssetDescriptor,
+        payload: AssetPayload,
+    ) -> Result<File, AssetStoreError> {
+        self.save_asset(descriptor, payload)?;
+        Ok(File::open(self.get_local_path_for_descriptor(descriptor)?)?)
+    }
+
+    /// Records the access time and, with the content-addressed layout, the reference to the
+    /// content. Both only steer eviction, and content that lost its reference is fetched
+    /// again, so failing to record them isn't an error.
+    fn record_access(&self, descriptor: &AssetDescriptor) {
+        let folder = self.get_local_folder(&descriptor.name, &descriptor.version);
+        if self.layout == CacheLayout::ContentAddressed {
+            let _ = create_dir_all(&folder).and_then(|_| {
+                std::fs::write(folder.join(REFERENCE_FILE_NAME), &descriptor.content_hash)
+            });
+        }
+        let _ = write_last_access(&folder, SystemTime::now());
+    }
+
+    fn save_asset(
+        &self,
+        descriptor: &AssetDescriptor,
+        payload: AssetPayload,
+    ) -> Result<AssetLocator, AssetStoreError> {
+        let file_path = self.get_local_path_for_descriptor(descriptor)?;
+        let folder = file_path.parent().ok_or_else(|| {
+            AssetStoreError::MisconfiguredStore("Asset path has no parent folder.".to_owned())
+        })?;
+        if !folder.exists() {
+            create_dir_all(folder)?;
+        }
+
+        let new_url = Url::from_file_path(&file_path).map_err(|_| {
+            AssetStoreError::MisconfiguredStore(
+                "Asset path could not be converted to a URL.".to_owned(),
+            )
+        })?;
+        let staged = tempfile::Builder::new()
+            .prefix(STAGING_FILE_PREFIX)
+            .tempfile_in(folder)?;
+
+        let mut reader = HashValidatingReader::new(payload.into_reader(), &descriptor.content_hash);
+        let mut writer = BufWriter::new(staged);
+        copy(&mut reader, &mut writer)?;
+        let staged = writer.into_inner().map_err(|e| e.into_error())?;
+        staged.as_file().sync_all()?;
+        staged.persist(&file_path).map_err(|e| e.error)?;
+
+        self.record_access(descriptor);
+        if let Some(max_size_bytes) = self.max_size_bytes {
+            // The asset is in place, so failing to make room for it only delays eviction.
+            if let Err(e) = self.prune_to(max_size_bytes, Some(descriptor)) {
+                event!(Level::WARN, error = e.to_string(), "pruning the cache failed");
+            }
+        }
+        Ok(AssetLocator::new(new_url))
+    }
+}
+
+/// The staging files in the folder that have gone unmodified for the grace period, along
+/// with their sizes.
+fn stale_staging_files(folder: &Path) -> Result<Vec<(PathBuf, u64)>, AssetStoreError> {
+    let mut stale = vec![];
+    for entry in read_dir(folder)? {
+        let entry = entry?;
+        let is_staging_file = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.starts_with(STAGING_FILE_PREFIX));
+        if !is_staging_file {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        let age = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+        if metadata.is_file() && age.is_some_and(|age| age >= STAGING_GRACE_PERIOD) {
+            stale.push((entry.path(), metadata.len()));
+        }
+    }
+    Ok(stale)
+}
+
+/// Access times are kept in a file next to the asset, as milliseconds since the Unix epoch,
+/// because file system access times are often disabled or updated lazily.
+fn read_last_access(folder: &Path) -> Option<SystemTime> {
+    let millis = std::fs::read_to_string(folder.join(LAST_ACCESS_FILE_NAME)).ok()?;
+    Some(UNIX_EPOCH + Duration::from_millis(millis.trim().parse().ok()?))
+}
+
+fn write_last_access(folder: &Path, time: SystemTime) -> std::io::Result<()> {
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    std::fs::write(folder.join(LAST_ACCESS_FILE_NAME), millis.to_string())
+}
+
+fn object_lock_path(object_path: &Path) -> PathBuf {
+    let mut lock_path = object_path.to_owned().into_os_string();
+    lock_path.push(LOCK_FILE_NAME);
+    PathBuf::from(lock_path)
+}
+
+/// The size of the content held by the cache, counting shared content once.
+fn content_size(entries: &[(CachedAsset, PathBuf)]) -> u64 {
+    let mut seen = HashSet::new();
+    entries
+        .iter()
+        .filter(|(_, content_path)| seen.insert(content_path))
+        .map(|(asset, _)| asset.size)
+        .sum()
+}
+
+/// Removes an asset's name/version folder unless another process holds its lock. Returns
+/// whether the folder was removed.
+fn remove_entry_folder(folder: &Path) -> Result<bool, AssetStoreError> {
+    let lock_path = folder.join(LOCK_FILE_NAME);
+    let lock = match FileLock::try_exclusive_removable(&lock_path)? {
+        Some(lock) => lock,
+        None => return Ok(false),
+    };
+    for entry in read_dir(folder)? {
+        let entry = entry?;
+        if entry.file_name() != LOCK_FILE_NAME {
+            remove_file(entry.path())?;
+        }
+    }
+    // Processes waiting for the lock take it on a new lock file, in a new folder if need
+    // be. The folder is only removed if it's empty, i.e. if none of them has got that far.
+    lock.remove(&lock_path);
+    let _ = remove_dir(folder);
+    if let Some(name_folder) = folder.parent() {
+        // Only succeeds once the last version of the asset is gone.
+        let _ = remove_dir(name_folder);
+    }
+    Ok(true)
+}
+
+/// Removes a content-addressed object unless another process is fetching it. Returns
+/// whether the object was removed.
+fn remove_object(object_path: &Path) -> Result<bool, AssetStoreError> {
+    let lock_path = object_lock_path(object_path);
+    let lock = match FileLock::try_exclusive_removable(&lock_path)? {
+        Some(lock) => lock,
+        None => return Ok(false),
+    };
+    remove_file(object_path)?;
+    lock.remove(&lock_path);
+    Ok(true)
 }
 
 impl<TInnerStore> AssetStore for FilesystemAssetStoreCache<TInnerStore>
@@ -79,7 +649,8 @@ where
     TInnerStore: AssetStore,
 {
     fn supports_locator(&self, locator: &AssetLocator) -> bool {
-        locator.url.scheme() == "file" || self.inner_store.supports_locator(locator)
+        AssetStore::supports_locator(&self.file_store, locator)
+            || self.inner_store.supports_locator(locator)
     }
 
     fn fetch_by_locator(
@@ -87,58 +658,502 @@ where
         locator: &AssetLocator,
         expected_hash: &str,
     ) -> Result<AssetPayload, AssetStoreError> {
-        if locator.url.scheme() == "file" {
-            if let Ok(mut f) = File::open(locator.url.as_str()) {
-                let mut buf = vec![];
-                match f.read_to_end(&mut buf) {
-                    Ok(_) => {
-                        validate_hash(&buf, expected_hash)?;
-                        return Ok(AssetPayload::Bytes(buf));
-                    }
-                    Err(e) => return Err(AssetStoreError::AssetStoreInternalError(e.to_string())),
-                }
-            }
-        }
-
-        Err(crate::AssetStoreError::UnsupportedScheme(
-            locator.url.scheme().to_owned(),
-        ))
+        AssetStore::fetch_by_locator(&self.file_store, locator, expected_hash)
     }
 
     fn fetch_by_descriptor(
         &self,
         descriptor: &AssetDescriptor,
     ) -> Result<AssetPayload, AssetStoreError> {
-        let asset_path = self.get_local_path_for_descriptor(descriptor);
-        if asset_path.exists() {
-            if let Ok(mut f) = File::open(asset_path) {
-                let mut buffer = vec![];
-                if f.read_to_end(&mut buffer).is_ok() {
-                    return Ok(AssetPayload::Bytes(buffer));
-                }
+        // Corrupt entries are only removed while holding the lock, so a failed check here
+        // falls through to `lock_entry`, which checks the entry again.
+        if let Some(file) = self.storage.open_verified(descriptor)? {
+            return Ok(AssetPayload::Stream(Box::new(file)));
+        }
+        let (_lock, file) = self.storage.lock_entry(descriptor)?;
+        if let Some(file) = file {
+            return Ok(AssetPayload::Stream(Box::new(file)));
+        }
+
+        // The download is checked while it's saved, so a mismatching mirror is skipped too.
+        let locators: Vec<&AssetLocator> = descriptor
+            .locators_by_priority()
+            .into_iter()
+            .filter(|locator| AssetStore::supports_locator(self, locator))
+            .collect();
+        fetch_with_failover(locators, &self.inner_store.retry_policy(), |locator| {
+            if AssetStore::supports_locator(&self.file_store, locator) {
+                return AssetStore::fetch_by_locator(
+                    &self.file_store,
+                    locator,
+                    &descriptor.content_hash,
+                );
             }
+            self.inner_store
+                .fetch_by_locator(locator, &descriptor.content_hash)
+                .and_then(|payload| self.storage.save_and_open(descriptor, payload))
+                .map(|file| AssetPayload::Stream(Box::new(file)))
+        })
+    }
+}
+
+#[async_trait]
+impl<TInnerStore> AsyncAssetStore for FilesystemAssetStoreCache<TInnerStore>
+where
+    TInnerStore: AsyncAssetStore,
+{
+    fn supports_locator(&self, locator: &AssetLocator) -> bool {
+        AsyncAssetStore::supports_locator(&self.file_store, locator)
+            || self.inner_store.supports_locator(locator)
+    }
+
+    async fn fetch_by_locator(
+        &self,
+        locator: &AssetLocator,
+        expected_hash: &str,
+    ) -> Result<AsyncAssetPayload, AssetStoreError> {
+        AsyncAssetStore::fetch_by_locator(&self.file_store, locator, expected_hash).await
+    }
+
+    async fn fetch_by_descriptor(
+        &self,
+        descriptor: &AssetDescriptor,
+    ) -> Result<AsyncAssetPayload, AssetStoreError> {
+        let storage = self.storage.clone();
+        let owned_descriptor = descriptor.clone();
+        if let Some(file) = run_blocking(move || storage.open_verified(&owned_descriptor)).await? {
+            return Ok(into_async_file_payload(file));
+        }
+        let storage = self.storage.clone();
+        let owned_descriptor = descriptor.clone();
+        // The lock is held across the download, just like in the blocking implementation.
+        let (_lock, file) = run_blocking(move || storage.lock_entry(&owned_descriptor)).await?;
+        if let Some(file) = file {
+            return Ok(into_async_file_payload(file));
         }
 
-        let mut error = AssetStoreError::NoSupportedLocator;
-        for locator in descriptor.locators.iter() {
-            if locator.url.scheme() == "file" {
-                match self.fetch_by_locator(locator, &descriptor.content_hash) {
-                    Ok(payload) => return Ok(payload),
-                    Err(e) => error = e,
-                }
-            } else if self.inner_store.supports_locator(locator) {
-                match self
-                    .inner_store
-                    .fetch_by_locator(locator, &descriptor.content_hash)
-                {
-                    Ok(payload) => {
-                        self.save_asset(descriptor, &payload);
-                        return Ok(payload);
-                    }
-                    Err(e) => error = e,
-                }
+        let locators: Vec<&AssetLocator> = descriptor
+            .locators_by_priority()
+            .into_iter()
+            .filter(|locator| AsyncAssetStore::supports_locator(self, locator))
+            .collect();
+        let policy = self.inner_store.retry_policy();
+        fetch_with_failover_async(locators, &policy, |locator| async move {
+            if AsyncAssetStore::supports_locator(&self.file_store, locator) {
+                return AsyncAssetStore::fetch_by_locator(
+                    &self.file_store,
+                    locator,
+                    &descriptor.content_hash,
+                )
+                .await;
             }
+            let payload = self
+                .inner_store
+                .fetch_by_locator(locator, &descriptor.content_hash)
+                .await?;
+            let storage = self.storage.clone();
+            let owned_descriptor = descriptor.clone();
+            let runtime = tokio::runtime::Handle::current();
+            let file = run_blocking(move || {
+                let payload = into_blocking_payload(payload, runtime);
+                storage.save_and_open(&owned_descriptor, payload)
+            })
+            .await?;
+            Ok(into_async_file_payload(file))
+        })
+        .await
+    }
+}
+
+fn into_async_file_payload(file: File) -> AsyncAssetPayload {
+    AsyncAssetPayload::Stream(Box::pin(tokio::fs::File::from_std(file)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_last_access, CacheLayout, FilesystemAssetStoreCache};
+    use crate::filesystem::FileAssetStore;
+    use crate::{
+        AssetDescriptor, AssetLocator, AssetPayload, AssetStore, AssetStoreError, AsyncAdapter,
+        AsyncAssetStore, HashValidatingReader, SemVer,
+    };
+    use std::io::Read;
+    use std::str::FromStr;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+    const WORLD_SHA256: &str = "486ea46224d1bb4fb680f34f7c9ad96a8f24ec88be73ea8e5a6c65260e9cb8a7";
+
+    struct StreamingStore {
+        content: &'static str,
+    }
+
+    impl AssetStore for StreamingStore {
+        fn supports_locator(&self, locator: &AssetLocator) -> bool {
+            locator.url.scheme() == "https"
+        }
+
+        fn fetch_by_locator(
+            &self,
+            _locator: &AssetLocator,
+            expected_hash: &str,
+        ) -> Result<AssetPayload, AssetStoreError> {
+            Ok(AssetPayload::Stream(Box::new(HashValidatingReader::new(
+                self.content.as_bytes(),
+                expected_hash,
+            ))))
         }
-        Err(error)
+    }
+
+    fn descriptor() -> AssetDescriptor {
+        named_descriptor("hello")
+    }
+
+    fn named_descriptor(name: &str) -> AssetDescriptor {
+        AssetDescriptor::new(
+            name,
+            SemVer::from_str("1.0.0").unwrap(),
+            HELLO_SHA256,
+            5,
+            vec![AssetLocator::new(
+                reqwest::Url::from_str("https://example.com/hello").unwrap(),
+            )],
+        )
+    }
+
+    #[test]
+    fn streams_inner_payload_to_disk() {
+        let root = tempfile::tempdir().unwrap();
+        let cache =
+            FilesystemAssetStoreCache::new(root.path(), StreamingStore { content: "hello" })
+                .unwrap();
+        let mut content = String::new();
+        cache
+            .fetch_by_descriptor(&descriptor())
+            .unwrap()
+            .into_reader()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "hello");
+        assert!(root.path().join("hello/1.0.0/asset").exists());
+    }
+
+    #[tokio::test]
+    async fn async_fetch_streams_inner_payload_to_disk() {
+        let root = tempfile::tempdir().unwrap();
+        let cache = FilesystemAssetStoreCache::new(
+            root.path(),
+            AsyncAdapter::new(StreamingStore { content: "hello" }),
+        )
+        .unwrap();
+        for _ in 0..2 {
+            let payload = AsyncAssetStore::fetch_by_descriptor(&cache, &descriptor())
+                .await
+                .unwrap();
+            assert_eq!(payload.into_bytes().await.unwrap(), b"hello");
+        }
+        assert!(root.path().join("hello/1.0.0/asset").exists());
+    }
+
+    #[test]
+    fn discards_partial_file_on_hash_mismatch() {
+        let root = tempfile::tempdir().unwrap();
+        let cache =
+            FilesystemAssetStoreCache::new(root.path(), StreamingStore { content: "hullo" })
+                .unwrap();
+        assert!(matches!(
+            cache.fetch_by_descriptor(&descriptor()),
+            Err(AssetStoreError::AssetHashMismatch { .. })
+        ));
+        assert!(!root.path().join("hello/1.0.0/asset").exists());
+    }
+
+    fn set_last_access(root: &std::path::Path, name: &str, seconds: u64) {
+        write_last_access(
+            &root.join(name).join("1.0.0"),
+            UNIX_EPOCH + Duration::from_secs(seconds),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn evicts_least_recently_used_assets_over_quota() {
+        let root = tempfile::tempdir().unwrap();
+        let cache =
+            FilesystemAssetStoreCache::new(root.path(), StreamingStore { content: "hello" })
+                .unwrap()
+                .with_max_size_bytes(10);
+        cache.fetch_by_descriptor(&named_descriptor("a")).unwrap();
+        cache.fetch_by_descriptor(&named_descriptor("b")).unwrap();
+        set_last_access(root.path(), "a", 2000);
+        set_last_access(root.path(), "b", 1000);
+
+        cache.fetch_by_descriptor(&named_descriptor("c")).unwrap();
+        assert!(root.path().join("a/1.0.0/asset").exists());
+        assert!(!root.path().join("b").exists());
+        assert!(root.path().join("c/1.0.0/asset").exists());
+    }
+
+    #[test]
+    fn serves_assets_when_eviction_fails() {
+        let root = tempfile::tempdir().unwrap();
+        let cache =
+            FilesystemAssetStoreCache::new(root.path(), StreamingStore { content: "hello" })
+                .unwrap()
+                .with_max_size_bytes(5);
+        cache.fetch_by_descriptor(&named_descriptor("a")).unwrap();
+        set_last_access(root.path(), "a", 1000);
+        // A folder the cache didn't create can't be evicted along with the asset.
+        std::fs::create_dir(root.path().join("a/1.0.0/extra")).unwrap();
+
+        let payload = cache.fetch_by_descriptor(&named_descriptor("b")).unwrap();
+        assert_eq!(payload.into_bytes().unwrap(), b"hello");
+        assert!(root.path().join("b/1.0.0/asset").exists());
+        assert!(root.path().join("a/1.0.0/extra").exists());
+    }
+
+    #[test]
+    fn prune_skips_pinned_assets_and_reports_removals() {
+        let root = tempfile::tempdir().unwrap();
+        let cache =
+            FilesystemAssetStoreCache::new(root.path(), StreamingStore { content: "hello" })
+                .unwrap();
+        cache.fetch_by_descriptor(&named_descriptor("a")).unwrap();
+        cache.fetch_by_descriptor(&named_descriptor("b")).unwrap();
+        cache.fetch_by_descriptor(&named_descriptor("c")).unwrap();
+        set_last_access(root.path(), "a", 1000);
+        set_last_access(root.path(), "b", 2000);
+        set_last_access(root.path(), "c", 3000);
+        cache.pin("a", &SemVer::from_str("1.0.0").unwrap()).unwrap();
+
+        let report = cache.prune().unwrap();
+        assert!(report.removed.is_empty());
+        assert_eq!(report.remaining_bytes, 15);
+
+        let report = cache.prune_to_size(5).unwrap();
+        let removed: Vec<_> = report.removed.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(removed, vec!["b", "c"]);
+        assert_eq!(report.freed_bytes, 10);
+        assert_eq!(report.remaining_bytes, 5);
+        assert!(root.path().join("a/1.0.0/asset").exists());
+    }
+
+    #[test]
+    fn refetches_corrupt_entries() {
+        let root = tempfile::tempdir().unwrap();
+        let cache =
+            FilesystemAssetStoreCache::new(root.path(), StreamingStore { content: "hello" })
+                .unwrap();
+        let asset_path = root.path().join("hello/1.0.0/asset");
+        std::fs::create_dir_all(asset_path.parent().unwrap()).unwrap();
+        std::fs::write(&asset_path, "hel").unwrap();
+
+        let mut content = String::new();
+        cache
+            .fetch_by_descriptor(&descriptor())
+            .unwrap()
+            .into_reader()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "hello");
+        assert_eq!(std::fs::read_to_string(&asset_path).unwrap(), "hello");
+    }
+
+    #[test]
+    fn drops_entries_corrupted_after_they_were_saved() {
+        let root = tempfile::tempdir().unwrap();
+        let cache =
+            FilesystemAssetStoreCache::new(root.path(), StreamingStore { content: "hello" })
+                .unwrap();
+        cache.fetch_by_descriptor(&descriptor()).unwrap();
+        let asset_path = root.path().join("hello/1.0.0/asset");
+        std::fs::write(&asset_path, "hullo").unwrap();
+
+        let payload = cache.fetch_by_descriptor(&descriptor()).unwrap();
+        assert_eq!(payload.into_bytes().unwrap(), b"hello");
+        assert_eq!(std::fs::read(&asset_path).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn prune_removes_abandoned_staging_files() {
+        let root = tempfile::tempdir().unwrap();
+        let cache =
+            FilesystemAssetStoreCache::new(root.path(), StreamingStore { content: "hello" })
+                .unwrap();
+        cache.fetch_by_descriptor(&descriptor()).unwrap();
+        let folder = root.path().join("hello/1.0.0");
+        let objects = root.path().join(".objects/sha256");
+        std::fs::create_dir_all(&objects).unwrap();
+        let abandoned = [
+            folder.join(".asset-abandoned"),
+            objects.join(".asset-abandoned"),
+        ];
+        for path in abandoned.iter() {
+            let file = std::fs::File::create(path).unwrap();
+            file.set_len(3).unwrap();
+            file.set_modified(SystemTime::now() - Duration::from_secs(2 * 60 * 60))
+                .unwrap();
+        }
+        let in_progress = folder.join(".asset-in-progress");
+        std::fs::write(&in_progress, "he").unwrap();
+
+        let report = cache.prune().unwrap();
+        assert_eq!(report.freed_bytes, 6);
+        assert_eq!(report.remaining_bytes, 5);
+        assert!(abandoned.iter().all(|path| !path.exists()));
+        assert!(in_progress.exists());
+        assert!(folder.join("asset").exists());
+    }
+
+    #[test]
+    fn save_asset_leaves_no_staging_file_on_mismatch() {
+        let root = tempfile::tempdir().unwrap();
+        let cache =
+            FilesystemAssetStoreCache::new(root.path(), StreamingStore { content: "hello" })
+                .unwrap();
+        assert!(matches!(
+            cache.save_asset(&descriptor(), AssetPayload::Bytes(b"hullo".to_vec())),
+            Err(AssetStoreError::AssetHashMismatch { .. })
+        ));
+        let folder = root.path().join("hello/1.0.0");
+        assert_eq!(std::fs::read_dir(folder).unwrap().count(), 0);
+    }
+
+    /// Serves "hullo" from the primary and "hello" from any other host.
+    struct MirroredStore {}
+
+    impl AssetStore for MirroredStore {
+        fn supports_locator(&self, _locator: &AssetLocator) -> bool {
+            true
+        }
+
+        fn fetch_by_locator(
+            &self,
+            locator: &AssetLocator,
+            expected_hash: &str,
+        ) -> Result<AssetPayload, AssetStoreError> {
+            let content = match locator.url.host_str() {
+                Some("primary.example.com") => "hullo",
+                _ => "hello",
+            };
+            Ok(AssetPayload::Stream(Box::new(HashValidatingReader::new(
+                content.as_bytes(),
+                expected_hash,
+            ))))
+        }
+    }
+
+    #[test]
+    fn falls_back_to_mirror_on_hash_mismatch() {
+        let root = tempfile::tempdir().unwrap();
+        let cache = FilesystemAssetStoreCache::new(root.path(), MirroredStore {}).unwrap();
+        let locator = |url: &str| AssetLocator::new(reqwest::Url::from_str(url).unwrap());
+        let mut mirrored = descriptor();
+        mirrored.locators = vec![
+            locator("https://mirror.example.com/hello").with_priority(2),
+            locator("https://primary.example.com/hello").with_priority(1),
+        ];
+        let payload = cache.fetch_by_descriptor(&mirrored).unwrap();
+        assert_eq!(payload.into_bytes().unwrap(), b"hello");
+        assert!(root.path().join("hello/1.0.0/asset").exists());
+    }
+
+    #[test]
+    fn serves_file_locators_in_place() {
+        let root = tempfile::tempdir().unwrap();
+        let cache_path = root.path().join("cache");
+        let cache =
+            FilesystemAssetStoreCache::new(&cache_path, StreamingStore { content: "world" })
+                .unwrap();
+        let locator = cache
+            .save_asset(&descriptor(), AssetPayload::Bytes(b"hello".to_vec()))
+            .unwrap();
+        let payload = cache.fetch_by_locator(&locator, HELLO_SHA256).unwrap();
+        assert_eq!(payload.into_bytes().unwrap(), b"hello");
+
+        let shared = root.path().join("shared");
+        std::fs::create_dir_all(&shared).unwrap();
+        std::fs::write(shared.join("hello.txt"), "hello").unwrap();
+        let mut local = descriptor();
+        local.locators = vec![AssetLocator::new(
+            reqwest::Url::from_file_path(shared.join("hello.txt")).unwrap(),
+        )];
+        assert!(matches!(
+            cache.fetch_by_locator(&local.locators[0], HELLO_SHA256),
+            Err(AssetStoreError::PathNotAllowed(_))
+        ));
+        let cache =
+            FilesystemAssetStoreCache::new(&cache_path, StreamingStore { content: "world" })
+                .unwrap()
+                .with_file_store(FileAssetStore::new().with_allowed_root(&shared));
+        let payload = cache
+            .fetch_by_locator(&local.locators[0], HELLO_SHA256)
+            .unwrap();
+        assert_eq!(payload.into_bytes().unwrap(), b"hello");
+        let payload = cache.fetch_by_locator(&locator, HELLO_SHA256).unwrap();
+        assert_eq!(payload.into_bytes().unwrap(), b"hello");
+    }
+
+    #[test]
+    fn content_addressed_layout_stores_shared_content_once() {
+        let root = tempfile::tempdir().unwrap();
+        let cache =
+            FilesystemAssetStoreCache::new(root.path(), StreamingStore { content: "hello" })
+                .unwrap()
+                .with_layout(CacheLayout::ContentAddressed);
+        cache.fetch_by_descriptor(&named_descriptor("a")).unwrap();
+
+        // This store would fail the hash check, so "b" can only be served from the content
+        // "a" already brought into the cache.
+        let cache =
+            FilesystemAssetStoreCache::new(root.path(), StreamingStore { content: "hullo" })
+                .unwrap()
+                .with_layout(CacheLayout::ContentAddressed);
+        let mut content = String::new();
+        cache
+            .fetch_by_descriptor(&named_descriptor("b"))
+            .unwrap()
+            .into_reader()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "hello");
+
+        let objects = root.path().join(".objects/sha256");
+        assert!(objects.join(HELLO_SHA256).exists());
+        for name in ["a", "b"] {
+            let reference = root.path().join(name).join("1.0.0/reference");
+            assert_eq!(std::fs::read_to_string(reference).unwrap(), HELLO_SHA256);
+        }
+        assert_eq!(cache.list_cached_assets().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn content_addressed_prune_frees_content_with_its_last_reference() {
+        let root = tempfile::tempdir().unwrap();
+        let hello_cache =
+            FilesystemAssetStoreCache::new(root.path(), StreamingStore { content: "hello" })
+                .unwrap()
+                .with_layout(CacheLayout::ContentAddressed);
+        hello_cache.fetch_by_descriptor(&named_descriptor("a")).unwrap();
+        hello_cache.fetch_by_descriptor(&named_descriptor("b")).unwrap();
+        let world_cache =
+            FilesystemAssetStoreCache::new(root.path(), StreamingStore { content: "world" })
+                .unwrap()
+                .with_layout(CacheLayout::ContentAddressed);
+        let mut world = named_descriptor("c");
+        world.content_hash = WORLD_SHA256.to_owned();
+        world_cache.fetch_by_descriptor(&world).unwrap();
+        set_last_access(root.path(), "a", 1000);
+        set_last_access(root.path(), "b", 2000);
+        set_last_access(root.path(), "c", 3000);
+
+        let report = world_cache.prune_to_size(5).unwrap();
+        let removed: Vec<_> = report.removed.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(removed, vec!["a", "b"]);
+        assert_eq!(report.freed_bytes, 5);
+        assert_eq!(report.remaining_bytes, 5);
+        let objects = root.path().join(".objects/sha256");
+        assert!(!objects.join(HELLO_SHA256).exists());
+        assert!(objects.join(WORLD_SHA256).exists());
     }
 }
@@ -1,10 +1,11 @@
-use crate::{AssetDescriptor, AssetIndex, AssetQuery, ListAssetsError};
+use crate::filesystem::file_lock::FileLock;
+use crate::{AssetDescriptor, AssetIndex, AssetQuery, Freshness, ListAssetsError};
 use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::fmt::Debug;
-use std::fs::{File, OpenOptions};
+use std::fs::File;
 use std::hash::{Hash, Hasher};
-use std::io::{BufReader, BufWriter};
+use std::io::{BufReader, BufWriter, Write};
 use std::path::{Path, PathBuf};
 use std::time::{Duration, SystemTime};
 use tracing::{event, instrument, Level};
@@ -23,6 +24,8 @@ where
 {
     storage_path: PathBuf,
     max_age: Duration,
+    stale_if_error: Duration,
+    offline: bool,
     inner_index: TInnerIndex,
 }
 
@@ -36,10 +39,103 @@ where
         JsonFileAssetIndexCache {
             storage_path: file_path.to_path_buf(),
             max_age,
+            stale_if_error: Duration::ZERO,
+            offline: false,
             inner_index,
         }
     }
 
+    /// When the inner index is unavailable, serves cached results that expired less than
+    /// `stale_if_error` ago instead of failing.
+    pub fn with_stale_if_error(mut self, stale_if_error: Duration) -> Self {
+        self.stale_if_error = stale_if_error;
+        self
+    }
+
+    /// An offline cache never calls the inner index. It answers from the cache whatever the
+    /// age of the cached results, and fails for queries it holds no results for.
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Lists the assets like `list_assets`, and tells whether the results are fresh or were
+    /// served stale.
+    pub fn list_assets_with_freshness(
+        &self,
+        query: &AssetQuery,
+    ) -> Result<(Vec<AssetDescriptor>, Freshness), ListAssetsError> {
+        if self.offline {
+            return self
+                .cached(query, None)
+                .ok_or_else(ListAssetsError::not_cached_offline);
+        }
+        if let Some(cached @ (_, Freshness::Fresh)) = self.cached(query, Some(Duration::ZERO)) {
+            return Ok(cached);
+        }
+
+        match self.inner_index.list_assets(query) {
+            Ok(results) => {
+                self.update_file(|cache_map| {
+                    cache_map.insert(
+                        Self::cache_key(query),
+                        CacheEntry {
+                            descriptor: results.to_vec(),
+                            query: query.clone(),
+                            last_modified: SystemTime::now(),
+                        },
+                    );
+                });
+                Ok((results, Freshness::Fresh))
+            }
+            Err(e) if e.is_outage() => match self.cached(query, Some(self.stale_if_error)) {
+                Some(cached) => {
+                    event!(Level::WARN, error = e.to_string(), "serving stale results");
+                    Ok(cached)
+                }
+                None => Err(e),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Looks up the cached results for the query. When the query itself isn't cached, the
+    /// results are filtered from the most recent cached query that covers it. Results past
+    /// the max age are only returned if they expired less than `max_staleness` ago, or
+    /// whatever their age when `max_staleness` is `None`.
+    fn cached(
+        &self,
+        query: &AssetQuery,
+        max_staleness: Option<Duration>,
+    ) -> Option<(Vec<AssetDescriptor>, Freshness)> {
+        let entries = self.read_from_file();
+        let freshness = |entry: &CacheEntry| {
+            let age = SystemTime::now()
+                .duration_since(entry.last_modified)
+                .unwrap_or(self.max_age);
+            Freshness::of(age, self.max_age, max_staleness)
+        };
+        let entry = match entries.get(&Self::cache_key(query)) {
+            Some(entry) if entry.query == *query && freshness(entry).is_some() => entry,
+            _ => entries
+                .values()
+                .filter(|entry| freshness(entry).is_some() && query.is_covered_by(&entry.query))
+                .max_by_key(|entry| entry.last_modified)?,
+        };
+
+        let descriptors = if entry.query == *query {
+            entry.descriptor.clone()
+        } else {
+            entry
+                .descriptor
+                .iter()
+                .filter(|descriptor| descriptor.matches_query(query))
+                .cloned()
+                .collect()
+        };
+        Some((descriptors, freshness(entry)?))
+    }
+
     fn read_from_file(&self) -> HashMap<u64, CacheEntry> {
         if let Ok(file) = File::open(&self.storage_path) {
             let reader = BufReader::new(file);
@@ -51,21 +147,42 @@ where
         HashMap::new()
     }
 
-    #[instrument]
-    fn save_to_file(&self, descriptors: HashMap<u64, CacheEntry>) {
-        match OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(&self.storage_path)
-        {
-            Ok(file) => {
-                let writer = BufWriter::new(file);
-                if let Err(e) = serde_json::to_writer_pretty(writer, &descriptors) {
-                    event!(Level::ERROR, error = e.to_string());
-                }
+    /// Applies an update to the cache file. Other processes may be updating the same file,
+    /// so the file is re-read under an exclusive lock, and the new content is written to a
+    /// temporary file that replaces the old one atomically.
+    #[instrument(skip(update))]
+    fn update_file<F>(&self, update: F)
+    where
+        F: FnOnce(&mut HashMap<u64, CacheEntry>),
+    {
+        let mut lock_path = self.storage_path.clone().into_os_string();
+        lock_path.push(".lock");
+        let _lock = match FileLock::exclusive(Path::new(&lock_path)) {
+            Ok(lock) => lock,
+            Err(e) => {
+                event!(Level::ERROR, error = e.to_string());
+                return;
             }
-            Err(e) => event!(Level::ERROR, error = e.to_string()),
         };
+
+        let mut descriptors = self.read_from_file();
+        update(&mut descriptors);
+        if let Err(e) = self.save_to_file(&descriptors) {
+            event!(Level::ERROR, error = e.to_string());
+        }
+    }
+
+    fn save_to_file(&self, descriptors: &HashMap<u64, CacheEntry>) -> std::io::Result<()> {
+        let folder = match self.storage_path.parent() {
+            Some(folder) if !folder.as_os_str().is_empty() => folder,
+            _ => Path::new("."),
+        };
+        let mut writer = BufWriter::new(tempfile::NamedTempFile::new_in(folder)?);
+        serde_json::to_writer_pretty(&mut writer, descriptors)?;
+        writer.flush()?;
+        let staged = writer.into_inner().map_err(|e| e.into_error())?;
+        staged.persist(&self.storage_path).map_err(|e| e.error)?;
+        Ok(())
     }
 
     fn cache_key(query: &AssetQuery) -> u64 {
@@ -80,31 +197,95 @@ where
     TInnerIndex: AssetIndex + Debug,
 {
     fn list_assets(&self, query: &AssetQuery) -> Result<Vec<AssetDescriptor>, ListAssetsError> {
-        if let Some(entry) = self.read_from_file().get(&Self::cache_key(query)) {
-            if SystemTime::now()
-                .duration_since(entry.last_modified)
-                .unwrap_or(self.max_age)
-                < self.max_age
-            {
-                return Ok(entry.descriptor.to_vec());
-            }
-        }
+        self.list_assets_with_freshness(query)
+            .map(|(descriptors, _)| descriptors)
+    }
+}
 
-        match self.inner_index.list_assets(query) {
-            Ok(results) => {
-                let mut cache_map = self.read_from_file();
-                cache_map.insert(
-                    Self::cache_key(query),
-                    CacheEntry {
-                        descriptor: results.to_vec(),
-                        query: query.clone(),
-                        last_modified: SystemTime::now(),
-                    },
-                );
-                self.save_to_file(cache_map);
-                Ok(results)
+#[cfg(test)]
+mod tests {
+    use super::JsonFileAssetIndexCache;
+    use crate::{AssetDescriptor, AssetIndex, AssetQuery, Freshness, ListAssetsError, SemVer};
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    /// Answers every query with a single descriptor until it's taken down or broken.
+    #[derive(Debug, Default)]
+    struct FlakyIndex {
+        down: AtomicBool,
+        broken: AtomicBool,
+    }
+
+    impl AssetIndex for FlakyIndex {
+        fn list_assets(&self, query: &AssetQuery) -> Result<Vec<AssetDescriptor>, ListAssetsError> {
+            if self.down.load(Ordering::SeqCst) {
+                return Err(ListAssetsError::AssetIndexUnavailable("down".to_owned()));
             }
-            Err(e) => Err(e),
+            if self.broken.load(Ordering::SeqCst) {
+                return Err(ListAssetsError::AssetIndexInternalError(
+                    "broken".to_owned(),
+                ));
+            }
+            Ok(vec![AssetDescriptor::new(
+                &query.name_constraint.to_string(),
+                SemVer::from_str("1.0.0").unwrap(),
+                "abc",
+                3,
+                vec![],
+            )])
         }
     }
+
+    fn query(name: &str) -> AssetQuery {
+        AssetQuery::new_from_strings(name, &None).unwrap()
+    }
+
+    #[test]
+    fn serves_expired_results_while_the_index_is_down() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("descriptors.json");
+        let cache = JsonFileAssetIndexCache::new(&path, Duration::ZERO, FlakyIndex::default())
+            .with_stale_if_error(Duration::from_secs(3600));
+        let (_, freshness) = cache.list_assets_with_freshness(&query("a")).unwrap();
+        assert_eq!(freshness, Freshness::Fresh);
+
+        cache.inner_index.down.store(true, Ordering::SeqCst);
+        let (descriptors, freshness) = cache.list_assets_with_freshness(&query("a")).unwrap();
+        assert_eq!(descriptors[0].name, "a");
+        assert!(matches!(freshness, Freshness::Stale(_)));
+        assert!(matches!(
+            cache.list_assets(&query("b")),
+            Err(ListAssetsError::AssetIndexUnavailable(_))
+        ));
+
+        // Errors other than outages go through.
+        cache.inner_index.down.store(false, Ordering::SeqCst);
+        cache.inner_index.broken.store(true, Ordering::SeqCst);
+        assert!(matches!(
+            cache.list_assets(&query("a")),
+            Err(ListAssetsError::AssetIndexInternalError(_))
+        ));
+
+        // Without a stale-if-error window, the error goes through.
+        let cache = JsonFileAssetIndexCache::new(&path, Duration::ZERO, FlakyIndex::default());
+        cache.inner_index.down.store(true, Ordering::SeqCst);
+        assert!(cache.list_assets(&query("a")).is_err());
+    }
+
+    #[test]
+    fn offline_cache_only_answers_from_the_cache() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("descriptors.json");
+        JsonFileAssetIndexCache::new(&path, Duration::ZERO, FlakyIndex::default())
+            .list_assets(&query("a"))
+            .unwrap();
+
+        // The index would answer, but an offline cache doesn't ask it.
+        let cache = JsonFileAssetIndexCache::new(&path, Duration::ZERO, FlakyIndex::default())
+            .with_offline(true);
+        let (_, freshness) = cache.list_assets_with_freshness(&query("a")).unwrap();
+        assert!(matches!(freshness, Freshness::Stale(_)));
+        assert!(cache.list_assets(&query("b")).is_err());
+    }
 }
@@ -1,5 +1,17 @@
+mod directory_asset_index;
+mod file_asset_store;
+pub(crate) mod file_lock;
+mod filesystem_asset_publisher;
 mod filesystem_asset_store_cache;
 mod json_asset_index_cache;
 
-pub use filesystem_asset_store_cache::FilesystemAssetStoreCache;
+pub use directory_asset_index::DirectoryAssetIndex;
+pub use file_asset_store::FileAssetStore;
+pub use filesystem_asset_publisher::FilesystemAssetPublisher;
+pub use filesystem_asset_store_cache::{
+    CacheLayout, CachedAsset, FilesystemAssetStoreCache, PruneReport,
+};
 pub use json_asset_index_cache::JsonFileAssetIndexCache;
+
+const ASSET_FILE_NAME: &str = "asset";
+const DESCRIPTOR_FILE_NAME: &str = "descriptor.json";
@@ -1,7 +1,9 @@
 use crate::{
-    http::AzureBlobAssetLocatorFactory, http::AzureBlobStorageDirectAccessLocatorFactory,
-    AssetDescriptor, AssetIndex, AssetQuery, ListAssetsError, SemVer,
+    format_content_hash, http::AzureBlobAssetLocatorFactory,
+    http::AzureBlobStorageDirectAccessLocatorFactory, http::HttpClient, AssetDescriptor,
+    AssetIndex, AssetQuery, AsyncAssetIndex, HashAlgorithm, ListAssetsError, SemVer,
 };
+use async_trait::async_trait;
 use quick_xml::de::from_str;
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
@@ -10,7 +12,26 @@ use std::str::FromStr;
 struct Metadata {
     name: String,
     version: String,
-    sha1: String,
+    sha512: Option<String>,
+    sha256: Option<String>,
+    sha1: Option<String>,
+}
+
+impl Metadata {
+    /// The strongest digest recorded on the blob, tagged with its algorithm.
+    fn content_hash(&self) -> Option<String> {
+        [
+            (HashAlgorithm::Sha512, &self.sha512),
+            (HashAlgorithm::Sha256, &self.sha256),
+            (HashAlgorithm::Sha1, &self.sha1),
+        ]
+        .into_iter()
+        .find_map(|(algorithm, digest)| {
+            digest
+                .as_deref()
+                .map(|digest| format_content_hash(algorithm, digest))
+        })
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -27,12 +48,9 @@ struct Blob {
     metadata: Option<Metadata>,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
-struct NextMarker {}
-
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 struct Blobs {
-    #[serde(rename = "$value")]
+    #[serde(rename = "$value", default)]
     blobs: Vec<Blob>,
 }
 
@@ -42,10 +60,16 @@ struct EnumerationResults {
     service_endpoint: String,
     container_name: String,
     blobs: Blobs,
-    next_marker: NextMarker,
+    #[serde(default)]
+    next_marker: Option<String>,
 }
 
 impl EnumerationResults {
+    /// The marker to pass to the next List Blobs call, if the listing isn't complete.
+    fn continuation(&self) -> Option<&str> {
+        self.next_marker.as_deref().filter(|m| !m.trim().is_empty())
+    }
+
     pub fn evaluate_query(
         &self,
         query: &AssetQuery,
@@ -53,7 +77,11 @@ impl EnumerationResults {
     ) -> Vec<AssetDescriptor> {
         let mut results: Vec<AssetDescriptor> = vec![];
         for b in self.blobs.blobs.iter() {
-            if let Some(m) = &b.metadata {
+            if let Some((m, content_hash)) = b
+                .metadata
+                .as_ref()
+                .and_then(|m| m.content_hash().map(|hash| (m, hash)))
+            {
                 let mut locators = vec![];
                 if let Ok(l) = locator_factory.get_locator(
                     &self.service_endpoint,
@@ -65,7 +93,7 @@ impl EnumerationResults {
                 let ad = AssetDescriptor::new(
                     &m.name,
                     SemVer::from_str(&m.version).unwrap_or_default(),
-                    &m.sha1,
+                    &content_hash,
                     b.properties.content_length,
                     locators,
                 );
@@ -90,6 +118,9 @@ impl From<Error> for ListAssetsError {
         match e.code.as_str() {
             "AuthenticationFailed" => ListAssetsError::AssetIndexAccessDenied(Some(e.message)),
             "InvalidQueryParameterValue" => ListAssetsError::AssetIndexInternalError(e.message),
+            "InternalError" | "ServerBusy" | "OperationTimedOut" => {
+                ListAssetsError::AssetIndexUnavailable(e.message)
+            }
             _ => ListAssetsError::AssetIndexInternalError(format!(
                 "Storage error '{}'. Details: {}",
                 e.code, e.message
@@ -109,8 +140,15 @@ pub struct AzureBlobAssetIndex {
     container_name: String,
     sas: String,
     locator_factory: AzureBlobStorageDirectAccessLocatorFactory,
+    page_size: Option<u32>,
+    max_pages: usize,
+    prefix_filtering: bool,
+    http_client: HttpClient,
 }
 
+/// Guards against listing an unexpectedly large container forever.
+const DEFAULT_MAX_PAGES: usize = 100;
+
 impl AzureBlobAssetIndex {
     pub fn new(storage_account_name: &str, container_name: &str, sas: &str) -> Self {
         AzureBlobAssetIndex {
@@ -120,30 +158,159 @@ impl AzureBlobAssetIndex {
             locator_factory: AzureBlobStorageDirectAccessLocatorFactory {
                 sas_token: sas.to_owned(),
             },
+            page_size: None,
+            max_pages: DEFAULT_MAX_PAGES,
+            prefix_filtering: true,
+            http_client: HttpClient::default(),
+        }
+    }
+
+    pub fn with_http_client(mut self, http_client: HttpClient) -> Self {
+        self.http_client = http_client;
+        self
+    }
+
+    /// Sets the number of blobs requested per List Blobs call. The service default is 5000.
+    pub fn with_page_size(mut self, page_size: u32) -> Self {
+        self.page_size = Some(page_size);
+        self
+    }
+
+    /// Sets the maximum number of List Blobs calls made for a single query. Listings that
+    /// need more pages fail rather than returning truncated results.
+    pub fn with_max_pages(mut self, max_pages: usize) -> Self {
+        self.max_pages = max_pages;
+        self
+    }
+
+    /// Enables or disables server side prefix filtering. Prefix filtering relies on blobs
+    /// being laid out as `name/version/...` and is enabled by default.
+    pub fn with_prefix_filtering(mut self, prefix_filtering: bool) -> Self {
+        self.prefix_filtering = prefix_filtering;
+        self
+    }
+
+    /// The List Blobs `prefix` that narrows the listing to blobs that could match the
+    /// query, given the `name/version/...` blob layout.
+    fn blob_prefix(&self, query: &AssetQuery) -> Option<String> {
+        if !self.prefix_filtering {
+            return None;
+        }
+        query.layout_prefix()
+    }
+
+    fn list_blobs_url(
+        &self,
+        prefix: Option<&str>,
+        marker: Option<&str>,
+    ) -> Result<reqwest::Url, ListAssetsError> {
+        let mut url = reqwest::Url::parse(&format!(
+            "https://{}.blob.core.windows.net/{}?restype=container&comp=list&include=metadata&{}",
+            &self.storage_account_name, &self.container_name, &self.sas
+        ))
+        .map_err(|e| ListAssetsError::MisconfiguredIndex(e.to_string()))?;
+        if let Some(page_size) = self.page_size {
+            url.query_pairs_mut()
+                .append_pair("maxresults", &page_size.to_string());
+        }
+        if let Some(prefix) = prefix {
+            url.query_pairs_mut().append_pair("prefix", prefix);
+        }
+        if let Some(marker) = marker {
+            url.query_pairs_mut().append_pair("marker", marker);
+        }
+        Ok(url)
+    }
+
+    /// Follows continuation markers until the listing is exhausted, evaluating the query
+    /// against each page.
+    fn collect_pages<F>(
+        &self,
+        query: &AssetQuery,
+        mut fetch_page: F,
+    ) -> Result<Vec<AssetDescriptor>, ListAssetsError>
+    where
+        F: FnMut(Option<&str>) -> Result<ListBlobResponse, ListAssetsError>,
+    {
+        let mut descriptors = vec![];
+        let mut marker: Option<String> = None;
+        for _ in 0..self.max_pages {
+            marker = self.evaluate_page(query, fetch_page(marker.as_deref())?, &mut descriptors)?;
+            if marker.is_none() {
+                return Ok(descriptors);
+            }
         }
+        Err(self.too_many_pages())
     }
 
-    fn make_request(url: String) -> Result<ListBlobResponse, crate::ListAssetsError> {
-        if let Ok(response) = reqwest::blocking::get(url) {
-            if let Ok(response_text) = response.text() {
-                from_str::<ListBlobResponse>(response_text.trim_start_matches(|c| c != '<'))
-                    .map_err(|parse_error| {
-                        ListAssetsError::AssetIndexInternalError(format!(
-                            "Failed to parse reponse. message: {} content: {}",
-                            parse_error, &response_text
-                        ))
-                    })
-            } else {
-                Err(ListAssetsError::AssetIndexInternalError(
-                    "Storage response was empty.".to_owned(),
-                ))
+    /// Adds the page's matches to `descriptors` and returns the marker of the next page, if
+    /// there is one.
+    fn evaluate_page(
+        &self,
+        query: &AssetQuery,
+        page: ListBlobResponse,
+        descriptors: &mut Vec<AssetDescriptor>,
+    ) -> Result<Option<String>, ListAssetsError> {
+        match page {
+            ListBlobResponse::EnumerationResults(results) => {
+                descriptors.extend(results.evaluate_query(query, &self.locator_factory));
+                Ok(results.continuation().map(|marker| marker.to_owned()))
             }
-        } else {
-            Err(ListAssetsError::AssetIndexInternalError(
-                "No response from storage.".to_owned(),
-            ))
+            ListBlobResponse::Error(e) => Err(e.into()),
         }
     }
+
+    fn too_many_pages(&self) -> ListAssetsError {
+        ListAssetsError::AssetIndexInternalError(format!(
+            "The listing didn't complete within {} pages.",
+            self.max_pages
+        ))
+    }
+
+    fn make_request(&self, url: reqwest::Url) -> Result<ListBlobResponse, crate::ListAssetsError> {
+        let response = self
+            .http_client
+            .blocking()
+            .map_err(client_error)?
+            .get(url)
+            .send()
+            .map_err(|_| no_response())?;
+        let response_text = response.text().map_err(|_| empty_response())?;
+        parse_response(&response_text)
+    }
+
+    async fn make_request_async(
+        &self,
+        url: reqwest::Url,
+    ) -> Result<ListBlobResponse, ListAssetsError> {
+        let client = self.http_client.client().map_err(client_error)?;
+        let response = client.get(url).send().await.map_err(|_| no_response())?;
+        let response_text = response.text().await.map_err(|_| empty_response())?;
+        parse_response(&response_text)
+    }
+}
+
+fn parse_response(response_text: &str) -> Result<ListBlobResponse, ListAssetsError> {
+    from_str::<ListBlobResponse>(response_text.trim_start_matches(|c| c != '<')).map_err(
+        |parse_error| {
+            ListAssetsError::AssetIndexInternalError(format!(
+                "Failed to parse reponse. message: {} content: {}",
+                parse_error, response_text
+            ))
+        },
+    )
+}
+
+fn no_response() -> ListAssetsError {
+    ListAssetsError::AssetIndexUnavailable("No response from storage.".to_owned())
+}
+
+fn client_error(e: reqwest::Error) -> ListAssetsError {
+    ListAssetsError::MisconfiguredIndex(e.to_string())
+}
+
+fn empty_response() -> ListAssetsError {
+    ListAssetsError::AssetIndexInternalError("Storage response was empty.".to_owned())
 }
 
 impl AssetIndex for AzureBlobAssetIndex {
@@ -151,27 +318,165 @@ impl AssetIndex for AzureBlobAssetIndex {
         &self,
         query: &crate::AssetQuery,
     ) -> Result<Vec<crate::AssetDescriptor>, crate::ListAssetsError> {
-        let url = format!(
-            "https://{}.blob.core.windows.net/{}?restype=container&comp=list&include=metadata&{}",
-            &self.storage_account_name, &self.container_name, &self.sas
-        );
-        match Self::make_request(url) {
-            Ok(ListBlobResponse::EnumerationResults(results)) => {
-                Ok(results.evaluate_query(query, &self.locator_factory))
+        let prefix = self.blob_prefix(query);
+        self.collect_pages(query, |marker| {
+            self.make_request(self.list_blobs_url(prefix.as_deref(), marker)?)
+        })
+    }
+}
+
+#[async_trait]
+impl AsyncAssetIndex for AzureBlobAssetIndex {
+    async fn list_assets(
+        &self,
+        query: &AssetQuery,
+    ) -> Result<Vec<AssetDescriptor>, ListAssetsError> {
+        let prefix = self.blob_prefix(query);
+        let mut descriptors = vec![];
+        let mut marker: Option<String> = None;
+        for _ in 0..self.max_pages {
+            let url = self.list_blobs_url(prefix.as_deref(), marker.as_deref())?;
+            let page = self.make_request_async(url).await?;
+            marker = self.evaluate_page(query, page, &mut descriptors)?;
+            if marker.is_none() {
+                return Ok(descriptors);
             }
-            Ok(ListBlobResponse::Error(e)) => Err(e.into()),
-            Err(e) => Err(e),
         }
+        Err(self.too_many_pages())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{http::AzureBlobStorageDirectAccessLocatorFactory, AssetQuery};
+    use crate::{http::AzureBlobStorageDirectAccessLocatorFactory, AssetQuery, ListAssetsError};
 
-    use super::ListBlobResponse;
+    use super::{AzureBlobAssetIndex, ListBlobResponse};
     use quick_xml::de::from_str;
 
+    const PAGE_1: &str = include_str!("test_data/list_blobs_page_1.xml");
+    const PAGE_2: &str = include_str!("test_data/list_blobs_page_2.xml");
+    const PAGE_3: &str = include_str!("test_data/list_blobs_page_3.xml");
+
+    fn recorded_page(marker: Option<&str>) -> Result<ListBlobResponse, ListAssetsError> {
+        let page = match marker {
+            None => PAGE_1,
+            Some("2!96!MDAwMDE5IXNpbXBsZV90ZXN0LzIuMC4wL2Fzc2V0ITAwMDAyOCE5OTk5LTEyLTMxVDIzOjU5OjU5Ljk5OTk5OTlaIQ--") => PAGE_2,
+            Some("2!96!MDAwMDE5IXNpbXBsZV90ZXN0LzMuMC4wL2Fzc2V0ITAwMDAyOCE5OTk5LTEyLTMxVDIzOjU5OjU5Ljk5OTk5OTlaIQ--") => PAGE_3,
+            Some(m) => panic!("Unexpected marker {m}"),
+        };
+        Ok(from_str::<ListBlobResponse>(page).unwrap())
+    }
+
+    #[test]
+    fn follows_next_marker() {
+        let index = AzureBlobAssetIndex::new("ioratest", "assets", "sas=tok").with_page_size(2);
+        let query = AssetQuery::new_from_strings("simple_test", &None).unwrap();
+        let mut markers = vec![];
+        let descriptors = index
+            .collect_pages(&query, |marker| {
+                markers.push(marker.map(|m| m.to_owned()));
+                recorded_page(marker)
+            })
+            .unwrap();
+        assert_eq!(markers.len(), 3);
+        let versions: Vec<_> = descriptors.iter().map(|d| d.version.to_string()).collect();
+        assert_eq!(versions, vec!["1.0.0", "2.0.0", "3.0.0", "3.1.0"]);
+    }
+
+    #[test]
+    fn page_limit_is_enforced() {
+        let index = AzureBlobAssetIndex::new("ioratest", "assets", "sas=tok").with_max_pages(2);
+        let query = AssetQuery::new_from_strings("simple_test", &None).unwrap();
+        assert!(matches!(
+            index.collect_pages(&query, recorded_page),
+            Err(ListAssetsError::AssetIndexInternalError(_))
+        ));
+    }
+
+    #[test]
+    fn list_blobs_url_includes_paging_parameters() {
+        let index =
+            AzureBlobAssetIndex::new("ioratest", "assets", "sv=2021&sig=abc%2B").with_page_size(2);
+        assert_eq!(
+            index.list_blobs_url(None, Some("2!96!MDA+/=")).unwrap().as_str(),
+            "https://ioratest.blob.core.windows.net/assets?restype=container&comp=list&include=metadata&sv=2021&sig=abc%2B&maxresults=2&marker=2%2196%21MDA%2B%2F%3D"
+        );
+        assert_eq!(
+            index.list_blobs_url(Some("simple test/1.0.0/"), None).unwrap().as_str(),
+            "https://ioratest.blob.core.windows.net/assets?restype=container&comp=list&include=metadata&sv=2021&sig=abc%2B&maxresults=2&prefix=simple+test%2F1.0.0%2F"
+        );
+    }
+
+    #[test]
+    fn content_hash_uses_strongest_digest() {
+        let response = r#"
+        <?xml version="1.0" encoding="utf-8"?>
+        <EnumerationResults ServiceEndpoint="https://ioratest.blob.core.windows.net/" ContainerName="assets">
+            <Blobs>
+                <Blob>
+                    <Name>hashed/1.0.0/asset</Name>
+                    <Properties><Content-Length>5</Content-Length></Properties>
+                    <Metadata>
+                        <version>1.0.0</version>
+                        <sha1>aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d</sha1>
+                        <sha256>2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824</sha256>
+                        <name>hashed</name>
+                    </Metadata>
+                </Blob>
+                <Blob>
+                    <Name>hashed/2.0.0/asset</Name>
+                    <Properties><Content-Length>5</Content-Length></Properties>
+                    <Metadata>
+                        <version>2.0.0</version>
+                        <name>hashed</name>
+                    </Metadata>
+                </Blob>
+            </Blobs>
+            <NextMarker />
+        </EnumerationResults>"#;
+        if let ListBlobResponse::EnumerationResults(results) =
+            from_str::<ListBlobResponse>(response).unwrap()
+        {
+            let query = AssetQuery::new_from_strings("hashed", &None).unwrap();
+            let locator_factory = AzureBlobStorageDirectAccessLocatorFactory {
+                sas_token: "sas=tok".to_owned(),
+            };
+            let descriptors = results.evaluate_query(&query, &locator_factory);
+            assert_eq!(descriptors.len(), 1);
+            assert_eq!(
+                descriptors[0].content_hash,
+                "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+            );
+        } else {
+            panic!("Unexpected parse result");
+        }
+    }
+
+    #[test]
+    fn blob_prefix_from_query() {
+        let index = AzureBlobAssetIndex::new("ioratest", "assets", "sas=tok");
+        let table = [
+            ("simple_test", None, Some("simple_test/")),
+            ("simple_test", Some("1.0.0"), Some("simple_test/1.0.0/")),
+            ("simple_test", Some("1"), Some("simple_test/1.")),
+            ("simple_test", Some("1.2"), Some("simple_test/1.2.")),
+            ("simple_test", Some("^1.2"), Some("simple_test/")),
+            ("simple*", Some("1.0.0"), Some("simple")),
+            ("*simple*", None, None),
+        ];
+        for (name, version, expected) in table {
+            let query = AssetQuery::new_from_strings(name, &version.map(|v| v.to_owned())).unwrap();
+            assert_eq!(
+                index.blob_prefix(&query).as_deref(),
+                expected,
+                "{name} {version:?}"
+            );
+        }
+
+        let query = AssetQuery::new_from_strings("simple_test", &None).unwrap();
+        assert_eq!(index.with_prefix_filtering(false).blob_prefix(&query), None);
+    }
+
     #[test]
     fn parse_list_blobs_response() {
         let response = r#"
@@ -225,7 +530,10 @@ mod tests {
             assert_eq!(ad.version.patch, 0);
             assert!(ad.version.prerelease.is_none());
             assert!(ad.version.buildmetadata.is_none());
-            assert_eq!(ad.content_hash, "a5dc94e2414b5445ddb4658b047166751f364f4a");
+            assert_eq!(
+                ad.content_hash,
+                "sha1:a5dc94e2414b5445ddb4658b047166751f364f4a"
+            );
             assert_eq!(ad.size, 266);
             assert_eq!(ad.locators.len(), 1);
             assert_eq!(
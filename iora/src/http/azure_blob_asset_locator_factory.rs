@@ -31,9 +31,7 @@ impl AzureBlobAssetLocatorFactory for AzureBlobStorageDirectAccessLocatorFactory
             service_endpoint, container_name, blob_name, self.sas_token
         );
         match Url::from_str(&url) {
-            Ok(u) => Ok(AssetLocator {
-                url: u,
-            }),
+            Ok(u) => Ok(AssetLocator::new(u)),
             Err(e) => Err(AzureBlobAssetLocatorFactoryError::FailedToConstructUrl(
                 e.to_string(),
             )),
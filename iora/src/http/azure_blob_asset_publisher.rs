@@ -0,0 +1,461 @@
+use crate::http::{
+    AzureBlobAssetLocatorFactory, AzureBlobStorageDirectAccessLocatorFactory, HttpClient,
+};
+use crate::{
+    parse_content_hash, redact_url, validate_asset_name, AssetDescriptor, AssetPayload,
+    AssetPublisher, ContentHasher, HashAlgorithm, PublishAssetError, SemVer,
+};
+use quick_xml::de::from_str;
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::{StatusCode, Url};
+use serde::Deserialize;
+use std::io::{ErrorKind, Read};
+
+/// Payloads up to this size are uploaded with a single Put Blob request. Larger ones are
+/// uploaded as blocks of this size, so that at most one block is held in memory. With at most
+/// 50,000 blocks per blob, this allows for assets of up to 390 GiB.
+const DEFAULT_BLOCK_SIZE: usize = 8 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Error {
+    code: String,
+    message: String,
+}
+
+/// Publishes assets as block blobs named `name/version/asset`. The blob metadata holds
+/// the name, version and SHA-256 hash, which is what `AzureBlobAssetIndex` lists.
+///
+/// Large assets are staged with Put Block as they are read, and committed together with
+/// their metadata by Put Block List once the hash is known. The blob only appears once it
+/// has been committed; blocks left uncommitted by a failed upload are discarded by the
+/// service after a week.
+pub struct AzureBlobAssetPublisher {
+    service_endpoint: String,
+    container_name: String,
+    sas: String,
+    locator_factory: AzureBlobStorageDirectAccessLocatorFactory,
+    http_client: HttpClient,
+    block_size: usize,
+}
+
+impl AzureBlobAssetPublisher {
+    pub fn new(storage_account_name: &str, container_name: &str, sas: &str) -> Self {
+        AzureBlobAssetPublisher {
+            service_endpoint: format!("https://{storage_account_name}.blob.core.windows.net/"),
+            container_name: container_name.to_owned(),
+            sas: sas.to_owned(),
+            locator_factory: AzureBlobStorageDirectAccessLocatorFactory {
+                sas_token: sas.to_owned(),
+            },
+            http_client: HttpClient::default(),
+            block_size: DEFAULT_BLOCK_SIZE,
+        }
+    }
+
+    pub fn with_http_client(mut self, http_client: HttpClient) -> Self {
+        self.http_client = http_client;
+        self
+    }
+
+    /// Publishes to another endpoint than the storage account's, such as the Azurite
+    /// emulator's `http://127.0.0.1:10000/devstoreaccount1/`.
+    pub fn with_service_endpoint(mut self, service_endpoint: &str) -> Self {
+        self.service_endpoint = format!("{}/", service_endpoint.trim_end_matches('/'));
+        self
+    }
+
+    /// Sets the size above which assets are uploaded in blocks, and the size of the blocks.
+    /// A blob holds at most 50,000 blocks of at most 4000 MiB.
+    pub fn with_block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size.max(1);
+        self
+    }
+
+    fn blob_name(name: &str, version: &SemVer) -> String {
+        format!("{}/{}/asset", name, version)
+    }
+
+    fn blob_url(&self, name: &str, version: &SemVer) -> Result<Url, PublishAssetError> {
+        let mut url = Url::parse(&format!(
+            "{}{}?{}",
+            self.service_endpoint, self.container_name, self.sas
+        ))
+        .map_err(|e| PublishAssetError::MisconfiguredPublisher(e.to_string()))?;
+        url.path_segments_mut()
+            .map_err(|_| {
+                PublishAssetError::MisconfiguredPublisher("Invalid storage URL.".to_owned())
+            })?
+            .extend(Self::blob_name(name, version).split('/'));
+        Ok(url)
+    }
+
+    /// Adds the metadata the index lists, and makes the request fail with 409
+    /// BlobAlreadyExists rather than overwrite a published version.
+    fn with_metadata(
+        request: RequestBuilder,
+        name: &str,
+        version: &SemVer,
+        content_hash: &str,
+    ) -> Result<RequestBuilder, PublishAssetError> {
+        let (_, digest) = parse_content_hash(content_hash)
+            .map_err(|e| PublishAssetError::PublisherInternalError(e.to_string()))?;
+        Ok(request
+            .header("If-None-Match", "*")
+            .header("x-ms-meta-name", name)
+            .header("x-ms-meta-version", version.to_string())
+            .header("x-ms-meta-sha256", digest))
+    }
+
+    fn put_blob_request(
+        &self,
+        client: &Client,
+        name: &str,
+        version: &SemVer,
+        content_hash: &str,
+    ) -> Result<RequestBuilder, PublishAssetError> {
+        let request = client
+            .put(self.blob_url(name, version)?)
+            .header("x-ms-blob-type", "BlockBlob")
+            .header("Content-Type", "application/octet-stream");
+        Self::with_metadata(request, name, version, content_hash)
+    }
+
+    fn put_block_request(
+        &self,
+        client: &Client,
+        name: &str,
+        version: &SemVer,
+        block_id: &str,
+    ) -> Result<RequestBuilder, PublishAssetError> {
+        let mut url = self.blob_url(name, version)?;
+        url.query_pairs_mut()
+            .append_pair("comp", "block")
+            .append_pair("blockid", block_id);
+        Ok(client.put(url))
+    }
+
+    fn put_block_list_request(
+        &self,
+        client: &Client,
+        name: &str,
+        version: &SemVer,
+        content_hash: &str,
+        block_ids: &[String],
+    ) -> Result<RequestBuilder, PublishAssetError> {
+        let mut url = self.blob_url(name, version)?;
+        url.query_pairs_mut().append_pair("comp", "blocklist");
+        let mut body = r#"<?xml version="1.0" encoding="utf-8"?><BlockList>"#.to_owned();
+        for block_id in block_ids {
+            body.push_str(&format!("<Latest>{block_id}</Latest>"));
+        }
+        body.push_str("</BlockList>");
+        let request = client
+            .put(url)
+            .header("x-ms-blob-content-type", "application/octet-stream")
+            .body(body);
+        Self::with_metadata(request, name, version, content_hash)
+    }
+
+    /// Block IDs must all have the same length, so the index is zero-padded.
+    fn block_id(index: usize) -> String {
+        base64::encode(format!("{index:08}"))
+    }
+
+    fn send(
+        request: RequestBuilder,
+        name: &str,
+        version: &SemVer,
+    ) -> Result<(), PublishAssetError> {
+        // The URL carries the SAS token, which mustn't end up in the error.
+        let response = request.send().map_err(|mut e| {
+            if let Some(url) = e.url_mut() {
+                *url = redact_url(url);
+            }
+            PublishAssetError::PublisherInternalError(e.to_string())
+        })?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().unwrap_or_default();
+            return Err(Self::map_error_response(name, version, status, &body));
+        }
+        Ok(())
+    }
+
+    fn map_error_response(
+        name: &str,
+        version: &SemVer,
+        status: StatusCode,
+        body: &str,
+    ) -> PublishAssetError {
+        let error = from_str::<Error>(body.trim_start_matches(|c| c != '<')).ok();
+        match (status, error) {
+            (StatusCode::CONFLICT, Some(e)) if e.code == "BlobAlreadyExists" => {
+                PublishAssetError::AssetAlreadyExists {
+                    name: name.to_owned(),
+                    version: version.to_string(),
+                }
+            }
+            (StatusCode::PRECONDITION_FAILED, _) => PublishAssetError::AssetAlreadyExists {
+                name: name.to_owned(),
+                version: version.to_string(),
+            },
+            (StatusCode::FORBIDDEN, e) => PublishAssetError::AccessDenied(e.map(|e| e.message)),
+            (_, Some(e)) => PublishAssetError::PublisherInternalError(format!(
+                "Storage error '{}'. Details: {}",
+                e.code, e.message
+            )),
+            (_, None) => {
+                PublishAssetError::PublisherInternalError(format!("Storage returned {}.", status))
+            }
+        }
+    }
+}
+
+impl AssetPublisher for AzureBlobAssetPublisher {
+    fn publish(
+        &self,
+        name: &str,
+        version: &SemVer,
+        payload: AssetPayload,
+    ) -> Result<AssetDescriptor, PublishAssetError> {
+        validate_asset_name(name)?;
+        let client = self
+            .http_client
+            .blocking()
+            .map_err(|e| PublishAssetError::PublisherInternalError(e.to_string()))?;
+
+        // The hash is sent as metadata, so it has to be known by the time the blob is
+        // committed. A payload that fits a single block is uploaded in one request.
+        let mut reader = payload.into_reader();
+        let mut hasher = ContentHasher::new(HashAlgorithm::Sha256);
+        let mut block = read_block(&mut reader, self.block_size)?;
+        let mut size = block.len();
+        hasher.update(&block);
+        let content_hash = if block.len() < self.block_size {
+            let content_hash = hasher.finalize();
+            let request = self.put_blob_request(client, name, version, &content_hash)?;
+            Self::send(request.body(block), name, version)?;
+            content_hash
+        } else {
+            let mut block_ids = vec![];
+            while !block.is_empty() {
+                let block_id = Self::block_id(block_ids.len());
+                let request = self.put_block_request(client, name, version, &block_id)?;
+                Self::send(request.body(block), name, version)?;
+                block_ids.push(block_id);
+                block = read_block(&mut reader, self.block_size)?;
+                size += block.len();
+                hasher.update(&block);
+            }
+            let content_hash = hasher.finalize();
+            let request =
+                self.put_block_list_request(client, name, version, &content_hash, &block_ids)?;
+            Self::send(request, name, version)?;
+            content_hash
+        };
+
+        let locators = self
+            .locator_factory
+            .get_locator(
+                &self.service_endpoint,
+                &self.container_name,
+                &Self::blob_name(name, version),
+            )
+            .into_iter()
+            .collect();
+        Ok(AssetDescriptor::new(
+            name,
+            version.clone(),
+            &content_hash,
+            size,
+            locators,
+        ))
+    }
+}
+
+/// Reads up to `block_size` bytes, fewer only at the end of the payload.
+fn read_block(reader: &mut impl Read, block_size: usize) -> Result<Vec<u8>, PublishAssetError> {
+    let mut block = vec![0u8; block_size];
+    let mut filled = 0;
+    while filled < block_size {
+        match reader.read(&mut block[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(PublishAssetError::PayloadError(e.into())),
+        }
+    }
+    block.truncate(filled);
+    Ok(block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AzureBlobAssetPublisher;
+    use crate::http::test_server::{TestRequest, TestResponse, TestServer};
+    use crate::{AssetPayload, AssetPublisher, PublishAssetError, SemVer};
+    use reqwest::StatusCode;
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+
+    const HELLO_WORLD_SHA256: &str =
+        "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+    /// Publishes the content to a server that accepts every request, and returns the
+    /// requests it received.
+    fn publish(content: &str, block_size: usize) -> Vec<TestRequest> {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let received = requests.clone();
+        let server = TestServer::start(move |request| {
+            received.lock().unwrap().push(request.clone());
+            TestResponse::new(201, "")
+        });
+        let descriptor = AzureBlobAssetPublisher::new("ioratest", "assets", "sv=2021&sig=abc")
+            .with_service_endpoint(server.url("/ioratest").as_str())
+            .with_block_size(block_size)
+            .publish(
+                "hello",
+                &SemVer::from_str("1.0.0").unwrap(),
+                AssetPayload::Bytes(content.as_bytes().to_vec()),
+            )
+            .unwrap();
+        assert_eq!(descriptor.size, content.len());
+        let requests = requests.lock().unwrap().clone();
+        requests
+    }
+
+    #[test]
+    fn keeps_the_sas_token_out_of_errors() {
+        let unreachable = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+        let error = AzureBlobAssetPublisher::new("ioratest", "assets", "sv=2021&sig=secret")
+            .with_service_endpoint(&format!("http://{unreachable}/ioratest"))
+            .publish(
+                "hello",
+                &SemVer::from_str("1.0.0").unwrap(),
+                AssetPayload::Bytes(b"hello world".to_vec()),
+            )
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            PublishAssetError::PublisherInternalError(_)
+        ));
+        assert!(!error.to_string().contains("secret"), "{error}");
+    }
+
+    #[test]
+    fn put_blob_request() {
+        let publisher = AzureBlobAssetPublisher::new("ioratest", "assets", "sv=2021&sig=abc");
+        let request = publisher
+            .put_blob_request(
+                &reqwest::blocking::Client::new(),
+                "simple test",
+                &SemVer::from_str("1.0.0-beta").unwrap(),
+                "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+            )
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(request.method(), "PUT");
+        assert_eq!(
+            request.url().as_str(),
+            "https://ioratest.blob.core.windows.net/assets/simple%20test/1.0.0-beta/asset?sv=2021&sig=abc"
+        );
+        let headers = request.headers();
+        assert_eq!(headers["x-ms-blob-type"], "BlockBlob");
+        assert_eq!(headers["If-None-Match"], "*");
+        assert_eq!(headers["x-ms-meta-name"], "simple test");
+        assert_eq!(headers["x-ms-meta-version"], "1.0.0-beta");
+        assert_eq!(
+            headers["x-ms-meta-sha256"],
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+    }
+
+    #[test]
+    fn uploads_small_assets_in_one_request() {
+        let requests = publish("hello world", 16);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "PUT");
+        assert_eq!(
+            requests[0].target,
+            "/ioratest/assets/hello/1.0.0/asset?sv=2021&sig=abc"
+        );
+        assert_eq!(requests[0].header("x-ms-blob-type"), Some("BlockBlob"));
+        assert_eq!(
+            requests[0].header("x-ms-meta-sha256"),
+            Some(HELLO_WORLD_SHA256)
+        );
+        assert_eq!(requests[0].body, b"hello world");
+    }
+
+    #[test]
+    fn uploads_large_assets_in_blocks() {
+        let requests = publish("hello world", 4);
+        let block_ids: Vec<String> = (0..3).map(AzureBlobAssetPublisher::block_id).collect();
+        let targets: Vec<&str> = requests.iter().map(|r| r.target.as_str()).collect();
+        let blob = "/ioratest/assets/hello/1.0.0/asset?sv=2021&sig=abc";
+        assert_eq!(
+            targets,
+            [
+                format!("{blob}&comp=block&blockid=MDAwMDAwMDA%3D"),
+                format!("{blob}&comp=block&blockid=MDAwMDAwMDE%3D"),
+                format!("{blob}&comp=block&blockid=MDAwMDAwMDI%3D"),
+                format!("{blob}&comp=blocklist"),
+            ]
+        );
+        let blocks: Vec<&[u8]> = requests[..3].iter().map(|r| r.body.as_slice()).collect();
+        assert_eq!(blocks, [&b"hell"[..], b"o wo", b"rld"]);
+        assert!(requests[..3]
+            .iter()
+            .all(|r| r.header("x-ms-meta-sha256").is_none()));
+
+        let commit = &requests[3];
+        assert_eq!(commit.header("If-None-Match"), Some("*"));
+        assert_eq!(commit.header("x-ms-meta-name"), Some("hello"));
+        assert_eq!(commit.header("x-ms-meta-sha256"), Some(HELLO_WORLD_SHA256));
+        let body = String::from_utf8(commit.body.clone()).unwrap();
+        let listed: Vec<String> = block_ids
+            .iter()
+            .map(|id| format!("<Latest>{id}</Latest>"))
+            .collect();
+        assert!(body.contains(&format!("<BlockList>{}</BlockList>", listed.concat())));
+    }
+
+    #[test]
+    fn error_responses() {
+        let version = SemVer::from_str("1.0.0").unwrap();
+        let conflict = r#"<?xml version="1.0" encoding="utf-8"?><Error><Code>BlobAlreadyExists</Code><Message>The specified blob already exists.</Message></Error>"#;
+        assert!(matches!(
+            AzureBlobAssetPublisher::map_error_response(
+                "a",
+                &version,
+                StatusCode::CONFLICT,
+                conflict
+            ),
+            PublishAssetError::AssetAlreadyExists { .. }
+        ));
+        let denied = r#"<?xml version="1.0" encoding="utf-8"?><Error><Code>AuthenticationFailed</Code><Message>Signature did not match.</Message></Error>"#;
+        assert!(matches!(
+            AzureBlobAssetPublisher::map_error_response(
+                "a",
+                &version,
+                StatusCode::FORBIDDEN,
+                denied
+            ),
+            PublishAssetError::AccessDenied(Some(_))
+        ));
+        assert!(matches!(
+            AzureBlobAssetPublisher::map_error_response(
+                "a",
+                &version,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ""
+            ),
+            PublishAssetError::PublisherInternalError(_)
+        ));
+    }
+}
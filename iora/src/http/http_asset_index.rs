@@ -1,8 +1,14 @@
-use crate::{AssetDescriptor, AssetIndex, AssetQuery, ListAssetsError};
+use crate::http::{HttpAuth, HttpClient};
+use crate::{AssetDescriptor, AssetIndex, AssetQuery, AsyncAssetIndex, ListAssetsError};
+use async_trait::async_trait;
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
 
 #[derive(Debug)]
 pub struct HttpAssetIndex {
     target_host: String,
+    http_client: HttpClient,
+    auth: Option<HttpAuth>,
 }
 
 impl HttpAssetIndex {
@@ -15,35 +21,181 @@ impl HttpAssetIndex {
             } else {
                 "https://".to_owned() + target_host
             },
+            http_client: HttpClient::default(),
+            auth: None,
         }
     }
+
+    pub fn with_http_client(mut self, http_client: HttpClient) -> Self {
+        self.http_client = http_client;
+        self
+    }
+
+    /// Authenticates every request to the index.
+    pub fn with_auth(mut self, auth: HttpAuth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    fn auth_headers(&self) -> Result<HeaderMap, ListAssetsError> {
+        match &self.auth {
+            Some(auth) => auth
+                .headers()
+                .map_err(|e| ListAssetsError::AssetIndexAccessDenied(Some(e.to_string()))),
+            None => Ok(HeaderMap::new()),
+        }
+    }
+
+    fn query_url(&self, query: &AssetQuery) -> Result<reqwest::Url, ListAssetsError> {
+        let mut params = vec![("name", query.name_constraint.to_string())];
+        if let Some(vc) = &query.version_constraint {
+            params.push(("version", vc.to_string()));
+        }
+        reqwest::Url::parse_with_params(&format!("{}/assets", self.target_host), params)
+            .map_err(|e| ListAssetsError::MisconfiguredIndex(e.to_string()))
+    }
+}
+
+fn parse_error(json_error: reqwest::Error) -> ListAssetsError {
+    ListAssetsError::AssetIndexInternalError(format!("Faield to parse response: {}", json_error))
+}
+
+fn request_error(request_error: reqwest::Error) -> ListAssetsError {
+    let message = format!("Service request failed: {}", request_error);
+    if request_error.is_timeout() || request_error.is_connect() {
+        ListAssetsError::AssetIndexUnavailable(message)
+    } else {
+        ListAssetsError::AssetIndexInternalError(message)
+    }
+}
+
+fn check_status(status: StatusCode) -> Result<(), ListAssetsError> {
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            Err(ListAssetsError::AssetIndexAccessDenied(Some(format!(
+                "The service answered {status}."
+            ))))
+        }
+        status if status.is_server_error() => Err(ListAssetsError::AssetIndexUnavailable(format!(
+            "Service request failed with {status}."
+        ))),
+        status if !status.is_success() => Err(ListAssetsError::AssetIndexInternalError(format!(
+            "Service request failed with {status}."
+        ))),
+        _ => Ok(()),
+    }
 }
 
 impl AssetIndex for HttpAssetIndex {
     fn list_assets(&self, query: &AssetQuery) -> Result<Vec<AssetDescriptor>, ListAssetsError> {
-        let url = format!(
-            "{}/assets?name={}{}",
-            self.target_host,
-            query.name_constraint.to_string(),
-            if let Some(vc) = &query.version_constraint {
-                "&version=".to_owned() + &vc.to_string()
-            } else {
-                "".to_owned()
-            }
-        );
-
-        match reqwest::blocking::get(url) {
-            Ok(resp) => match resp.json::<Vec<AssetDescriptor>>() {
-                Ok(result) => Ok(result),
-                Err(json_error) => Err(ListAssetsError::AssetIndexInternalError(format!(
-                    "Faield to parse response: {}",
-                    json_error
-                ))),
-            },
-            Err(request_error) => Err(ListAssetsError::AssetIndexInternalError(format!(
-                "Service request failed: {}",
-                request_error
-            ))),
-        }
+        let url = self.query_url(query)?;
+        let headers = self.auth_headers()?;
+        let response = self
+            .http_client
+            .blocking_for(&headers)
+            .and_then(|client| client.get(url).headers(headers).send())
+            .map_err(request_error)?;
+        check_status(response.status())?;
+        response.json::<Vec<AssetDescriptor>>().map_err(parse_error)
+    }
+}
+
+#[async_trait]
+impl AsyncAssetIndex for HttpAssetIndex {
+    async fn list_assets(
+        &self,
+        query: &AssetQuery,
+    ) -> Result<Vec<AssetDescriptor>, ListAssetsError> {
+        let headers = self.auth_headers()?;
+        let response = self
+            .http_client
+            .client_for(&headers)
+            .map_err(request_error)?
+            .get(self.query_url(query)?)
+            .headers(headers)
+            .send()
+            .await
+            .map_err(request_error)?;
+        check_status(response.status())?;
+        response
+            .json::<Vec<AssetDescriptor>>()
+            .await
+            .map_err(parse_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HttpAssetIndex;
+    use crate::http::test_server::{TestResponse, TestServer};
+    use crate::http::HttpAuth;
+    use crate::{AssetIndex, AssetQuery, AsyncAssetIndex, ListAssetsError};
+
+    fn server() -> TestServer {
+        TestServer::start(|request| match request.header("authorization") {
+            Some("Bearer secret") => TestResponse::new(200, "[]"),
+            Some(_) => TestResponse::new(403, "Forbidden"),
+            None => TestResponse::new(401, "Unauthorized"),
+        })
+    }
+
+    #[test]
+    fn authenticates_requests() {
+        let server = server();
+        let url = server.url("/");
+        let host = url.as_str().trim_end_matches('/');
+        let query = AssetQuery::new_from_strings("hello", &None).unwrap();
+
+        let index = HttpAssetIndex::new(host).with_auth(HttpAuth::bearer("secret"));
+        assert!(AssetIndex::list_assets(&index, &query).unwrap().is_empty());
+        assert!(matches!(
+            AssetIndex::list_assets(&HttpAssetIndex::new(host), &query),
+            Err(ListAssetsError::AssetIndexAccessDenied(_))
+        ));
+        let index = HttpAssetIndex::new(host).with_auth(HttpAuth::bearer("wrong"));
+        assert!(matches!(
+            AssetIndex::list_assets(&index, &query),
+            Err(ListAssetsError::AssetIndexAccessDenied(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn authenticates_async_requests() {
+        let server = server();
+        let url = server.url("/");
+        let host = url.as_str().trim_end_matches('/');
+        let query = AssetQuery::new_from_strings("hello", &None).unwrap();
+
+        let index = HttpAssetIndex::new(host).with_auth(HttpAuth::bearer("secret"));
+        assert!(AsyncAssetIndex::list_assets(&index, &query)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(matches!(
+            AsyncAssetIndex::list_assets(&HttpAssetIndex::new(host), &query).await,
+            Err(ListAssetsError::AssetIndexAccessDenied(_))
+        ));
+    }
+
+    #[test]
+    fn tells_outages_from_other_failures() {
+        let server = TestServer::start(|request| match request.target.as_str() {
+            "/busy/assets?name=hello" => TestResponse::new(503, ""),
+            "/garbled/assets?name=hello" => TestResponse::new(200, "{"),
+            _ => TestResponse::new(404, ""),
+        });
+        let url = server.url("/");
+        let host = url.as_str().trim_end_matches('/');
+        let unreachable = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+        let query = AssetQuery::new_from_strings("hello", &None).unwrap();
+        let list = |host: &str| AssetIndex::list_assets(&HttpAssetIndex::new(host), &query);
+
+        assert!(list(&format!("{host}/busy")).unwrap_err().is_outage());
+        assert!(list(&unreachable).unwrap_err().is_outage());
+        assert!(!list(&format!("{host}/garbled")).unwrap_err().is_outage());
+        assert!(!list(&format!("{host}/missing")).unwrap_err().is_outage());
     }
 }
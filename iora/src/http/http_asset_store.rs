@@ -1,31 +1,353 @@
-use crate::{validate_hash, AssetLocator, AssetPayload, AssetStore, AssetStoreError};
+use crate::adapters::{into_async_payload, run_blocking};
+use crate::http::ranged_download::{download, Chunking};
+use crate::http::{HttpAuth, HttpClient};
+use crate::{
+    AssetLocator, AssetPayload, AssetStore, AssetStoreError, AsyncAssetPayload, AsyncAssetStore,
+    AsyncHashValidatingReader, HashValidatingReader, RetryPolicy,
+};
+use async_trait::async_trait;
+use futures_util::TryStreamExt;
+use reqwest::header::HeaderMap;
+use reqwest::Url;
+use std::path::{Path, PathBuf};
+use tokio_util::io::StreamReader;
 
-pub struct HttpAsssetStore {}
+/// Fetches assets over HTTP(S). By default the response is streamed straight to the reader.
+///
+/// With a download directory, assets are downloaded to disk first and checked against their
+/// hash before they are handed out. What was received before a transfer failed is kept, and
+/// the next fetch of the same content resumes from there with a `Range` request. Large
+/// assets can also be fetched as ranges over several parallel connections.
+///
+/// Credentials are only sent to the origins they were configured for, since descriptors
+/// may point at any host.
+#[derive(Clone, Default)]
+pub struct HttpAsssetStore {
+    http_client: HttpClient,
+    auth: Vec<(Url, HttpAuth)>,
+    retry_policy: RetryPolicy,
+    download_directory: Option<PathBuf>,
+    chunking: Option<Chunking>,
+}
+
+impl HttpAsssetStore {
+    pub fn new() -> Self {
+        HttpAsssetStore::default()
+    }
+
+    pub fn with_http_client(mut self, http_client: HttpClient) -> Self {
+        self.http_client = http_client;
+        self
+    }
+
+    /// Authenticates the requests to locators with the same scheme, host and port as
+    /// `origin`.
+    pub fn with_auth(mut self, origin: &Url, auth: HttpAuth) -> Self {
+        self.auth.push((origin.clone(), auth));
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Keeps partial downloads in the directory, so that interrupted transfers resume.
+    pub fn with_download_directory(mut self, directory: &Path) -> Self {
+        self.download_directory = Some(directory.to_owned());
+        self
+    }
+
+    /// Splits assets larger than `chunk_size` bytes into ranges, which are fetched over up
+    /// to `connections` parallel connections when the server accepts range requests. Only
+    /// applies to stores with a download directory.
+    pub fn with_parallel_ranges(mut self, chunk_size: u64, connections: usize) -> Self {
+        self.chunking = Some(Chunking {
+            chunk_size: chunk_size.max(1),
+            connections: connections.max(1),
+        });
+        self
+    }
+
+    fn auth_headers(&self, url: &Url) -> Result<HeaderMap, AssetStoreError> {
+        match self
+            .auth
+            .iter()
+            .find(|(origin, _)| origin.origin() == url.origin())
+        {
+            Some((_, auth)) => auth
+                .headers()
+                .map_err(|e| AssetStoreError::AssetStoreAccessDenied(e.to_string())),
+            None => Ok(HeaderMap::new()),
+        }
+    }
+
+    fn download(
+        &self,
+        directory: &Path,
+        locator: &AssetLocator,
+        expected_hash: &str,
+    ) -> Result<AssetPayload, AssetStoreError> {
+        let headers = self.auth_headers(&locator.url)?;
+        let file = download(
+            self.http_client.blocking_for(&headers)?,
+            &locator.url,
+            &headers,
+            directory,
+            expected_hash,
+            self.chunking,
+        )?;
+        Ok(AssetPayload::Stream(Box::new(file)))
+    }
+}
 
 impl AssetStore for HttpAsssetStore {
     fn supports_locator(&self, locator: &AssetLocator) -> bool {
         matches!(locator.url.scheme(), "http" | "https")
     }
 
+    fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy.clone()
+    }
+
     fn fetch_by_locator(
         &self,
         locator: &AssetLocator,
         expected_hash: &str,
     ) -> Result<AssetPayload, AssetStoreError> {
-        match reqwest::blocking::get(locator.url.as_str()) {
-            Ok(resp) => match resp.bytes() {
-                Ok(bytes) => {
-                    let bytes_vec = bytes.to_vec();
-                    validate_hash(&bytes_vec, expected_hash)?;
-                    Ok(AssetPayload::Bytes(bytes.to_vec()))
+        if let Some(directory) = &self.download_directory {
+            return self.download(directory, locator, expected_hash);
+        }
+        let headers = self.auth_headers(&locator.url)?;
+        let resp = self
+            .http_client
+            .blocking_for(&headers)
+            .and_then(|client| client.get(locator.url.as_str()).headers(headers).send())
+            .and_then(|resp| resp.error_for_status())
+            .map_err(AssetStoreError::from)?;
+        Ok(AssetPayload::Stream(Box::new(HashValidatingReader::new(
+            resp,
+            expected_hash,
+        ))))
+    }
+}
+
+#[async_trait]
+impl AsyncAssetStore for HttpAsssetStore {
+    fn supports_locator(&self, locator: &AssetLocator) -> bool {
+        matches!(locator.url.scheme(), "http" | "https")
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy.clone()
+    }
+
+    async fn fetch_by_locator(
+        &self,
+        locator: &AssetLocator,
+        expected_hash: &str,
+    ) -> Result<AsyncAssetPayload, AssetStoreError> {
+        if let Some(directory) = self.download_directory.clone() {
+            let store = self.clone();
+            let locator = locator.clone();
+            let expected_hash = expected_hash.to_owned();
+            return run_blocking(move || store.download(&directory, &locator, &expected_hash))
+                .await
+                .map(into_async_payload);
+        }
+        let headers = self.auth_headers(&locator.url)?;
+        let resp = self
+            .http_client
+            .client_for(&headers)?
+            .get(locator.url.as_str())
+            .headers(headers)
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .map_err(AssetStoreError::from)?;
+        let body = resp.bytes_stream().map_err(std::io::Error::other);
+        Ok(AsyncAssetPayload::Stream(Box::pin(
+            AsyncHashValidatingReader::new(StreamReader::new(body), expected_hash),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HttpAsssetStore;
+    use crate::http::test_server::{TestResponse, TestServer};
+    use crate::http::HttpAuth;
+    use crate::{AssetDescriptor, AssetLocator, AssetStore, AssetStoreError, RetryPolicy, SemVer};
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    fn descriptor(server: &TestServer, paths: &[&str]) -> AssetDescriptor {
+        AssetDescriptor::new(
+            "hello",
+            SemVer::from_str("1.0.0").unwrap(),
+            HELLO_SHA256,
+            5,
+            paths
+                .iter()
+                .map(|path| AssetLocator::new(server.url(path)))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn fails_over_to_mirrors() {
+        let primary_requests = Arc::new(AtomicUsize::new(0));
+        let counter = primary_requests.clone();
+        let server = TestServer::start(move |request| match request.target.as_str() {
+            "/primary" => {
+                counter.fetch_add(1, Ordering::SeqCst);
+                TestResponse::new(503, "")
+            }
+            "/mirror" => TestResponse::new(200, "hello"),
+            _ => TestResponse::new(404, ""),
+        });
+        let store = HttpAsssetStore::new().with_retry_policy(
+            RetryPolicy::default()
+                .with_max_attempts(3)
+                .with_initial_backoff(Duration::from_millis(1)),
+        );
+
+        let payload = store
+            .fetch_by_descriptor(&descriptor(&server, &["/primary", "/mirror"]))
+            .unwrap();
+        assert_eq!(payload.into_bytes().unwrap(), b"hello");
+        assert_eq!(primary_requests.load(Ordering::SeqCst), 3);
+
+        // Only transient errors are retried.
+        match store.fetch_by_descriptor(&descriptor(&server, &["/missing", "/primary"])) {
+            Err(AssetStoreError::AllLocatorsFailed(attempts)) => {
+                let paths: Vec<_> = attempts.iter().map(|a| a.url.path()).collect();
+                assert_eq!(paths, ["/missing", "/primary", "/primary", "/primary"]);
+                assert!(!attempts[0].error.is_transient());
+            }
+            _ => panic!("Expected every attempt to fail"),
+        }
+    }
+
+    #[test]
+    fn keeps_credentials_out_of_errors() {
+        let server = TestServer::start(|_| TestResponse::new(404, ""));
+        let unreachable = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+        let mut descriptor = descriptor(&server, &["/missing?sig=secret"]);
+        descriptor.locators.push(AssetLocator::new(
+            reqwest::Url::parse(&format!("{unreachable}/hello?sig=secret")).unwrap(),
+        ));
+        let store = HttpAsssetStore::new().with_retry_policy(RetryPolicy::no_retries());
+
+        let error = match store.fetch_by_descriptor(&descriptor) {
+            Err(error @ AssetStoreError::AllLocatorsFailed(_)) => error,
+            _ => panic!("Expected every attempt to fail"),
+        };
+        assert!(!error.to_string().contains("secret"), "{error}");
+        assert!(!format!("{error:?}").contains("secret"), "{error:?}");
+    }
+
+    #[test]
+    fn resumes_interrupted_downloads_when_retrying() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let server = TestServer::start(move |request| {
+            match (
+                counter.fetch_add(1, Ordering::SeqCst),
+                request.header("range"),
+            ) {
+                // Promises the whole content, but closes the connection after two bytes.
+                (0, None) => TestResponse::new(200, "he").with_header("content-length", "5"),
+                (_, Some("bytes=2-")) => {
+                    TestResponse::new(206, "llo").with_header("content-range", "bytes 2-4/5")
                 }
-                Err(bytes_error) => Err(AssetStoreError::AssetStoreInternalError(
-                    bytes_error.to_string(),
-                )),
-            },
-            Err(request_error) => Err(AssetStoreError::AssetStoreInternalError(
-                request_error.to_string(),
-            )),
+                _ => TestResponse::new(400, ""),
+            }
+        });
+        let directory = tempfile::tempdir().unwrap();
+        let store = HttpAsssetStore::new()
+            .with_download_directory(directory.path())
+            .with_retry_policy(
+                RetryPolicy::default().with_initial_backoff(Duration::from_millis(1)),
+            );
+
+        let payload = store
+            .fetch_by_descriptor(&descriptor(&server, &["/hello"]))
+            .unwrap();
+        assert_eq!(payload.into_bytes().unwrap(), b"hello");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn sends_credentials_only_to_their_origin() {
+        let protected = TestServer::start(|request| match request.header("authorization") {
+            Some("Bearer secret") => TestResponse::new(200, "hello"),
+            _ => TestResponse::new(401, ""),
+        });
+        let public = TestServer::start(|request| match request.header("authorization") {
+            Some(_) => TestResponse::new(400, ""),
+            None => TestResponse::new(200, "hello"),
+        });
+        let store = HttpAsssetStore::new()
+            .with_auth(&protected.url("/"), HttpAuth::bearer("secret"))
+            .with_retry_policy(RetryPolicy::no_retries());
+
+        for server in [&protected, &public] {
+            let payload = store
+                .fetch_by_descriptor(&descriptor(server, &["/hello"]))
+                .unwrap();
+            assert_eq!(payload.into_bytes().unwrap(), b"hello");
         }
+        assert!(matches!(
+            HttpAsssetStore::new().fetch_by_descriptor(&descriptor(&protected, &["/hello"])),
+            Err(AssetStoreError::AssetStoreAccessDenied(_))
+        ));
+    }
+    #[test]
+    fn does_not_forward_credentials_across_origins() {
+        let leaked = Arc::new(AtomicUsize::new(0));
+        let counter = leaked.clone();
+        let elsewhere = TestServer::start(move |request| {
+            if request.header("x-api-key").is_some() {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+            TestResponse::new(200, "hello")
+        });
+        let moved = elsewhere.url("/hello").to_string();
+        let origin = TestServer::start(move |request| match request.target.as_str() {
+            "/same" => TestResponse::new(302, "").with_header("location", "/hello"),
+            "/elsewhere" => TestResponse::new(302, "").with_header("location", &moved),
+            _ => match request.header("x-api-key") {
+                Some("secret") => TestResponse::new(200, "hello"),
+                _ => TestResponse::new(401, ""),
+            },
+        });
+        let store = HttpAsssetStore::new()
+            .with_auth(
+                &origin.url("/"),
+                HttpAuth::header("X-Api-Key", "secret").unwrap(),
+            )
+            .with_retry_policy(RetryPolicy::no_retries());
+
+        let payload = store
+            .fetch_by_descriptor(&descriptor(&origin, &["/same"]))
+            .unwrap();
+        assert_eq!(payload.into_bytes().unwrap(), b"hello");
+        assert!(store
+            .fetch_by_descriptor(&descriptor(&origin, &["/elsewhere"]))
+            .is_err());
+        assert_eq!(leaked.load(Ordering::SeqCst), 0);
+
+        let payload = HttpAsssetStore::new()
+            .fetch_by_descriptor(&descriptor(&origin, &["/elsewhere"]))
+            .unwrap();
+        assert_eq!(payload.into_bytes().unwrap(), b"hello");
     }
 }
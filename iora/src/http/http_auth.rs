@@ -0,0 +1,375 @@
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use thiserror::Error;
+
+/// The environment variables credentials are read from by [HttpAuth::from_env].
+const CREDENTIALS_FILE_VAR: &str = "IORA_CREDENTIALS_FILE";
+const TOKEN_VAR: &str = "IORA_AUTH_TOKEN";
+const USERNAME_VAR: &str = "IORA_AUTH_USERNAME";
+const PASSWORD_VAR: &str = "IORA_AUTH_PASSWORD";
+/// Holds a whole header, such as `X-Api-Key: secret`.
+const HEADER_VAR: &str = "IORA_AUTH_HEADER";
+
+#[derive(Error, Debug)]
+pub enum HttpAuthError {
+    #[error("Failed to read the credentials file {path}. Details: {details}")]
+    InvalidCredentialsFile { path: PathBuf, details: String },
+    #[error("Invalid credentials. Details: {0}")]
+    InvalidCredentials(String),
+    #[error("No token is available. Details: {0}")]
+    TokenUnavailable(String),
+}
+
+/// Supplies tokens that expire and have to be refreshed, such as OAuth access tokens. The
+/// provider is asked for a token for every request, so it should hold on to the current
+/// token until it's about to expire.
+pub trait TokenProvider: Send + Sync {
+    fn token(&self) -> Result<String, HttpAuthError>;
+}
+
+/// Reads the token from a file whenever the file changes, which suits tokens that another
+/// process keeps refreshing, such as projected service account tokens.
+pub struct FileTokenProvider {
+    path: PathBuf,
+    cached: Mutex<Option<(FileVersion, String)>>,
+}
+
+/// Tells the versions of a file apart, even where modification times are coarse.
+type FileVersion = (SystemTime, u64);
+
+impl FileTokenProvider {
+    pub fn new(path: &Path) -> Self {
+        FileTokenProvider {
+            path: path.to_owned(),
+            cached: Mutex::new(None),
+        }
+    }
+}
+
+impl TokenProvider for FileTokenProvider {
+    fn token(&self) -> Result<String, HttpAuthError> {
+        let unavailable = |e: std::io::Error| {
+            HttpAuthError::TokenUnavailable(format!("{}: {e}", self.path.display()))
+        };
+        let version = std::fs::metadata(&self.path)
+            .and_then(|metadata| Ok((metadata.modified()?, metadata.len())))
+            .map_err(unavailable)?;
+        let mut cached = self.cached.lock().unwrap();
+        match cached.as_ref() {
+            Some((read, token)) if *read == version => Ok(token.clone()),
+            _ => {
+                let token = std::fs::read_to_string(&self.path)
+                    .map_err(unavailable)?
+                    .trim()
+                    .to_owned();
+                *cached = Some((version, token.clone()));
+                Ok(token)
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Scheme {
+    Bearer(String),
+    Basic {
+        username: String,
+        password: Option<String>,
+    },
+    Header {
+        name: HeaderName,
+        value: HeaderValue,
+    },
+    TokenProvider(Arc<dyn TokenProvider>),
+}
+
+/// How requests authenticate. Credentials are never accepted as command line arguments,
+/// where other users of the machine could see them; they're read from the environment or
+/// from a credentials file instead.
+#[derive(Clone)]
+pub struct HttpAuth {
+    scheme: Scheme,
+}
+
+impl std::fmt::Debug for HttpAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let scheme = match &self.scheme {
+            Scheme::Bearer(_) => "Bearer",
+            Scheme::Basic { .. } => "Basic",
+            Scheme::Header { .. } => "Header",
+            Scheme::TokenProvider(_) => "TokenProvider",
+        };
+        f.debug_struct("HttpAuth").field("scheme", &scheme).finish()
+    }
+}
+
+/// The contents of a credentials file.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum Credentials {
+    Bearer {
+        token: String,
+    },
+    Basic {
+        username: String,
+        password: Option<String>,
+    },
+    Header {
+        name: String,
+        value: String,
+    },
+    TokenFile {
+        path: PathBuf,
+    },
+}
+
+impl HttpAuth {
+    /// Sends `Authorization: Bearer <token>`.
+    pub fn bearer(token: &str) -> Self {
+        HttpAuth {
+            scheme: Scheme::Bearer(token.to_owned()),
+        }
+    }
+
+    pub fn basic(username: &str, password: Option<&str>) -> Self {
+        HttpAuth {
+            scheme: Scheme::Basic {
+                username: username.to_owned(),
+                password: password.map(|password| password.to_owned()),
+            },
+        }
+    }
+
+    /// Sends the credentials in a header of their own, such as `X-Api-Key`.
+    pub fn header(name: &str, value: &str) -> Result<Self, HttpAuthError> {
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|e| HttpAuthError::InvalidCredentials(format!("'{name}': {e}")))?;
+        let value = HeaderValue::from_str(value.trim())
+            .map_err(|e| HttpAuthError::InvalidCredentials(format!("Value of '{name}': {e}")))?;
+        Ok(HttpAuth {
+            scheme: Scheme::Header { name, value },
+        })
+    }
+
+    /// Sends `Authorization: Bearer <token>` with a token from the provider.
+    pub fn token_provider(provider: Arc<dyn TokenProvider>) -> Self {
+        HttpAuth {
+            scheme: Scheme::TokenProvider(provider),
+        }
+    }
+
+    /// Reads credentials from a JSON file such as `{"type": "bearer", "token": "..."}`.
+    /// The other types are `basic` with a `username` and an optional `password`, `header`
+    /// with a `name` and a `value`, and `token_file` with the `path` of a file holding a
+    /// token that is re-read when it changes.
+    pub fn from_credentials_file(path: &Path) -> Result<Self, HttpAuthError> {
+        let invalid = |details: String| HttpAuthError::InvalidCredentialsFile {
+            path: path.to_owned(),
+            details,
+        };
+        let contents = std::fs::read_to_string(path).map_err(|e| invalid(e.to_string()))?;
+        let credentials: Credentials =
+            serde_json::from_str(&contents).map_err(|e| invalid(e.to_string()))?;
+        match credentials {
+            Credentials::Bearer { token } => Ok(HttpAuth::bearer(&token)),
+            Credentials::Basic { username, password } => {
+                Ok(HttpAuth::basic(&username, password.as_deref()))
+            }
+            Credentials::Header { name, value } => {
+                HttpAuth::header(&name, &value).map_err(|e| invalid(e.to_string()))
+            }
+            Credentials::TokenFile { path } => Ok(HttpAuth::token_provider(Arc::new(
+                FileTokenProvider::new(&path),
+            ))),
+        }
+    }
+
+    /// Reads credentials from the environment: a credentials file named by
+    /// `IORA_CREDENTIALS_FILE`, a bearer token in `IORA_AUTH_TOKEN`, basic credentials in
+    /// `IORA_AUTH_USERNAME` and `IORA_AUTH_PASSWORD`, or a header in `IORA_AUTH_HEADER`.
+    /// Returns `None` when none of them is set, and an error when more than one is.
+    pub fn from_env() -> Result<Option<Self>, HttpAuthError> {
+        Self::from_vars(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Option<Self>, HttpAuthError> {
+        let file = var(CREDENTIALS_FILE_VAR);
+        let token = var(TOKEN_VAR);
+        let username = var(USERNAME_VAR);
+        let header = var(HEADER_VAR);
+        let configured = [&file, &token, &username, &header]
+            .iter()
+            .filter(|value| value.is_some())
+            .count();
+        if configured > 1 {
+            return Err(HttpAuthError::InvalidCredentials(format!(
+                "Only one of {CREDENTIALS_FILE_VAR}, {TOKEN_VAR}, {USERNAME_VAR} and {HEADER_VAR} may be set."
+            )));
+        }
+        if let Some(file) = file {
+            return HttpAuth::from_credentials_file(Path::new(&file)).map(Some);
+        }
+        if let Some(token) = token {
+            return Ok(Some(HttpAuth::bearer(&token)));
+        }
+        if let Some(username) = username {
+            return Ok(Some(HttpAuth::basic(
+                &username,
+                var(PASSWORD_VAR).as_deref(),
+            )));
+        }
+        match header {
+            Some(header) => match header.split_once(':') {
+                Some((name, value)) => HttpAuth::header(name, value).map(Some),
+                None => Err(HttpAuthError::InvalidCredentials(format!(
+                    "{HEADER_VAR} must look like 'Name: value'."
+                ))),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// The headers that authenticate a request. They are marked as sensitive, so that they
+    /// are left out of debug output.
+    pub(crate) fn headers(&self) -> Result<HeaderMap, HttpAuthError> {
+        let (name, value) = match &self.scheme {
+            Scheme::Bearer(token) => (AUTHORIZATION, bearer_value(token)?),
+            Scheme::Basic { username, password } => {
+                let credentials = format!("{}:{}", username, password.as_deref().unwrap_or(""));
+                let value = format!("Basic {}", base64::encode(credentials));
+                (AUTHORIZATION, header_value(&value)?)
+            }
+            Scheme::Header { name, value } => (name.clone(), value.clone()),
+            Scheme::TokenProvider(provider) => (AUTHORIZATION, bearer_value(&provider.token()?)?),
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(name, value);
+        for value in headers.values_mut() {
+            value.set_sensitive(true);
+        }
+        Ok(headers)
+    }
+}
+
+fn bearer_value(token: &str) -> Result<HeaderValue, HttpAuthError> {
+    header_value(&format!("Bearer {}", token.trim()))
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, HttpAuthError> {
+    HeaderValue::from_str(value).map_err(|_| {
+        HttpAuthError::InvalidCredentials("The credentials contain invalid characters.".to_owned())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HttpAuth, HttpAuthError, TokenProvider};
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn authorization(auth: &HttpAuth) -> String {
+        auth.headers().unwrap()["authorization"]
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    fn from_vars(vars: &[(&str, &str)]) -> Result<Option<HttpAuth>, HttpAuthError> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        HttpAuth::from_vars(|name| vars.get(name).cloned())
+    }
+
+    struct CountingProvider {
+        calls: AtomicUsize,
+    }
+
+    impl TokenProvider for CountingProvider {
+        fn token(&self) -> Result<String, HttpAuthError> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(format!("token-{call}"))
+        }
+    }
+
+    #[test]
+    fn builds_authentication_headers() {
+        assert_eq!(authorization(&HttpAuth::bearer("abc")), "Bearer abc");
+        assert_eq!(
+            authorization(&HttpAuth::basic("Aladdin", Some("open sesame"))),
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="
+        );
+        let headers = HttpAuth::header("X-Api-Key", "secret")
+            .unwrap()
+            .headers()
+            .unwrap();
+        assert_eq!(headers["x-api-key"], "secret");
+        assert!(headers["x-api-key"].is_sensitive());
+        assert!(HttpAuth::header("X Api Key", "secret").is_err());
+
+        let provider = HttpAuth::token_provider(Arc::new(CountingProvider {
+            calls: AtomicUsize::new(0),
+        }));
+        assert_eq!(authorization(&provider), "Bearer token-1");
+        assert_eq!(authorization(&provider), "Bearer token-2");
+    }
+
+    #[test]
+    fn reads_credentials_from_the_environment() {
+        assert!(from_vars(&[]).unwrap().is_none());
+        let auth = from_vars(&[("IORA_AUTH_TOKEN", "abc")]).unwrap().unwrap();
+        assert_eq!(authorization(&auth), "Bearer abc");
+        let auth = from_vars(&[
+            ("IORA_AUTH_USERNAME", "user"),
+            ("IORA_AUTH_PASSWORD", "pass"),
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(authorization(&auth), "Basic dXNlcjpwYXNz");
+        let auth = from_vars(&[("IORA_AUTH_HEADER", "X-Api-Key: secret")])
+            .unwrap()
+            .unwrap();
+        assert_eq!(auth.headers().unwrap()["x-api-key"], "secret");
+        assert!(from_vars(&[("IORA_AUTH_HEADER", "secret")]).is_err());
+        assert!(from_vars(&[("IORA_AUTH_TOKEN", "abc"), ("IORA_AUTH_USERNAME", "user")]).is_err());
+    }
+
+    #[test]
+    fn reads_credentials_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let token_path = directory.path().join("token");
+        std::fs::write(&token_path, "first\n").unwrap();
+        let credentials_path = directory.path().join("credentials.json");
+        std::fs::write(
+            &credentials_path,
+            serde_json::json!({"type": "token_file", "path": token_path}).to_string(),
+        )
+        .unwrap();
+        let auth = from_vars(&[("IORA_CREDENTIALS_FILE", credentials_path.to_str().unwrap())])
+            .unwrap()
+            .unwrap();
+        assert_eq!(authorization(&auth), "Bearer first");
+        // The token is re-read once the file has been replaced.
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        std::fs::write(&token_path, "second\n").unwrap();
+        assert_eq!(authorization(&auth), "Bearer second");
+
+        std::fs::write(
+            &credentials_path,
+            r#"{"type": "basic", "username": "user"}"#,
+        )
+        .unwrap();
+        let auth = HttpAuth::from_credentials_file(&credentials_path).unwrap();
+        assert_eq!(authorization(&auth), "Basic dXNlcjo=");
+        std::fs::write(&credentials_path, r#"{"type": "bearer", "tokn": "abc"}"#).unwrap();
+        assert!(matches!(
+            HttpAuth::from_credentials_file(&credentials_path),
+            Err(HttpAuthError::InvalidCredentialsFile { .. })
+        ));
+    }
+}
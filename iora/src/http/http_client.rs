@@ -0,0 +1,366 @@
+use once_cell::sync::OnceCell;
+use reqwest::header::HeaderMap;
+use reqwest::redirect::Policy;
+use reqwest::{Certificate, Proxy, Url};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+
+const DEFAULT_USER_AGENT: &str = concat!("iora/", env!("CARGO_PKG_VERSION"));
+/// Matches reqwest's default policy.
+const MAX_REDIRECTS: usize = 10;
+
+/// The settings of an [HttpClient]. Everything is optional: without a proxy the
+/// `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` environment variables apply, and the extra
+/// CA certificates are trusted in addition to the system's.
+#[derive(Clone, Debug, Default)]
+pub struct HttpClientConfig {
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    proxy: Option<String>,
+    ca_certificates: Vec<PathBuf>,
+    user_agent: Option<String>,
+}
+
+impl HttpClientConfig {
+    pub fn new() -> Self {
+        HttpClientConfig::default()
+    }
+
+    /// Limits how long establishing a connection may take.
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    /// Limits how long a whole request may take, from connecting until the body has been
+    /// read. Requests don't time out by default, since assets can be large.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sends every request through the given proxy, such as `http://proxy.example.com:8080`.
+    pub fn with_proxy(mut self, proxy: &str) -> Self {
+        self.proxy = Some(proxy.to_owned());
+        self
+    }
+
+    /// Trusts the CA certificates in the given PEM or DER file.
+    pub fn with_ca_certificate(mut self, path: &Path) -> Self {
+        self.ca_certificates.push(path.to_owned());
+        self
+    }
+
+    pub fn with_user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = Some(user_agent.to_owned());
+        self
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum HttpClientError {
+    #[error("Failed to load the CA certificate {path}. Details: {details}")]
+    InvalidCaCertificate { path: PathBuf, details: String },
+    #[error("Invalid proxy '{proxy}'. Details: {details}")]
+    InvalidProxy { proxy: String, details: String },
+}
+
+struct Settings {
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    proxy: Option<Proxy>,
+    ca_certificates: Vec<Certificate>,
+    user_agent: String,
+}
+
+struct Clients {
+    settings: Settings,
+    client: OnceCell<reqwest::Client>,
+    blocking: OnceCell<reqwest::blocking::Client>,
+    client_with_credentials: OnceCell<reqwest::Client>,
+    blocking_with_credentials: OnceCell<reqwest::blocking::Client>,
+}
+
+/// The HTTP client shared by the HTTP based components. Clones share their connection
+/// pools, so a single client should be created and handed to every component. The
+/// underlying clients are built on first use, which keeps the blocking client from being
+/// created by components that only make async requests.
+#[derive(Clone)]
+pub struct HttpClient {
+    clients: Arc<Clients>,
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        HttpClient::new(&HttpClientConfig::default())
+            .expect("The default HTTP client settings are valid")
+    }
+}
+
+impl std::fmt::Debug for HttpClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HttpClient")
+            .field("user_agent", &self.clients.settings.user_agent)
+            .finish_non_exhaustive()
+    }
+}
+
+impl HttpClient {
+    /// Creates a client with the given settings. The proxy and the CA certificates are
+    /// checked here, so that misconfigurations surface at startup.
+    pub fn new(config: &HttpClientConfig) -> Result<Self, HttpClientError> {
+        let proxy = config
+            .proxy
+            .as_deref()
+            .map(|proxy| {
+                Proxy::all(proxy).map_err(|e| HttpClientError::InvalidProxy {
+                    proxy: proxy.to_owned(),
+                    details: e.to_string(),
+                })
+            })
+            .transpose()?;
+        let mut ca_certificates = vec![];
+        for path in config.ca_certificates.iter() {
+            ca_certificates.extend(load_certificates(path)?);
+        }
+        Ok(HttpClient {
+            clients: Arc::new(Clients {
+                settings: Settings {
+                    connect_timeout: config.connect_timeout,
+                    timeout: config.timeout,
+                    proxy,
+                    ca_certificates,
+                    user_agent: config
+                        .user_agent
+                        .clone()
+                        .unwrap_or_else(|| DEFAULT_USER_AGENT.to_owned()),
+                },
+                client: OnceCell::new(),
+                blocking: OnceCell::new(),
+                client_with_credentials: OnceCell::new(),
+                blocking_with_credentials: OnceCell::new(),
+            }),
+        })
+    }
+
+    pub(crate) fn client(&self) -> Result<&reqwest::Client, reqwest::Error> {
+        let settings = &self.clients.settings;
+        self.clients
+            .client
+            .get_or_try_init(|| settings.client(Policy::limited(MAX_REDIRECTS)))
+    }
+
+    /// The blocking client. It must not be first used from within an async context.
+    pub(crate) fn blocking(&self) -> Result<&reqwest::blocking::Client, reqwest::Error> {
+        let settings = &self.clients.settings;
+        self.clients
+            .blocking
+            .get_or_try_init(|| settings.blocking(Policy::limited(MAX_REDIRECTS)))
+    }
+
+    /// The client for requests with the given headers. When they carry credentials, the
+    /// requests only follow redirects within their origin: reqwest drops the standard
+    /// `Authorization` and `Cookie` headers from redirects to other hosts, but would pass
+    /// on credentials sent in other headers, such as API keys.
+    pub(crate) fn client_for(
+        &self,
+        headers: &HeaderMap,
+    ) -> Result<&reqwest::Client, reqwest::Error> {
+        if headers.is_empty() {
+            return self.client();
+        }
+        let settings = &self.clients.settings;
+        self.clients
+            .client_with_credentials
+            .get_or_try_init(|| settings.client(same_origin_redirects()))
+    }
+
+    /// The blocking counterpart of `client_for`.
+    pub(crate) fn blocking_for(
+        &self,
+        headers: &HeaderMap,
+    ) -> Result<&reqwest::blocking::Client, reqwest::Error> {
+        if headers.is_empty() {
+            return self.blocking();
+        }
+        let settings = &self.clients.settings;
+        self.clients
+            .blocking_with_credentials
+            .get_or_try_init(|| settings.blocking(same_origin_redirects()))
+    }
+}
+
+impl Settings {
+    fn client(&self, redirect: Policy) -> Result<reqwest::Client, reqwest::Error> {
+        let mut builder = reqwest::Client::builder()
+            .user_agent(&self.user_agent)
+            .redirect(redirect);
+        if let Some(connect_timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(proxy.clone());
+        }
+        for certificate in self.ca_certificates.iter() {
+            builder = builder.add_root_certificate(certificate.clone());
+        }
+        builder.build()
+    }
+
+    fn blocking(&self, redirect: Policy) -> Result<reqwest::blocking::Client, reqwest::Error> {
+        // Unlike the async client, the blocking one times out after 30 seconds by default.
+        let mut builder = reqwest::blocking::Client::builder()
+            .user_agent(&self.user_agent)
+            .timeout(self.timeout)
+            .redirect(redirect);
+        if let Some(connect_timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(proxy.clone());
+        }
+        for certificate in self.ca_certificates.iter() {
+            builder = builder.add_root_certificate(certificate.clone());
+        }
+        builder.build()
+    }
+}
+
+/// Follows redirects as long as they stay within the origin of the first request.
+fn same_origin_redirects() -> Policy {
+    Policy::custom(|attempt| {
+        let origin = attempt.previous().first().map(Url::origin);
+        if attempt.previous().len() > MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else if origin == Some(attempt.url().origin()) {
+            attempt.follow()
+        } else {
+            let error = format!(
+                "refusing to send credentials along a redirect to {}",
+                attempt.url().origin().ascii_serialization()
+            );
+            attempt.error(error)
+        }
+    })
+}
+
+/// Reads every certificate of a PEM bundle, or the single certificate of a DER file.
+fn load_certificates(path: &Path) -> Result<Vec<Certificate>, HttpClientError> {
+    let invalid = |details: String| HttpClientError::InvalidCaCertificate {
+        path: path.to_owned(),
+        details,
+    };
+    let contents = std::fs::read(path).map_err(|e| invalid(e.to_string()))?;
+    const END: &str = "-----END CERTIFICATE-----";
+    let pem = String::from_utf8_lossy(&contents);
+    if !pem.contains("-----BEGIN CERTIFICATE-----") {
+        return Certificate::from_der(&contents)
+            .map(|certificate| vec![certificate])
+            .map_err(|e| invalid(e.to_string()));
+    }
+    pem.split_inclusive(END)
+        .filter(|block| block.contains(END))
+        .map(|block| Certificate::from_pem(block.as_bytes()).map_err(|e| invalid(e.to_string())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HttpClient, HttpClientConfig, HttpClientError, DEFAULT_USER_AGENT};
+    use crate::http::test_server::{TestResponse, TestServer};
+    use std::time::Duration;
+
+    #[test]
+    fn sends_the_configured_user_agent() {
+        let server = TestServer::start(|request| {
+            TestResponse::new(200, request.header("user-agent").unwrap_or_default())
+        });
+        let client = HttpClient::default();
+        let body = client
+            .blocking()
+            .unwrap()
+            .get(server.url("/"))
+            .send()
+            .unwrap()
+            .text()
+            .unwrap();
+        assert_eq!(body, DEFAULT_USER_AGENT);
+
+        let client =
+            HttpClient::new(&HttpClientConfig::new().with_user_agent("tests/1.0")).unwrap();
+        let body = client
+            .blocking()
+            .unwrap()
+            .get(server.url("/"))
+            .send()
+            .unwrap()
+            .text()
+            .unwrap();
+        assert_eq!(body, "tests/1.0");
+    }
+
+    #[test]
+    fn sends_requests_through_the_proxy() {
+        let proxy = TestServer::start(|request| TestResponse::new(200, request.target.as_str()));
+        let client =
+            HttpClient::new(&HttpClientConfig::new().with_proxy(proxy.url("/").as_str())).unwrap();
+        let body = client
+            .blocking()
+            .unwrap()
+            .get("http://assets.example.invalid/hello.txt")
+            .send()
+            .unwrap()
+            .text()
+            .unwrap();
+        assert_eq!(body, "http://assets.example.invalid/hello.txt");
+    }
+
+    #[tokio::test]
+    async fn times_out_slow_requests() {
+        let server = TestServer::start(|_| {
+            std::thread::sleep(Duration::from_secs(2));
+            TestResponse::new(200, "hello")
+        });
+        let client =
+            HttpClient::new(&HttpClientConfig::new().with_timeout(Duration::from_millis(100)))
+                .unwrap();
+        let error = client
+            .client()
+            .unwrap()
+            .get(server.url("/"))
+            .send()
+            .await
+            .unwrap_err();
+        assert!(error.is_timeout());
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("ca.pem");
+        std::fs::write(
+            &path,
+            "-----BEGIN CERTIFICATE-----\nnot base64\n-----END CERTIFICATE-----\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            HttpClient::new(&HttpClientConfig::new().with_ca_certificate(&path)),
+            Err(HttpClientError::InvalidCaCertificate { .. })
+        ));
+        assert!(matches!(
+            HttpClient::new(
+                &HttpClientConfig::new().with_ca_certificate(&directory.path().join("missing.pem"))
+            ),
+            Err(HttpClientError::InvalidCaCertificate { .. })
+        ));
+        assert!(matches!(
+            HttpClient::new(&HttpClientConfig::new().with_proxy("not a url")),
+            Err(HttpClientError::InvalidProxy { .. })
+        ));
+    }
+}
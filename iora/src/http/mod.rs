@@ -1,12 +1,29 @@
 mod azure_blob_asset_index;
 mod azure_blob_asset_locator_factory;
+mod azure_blob_asset_publisher;
 mod http_asset_index;
 mod http_asset_store;
+mod http_auth;
+mod http_client;
+mod ranged_download;
+mod s3_asset_index;
+mod s3_asset_locator_factory;
+mod s3_asset_store;
+mod s3_bucket;
+#[cfg(test)]
+mod test_server;
 
 pub use azure_blob_asset_index::AzureBlobAssetIndex;
 pub use azure_blob_asset_locator_factory::{
     AzureBlobAssetLocatorFactory, AzureBlobAssetLocatorFactoryError,
     AzureBlobStorageDirectAccessLocatorFactory,
 };
+pub use azure_blob_asset_publisher::AzureBlobAssetPublisher;
 pub use http_asset_index::HttpAssetIndex;
 pub use http_asset_store::HttpAsssetStore;
+pub use http_auth::{FileTokenProvider, HttpAuth, HttpAuthError, TokenProvider};
+pub use http_client::{HttpClient, HttpClientConfig, HttpClientError};
+pub use s3_asset_index::{S3AssetIndex, S3AttributeSource};
+pub use s3_asset_locator_factory::S3PresignedUrlLocatorFactory;
+pub use s3_asset_store::S3AssetStore;
+pub use s3_bucket::{S3Bucket, S3Credentials};
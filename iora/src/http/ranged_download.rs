@@ -0,0 +1,600 @@
+use crate::content_hash::parse_content_hash;
+use crate::filesystem::file_lock::FileLock;
+use crate::{redact_url, AssetStoreError, ContentHasher};
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{HeaderMap, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, RANGE};
+use reqwest::{StatusCode, Url};
+use std::fs::{create_dir_all, read_dir, remove_file, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tempfile::TempPath;
+
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// How large assets are split into ranges that are downloaded in parallel.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Chunking {
+    pub(crate) chunk_size: u64,
+    pub(crate) connections: usize,
+}
+
+/// A byte range of the asset and the file it's downloaded to. Ranges without an end run
+/// to the end of the asset.
+struct Part {
+    path: PathBuf,
+    start: u64,
+    end: Option<u64>,
+}
+
+/// Downloads an asset into the directory, keeping whatever has been received when the
+/// transfer is interrupted. The next download of the same content resumes with a `Range`
+/// request, even when it comes from another locator. Once every part is complete the
+/// content is checked against the expected hash, and discarded if it doesn't match. The
+/// headers are sent with every request.
+///
+/// Downloads of the same content take turns on a lock file, which each of them removes once
+/// done, so that the directory only holds the parts of unfinished downloads.
+pub(crate) fn download(
+    client: &Client,
+    url: &Url,
+    headers: &HeaderMap,
+    directory: &Path,
+    expected_hash: &str,
+    chunking: Option<Chunking>,
+) -> Result<DownloadedFile, AssetStoreError> {
+    let (algorithm, digest) = parse_content_hash(expected_hash)?;
+    if digest.is_empty() || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(AssetStoreError::AssetStoreInternalError(format!(
+            "The content hash {expected_hash} isn't a hex digest."
+        )));
+    }
+    create_dir_all(directory)?;
+    let key = format!("{}-{}", algorithm.tag(), digest.to_lowercase());
+    let lock_path = directory.join(format!("{key}.lock"));
+    let lock = FileLock::exclusive_removable(&lock_path)?;
+    let result = download_locked(
+        client,
+        url,
+        headers,
+        directory,
+        &key,
+        expected_hash,
+        chunking,
+    );
+    lock.remove(&lock_path);
+    result
+}
+
+fn download_locked(
+    client: &Client,
+    url: &Url,
+    headers: &HeaderMap,
+    directory: &Path,
+    key: &str,
+    expected_hash: &str,
+    chunking: Option<Chunking>,
+) -> Result<DownloadedFile, AssetStoreError> {
+    let (algorithm, _) = parse_content_hash(expected_hash)?;
+    let parts = match chunking {
+        Some(chunking) => match probe_length(client, url, headers)? {
+            Some(length) if length > chunking.chunk_size => {
+                split(directory, key, length, chunking.chunk_size)
+            }
+            _ => vec![whole(directory, key)],
+        },
+        None => vec![whole(directory, key)],
+    };
+    remove_stale_parts(directory, key, &parts)?;
+    let connections = chunking.map_or(1, |chunking| chunking.connections.max(1));
+    fetch_parts(client, url, headers, &parts, connections)?;
+
+    let mut hasher = ContentHasher::new(algorithm);
+    let mut buf = vec![0; COPY_BUFFER_SIZE];
+    for part in &parts {
+        let mut file = File::open(&part.path)?;
+        loop {
+            let read = file.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buf[..read]);
+        }
+    }
+    if let Err(e) = hasher.verify(expected_hash) {
+        for part in &parts {
+            let _ = remove_file(&part.path);
+        }
+        return Err(e);
+    }
+
+    // Completed parts are moved out of the way, so that a concurrent download of the same
+    // content starts over instead of picking up files that are deleted once read.
+    let mut completed = vec![];
+    for part in parts {
+        let target = tempfile::Builder::new()
+            .prefix(&format!("{key}."))
+            .suffix(".download")
+            .tempfile_in(directory)?
+            .into_temp_path();
+        std::fs::rename(&part.path, &target)?;
+        completed.push(target);
+    }
+    Ok(DownloadedFile::new(completed))
+}
+
+/// Removes the parts of earlier attempts that split the content differently, for instance
+/// because chunking was configured since, which would otherwise never be read or removed.
+fn remove_stale_parts(directory: &Path, key: &str, parts: &[Part]) -> Result<(), AssetStoreError> {
+    let prefix = format!("{key}.");
+    for entry in read_dir(directory)? {
+        let path = entry?.path();
+        let is_part = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(&prefix) && name.ends_with(".part"));
+        if !is_part || parts.iter().any(|part| part.path == path) {
+            continue;
+        }
+        if let Err(e) = remove_file(&path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(e.into());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn whole(directory: &Path, key: &str) -> Part {
+    Part {
+        path: directory.join(format!("{key}.part")),
+        start: 0,
+        end: None,
+    }
+}
+
+fn split(directory: &Path, key: &str, length: u64, chunk_size: u64) -> Vec<Part> {
+    (0..length)
+        .step_by(chunk_size as usize)
+        .map(|start| {
+            let end = (start + chunk_size).min(length) - 1;
+            Part {
+                path: directory.join(format!("{key}.{start}-{end}.part")),
+                start,
+                end: Some(end),
+            }
+        })
+        .collect()
+}
+
+/// The length of the asset, if the server reports it and accepts range requests.
+fn probe_length(
+    client: &Client,
+    url: &Url,
+    headers: &HeaderMap,
+) -> Result<Option<u64>, AssetStoreError> {
+    let resp = client
+        .head(url.clone())
+        .headers(headers.clone())
+        .send()?
+        .error_for_status()?;
+    let accepts_ranges = resp
+        .headers()
+        .get(ACCEPT_RANGES)
+        .is_some_and(|value| value.as_bytes() == b"bytes");
+    let length = resp
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+    Ok(length.filter(|_| accepts_ranges))
+}
+
+/// Fetches the parts on up to `connections` threads. Every part is attempted, so that as
+/// much as possible is kept for the next attempt, and the first error is returned.
+fn fetch_parts(
+    client: &Client,
+    url: &Url,
+    headers: &HeaderMap,
+    parts: &[Part],
+    connections: usize,
+) -> Result<(), AssetStoreError> {
+    let next = AtomicUsize::new(0);
+    let errors = Mutex::new(vec![]);
+    std::thread::scope(|scope| {
+        for _ in 0..connections.min(parts.len()) {
+            scope.spawn(|| {
+                while let Some(part) = parts.get(next.fetch_add(1, Ordering::SeqCst)) {
+                    if let Err(e) = fetch_part(client, url, headers, part) {
+                        errors.lock().unwrap().push(e);
+                    }
+                }
+            });
+        }
+    });
+    match errors.into_inner().unwrap().into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Fetches the rest of a part, appending to what earlier attempts received.
+fn fetch_part(
+    client: &Client,
+    url: &Url,
+    headers: &HeaderMap,
+    part: &Part,
+) -> Result<(), AssetStoreError> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&part.path)?;
+    let received = file.metadata()?.len();
+    let length = part.end.map(|end| end + 1 - part.start);
+    if Some(received) == length {
+        return Ok(());
+    }
+
+    let offset = part.start + received;
+    let shown = redact_url(url);
+    let mut request = client.get(url.clone()).headers(headers.clone());
+    if offset > 0 || part.end.is_some() {
+        let end = part.end.map(|end| end.to_string()).unwrap_or_default();
+        request = request.header(RANGE, format!("bytes={offset}-{end}"));
+    }
+    let resp = request.send()?;
+    match resp.status() {
+        StatusCode::PARTIAL_CONTENT if starts_at(&resp, offset) => {}
+        // The server ignored the range, so the whole asset is on its way.
+        StatusCode::OK if part.start == 0 && part.end.is_none() => file.set_len(0)?,
+        // A previous attempt received everything, the hash will tell whether it's intact.
+        StatusCode::RANGE_NOT_SATISFIABLE if part.end.is_none() && received > 0 => return Ok(()),
+        StatusCode::OK | StatusCode::PARTIAL_CONTENT => {
+            return Err(AssetStoreError::AssetStoreInternalError(format!(
+                "The server didn't return the requested range of {shown}."
+            )))
+        }
+        status => {
+            return Err(match resp.error_for_status() {
+                Err(e) => AssetStoreError::from(e),
+                Ok(_) => AssetStoreError::AssetStoreInternalError(format!(
+                    "Fetching {shown} returned the unexpected status {status}."
+                )),
+            })
+        }
+    }
+    copy_body(resp, &mut file, url)?;
+
+    let received = file.metadata()?.len();
+    match length {
+        Some(length) if received != length => Err(AssetStoreError::AssetStoreUnavailable(format!(
+            "The download of {shown} ended after {received} of {length} bytes."
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn starts_at(resp: &Response, offset: u64) -> bool {
+    resp.headers()
+        .get(CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|range| range.starts_with(&format!("bytes {offset}-")))
+}
+
+/// Copies the response body to the file. Failing to read the body means the transfer was
+/// interrupted, which is worth retrying since the next attempt resumes from here.
+fn copy_body(mut resp: Response, file: &mut File, url: &Url) -> Result<(), AssetStoreError> {
+    let mut buf = vec![0; COPY_BUFFER_SIZE];
+    loop {
+        let read = match resp.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(read) => read,
+            Err(e) => {
+                return Err(AssetStoreError::AssetStoreUnavailable(format!(
+                    "The download of {} was interrupted: {e}",
+                    redact_url(url)
+                )))
+            }
+        };
+        file.write_all(&buf[..read])?;
+    }
+}
+
+/// The downloaded content, read from its parts in order. The parts are deleted once the
+/// file is dropped.
+pub(crate) struct DownloadedFile {
+    current: Option<File>,
+    remaining: std::vec::IntoIter<TempPath>,
+    // Declared last so that the open file is closed before the parts are deleted.
+    parts: Vec<TempPath>,
+}
+
+impl DownloadedFile {
+    fn new(parts: Vec<TempPath>) -> Self {
+        DownloadedFile {
+            current: None,
+            remaining: parts.into_iter(),
+            parts: vec![],
+        }
+    }
+}
+
+impl Read for DownloadedFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if let Some(file) = self.current.as_mut() {
+                let read = file.read(buf)?;
+                if read > 0 || buf.is_empty() {
+                    return Ok(read);
+                }
+                self.current = None;
+            }
+            match self.remaining.next() {
+                Some(part) => {
+                    self.current = Some(File::open(&part)?);
+                    self.parts.push(part);
+                }
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{download, Chunking};
+    use crate::http::test_server::{TestRequest, TestResponse, TestServer};
+    use crate::{AssetStoreError, ContentHasher, HashAlgorithm};
+    use reqwest::blocking::Client;
+    use reqwest::header::HeaderMap;
+    use std::collections::HashMap;
+    use std::io::Read;
+    use std::sync::{Arc, Mutex};
+
+    fn content(length: usize) -> Vec<u8> {
+        (0..length).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn content_hash(content: &[u8]) -> String {
+        let mut hasher = ContentHasher::new(HashAlgorithm::Sha256);
+        hasher.update(content);
+        hasher.finalize()
+    }
+
+    /// Serves the content, honoring ranges. With `cut`, only that many bytes of the body
+    /// are sent before the connection is closed, although the headers promise all of them.
+    fn respond(content: &[u8], request: &TestRequest, cut: Option<usize>) -> TestResponse {
+        let range = request.header("range").and_then(|range| {
+            let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+            let start: usize = start.parse().ok()?;
+            let end = end.parse().unwrap_or(content.len() - 1);
+            Some((start, end))
+        });
+        let (status, start, end) = match range {
+            Some((start, end)) => (206, start, end),
+            None => (200, 0, content.len() - 1),
+        };
+        let body = &content[start..=end];
+        let sent = cut.map_or(body, |cut| &body[..cut]);
+        let mut response = TestResponse::new(status, sent)
+            .with_header("accept-ranges", "bytes")
+            .with_header("content-length", &body.len().to_string());
+        if range.is_some() {
+            response = response.with_header(
+                "content-range",
+                &format!("bytes {start}-{end}/{}", content.len()),
+            );
+        }
+        response
+    }
+
+    fn read(file: impl Read) -> Vec<u8> {
+        let mut buf = vec![];
+        let mut file = file;
+        file.read_to_end(&mut buf).unwrap();
+        buf
+    }
+
+    /// The files left in the download directory.
+    fn leftovers(directory: &std::path::Path) -> Vec<String> {
+        std::fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn resumes_interrupted_transfers() {
+        let expected = content(100_000);
+        let hash = content_hash(&expected);
+        let ranges = Arc::new(Mutex::new(vec![]));
+        let served = expected.clone();
+        let seen = ranges.clone();
+        let server = TestServer::start(move |request| {
+            let mut seen = seen.lock().unwrap();
+            seen.push(request.header("range").map(str::to_owned));
+            let cut = if seen.len() == 1 { Some(40_000) } else { None };
+            respond(&served, request, cut)
+        });
+        let directory = tempfile::tempdir().unwrap();
+        let client = Client::new();
+
+        let interrupted = download(
+            &client,
+            &server.url("/a"),
+            &HeaderMap::new(),
+            directory.path(),
+            &hash,
+            None,
+        );
+        match interrupted {
+            Err(e) => assert!(e.is_transient(), "{e}"),
+            Ok(_) => panic!("Expected the transfer to be interrupted"),
+        }
+        let file = download(
+            &client,
+            &server.url("/a"),
+            &HeaderMap::new(),
+            directory.path(),
+            &hash,
+            None,
+        )
+        .unwrap();
+        assert_eq!(read(file), expected);
+        assert_eq!(
+            *ranges.lock().unwrap(),
+            [None, Some("bytes=40000-".to_owned())]
+        );
+        assert!(leftovers(directory.path()).is_empty());
+    }
+
+    #[test]
+    fn fetches_parallel_ranges() {
+        let expected = content(10_000);
+        let hash = content_hash(&expected);
+        let requests = Arc::new(Mutex::new(HashMap::<String, usize>::new()));
+        let served = expected.clone();
+        let seen = requests.clone();
+        let server = TestServer::start(move |request| {
+            if request.method == "HEAD" {
+                return respond(&served, request, None);
+            }
+            let range = request.header("range").unwrap_or_default().to_owned();
+            let mut seen = seen.lock().unwrap();
+            let count = seen.entry(range.clone()).or_default();
+            *count += 1;
+            let cut = (range == "bytes=3000-5999" && *count == 1).then_some(1000);
+            respond(&served, request, cut)
+        });
+        let directory = tempfile::tempdir().unwrap();
+        let client = Client::new();
+        let chunking = Some(Chunking {
+            chunk_size: 3000,
+            connections: 3,
+        });
+
+        let url = server.url("/a");
+        assert!(download(
+            &client,
+            &url,
+            &HeaderMap::new(),
+            directory.path(),
+            &hash,
+            chunking
+        )
+        .is_err());
+        let file = download(
+            &client,
+            &url,
+            &HeaderMap::new(),
+            directory.path(),
+            &hash,
+            chunking,
+        )
+        .unwrap();
+        assert_eq!(read(file), expected);
+        let mut requests: Vec<_> = requests.lock().unwrap().clone().into_iter().collect();
+        requests.sort();
+        assert_eq!(
+            requests,
+            [
+                ("bytes=0-2999".to_owned(), 1),
+                ("bytes=3000-5999".to_owned(), 1),
+                ("bytes=4000-5999".to_owned(), 1),
+                ("bytes=6000-8999".to_owned(), 1),
+                ("bytes=9000-9999".to_owned(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn restarts_when_the_server_ignores_ranges() {
+        let expected = content(5000);
+        let hash = content_hash(&expected);
+        let served = expected.clone();
+        let server = TestServer::start(move |_| TestResponse::new(200, served.clone()));
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::write(
+            directory
+                .path()
+                .join(format!("{}.part", hash.replace(':', "-"))),
+            "stale",
+        )
+        .unwrap();
+        let file = download(
+            &Client::new(),
+            &server.url("/a"),
+            &HeaderMap::new(),
+            directory.path(),
+            &hash,
+            None,
+        );
+        assert_eq!(read(file.unwrap()), expected);
+    }
+
+    #[test]
+    fn keeps_credentials_out_of_errors() {
+        let expected = content(5000);
+        let hash = content_hash(&expected);
+        let server = TestServer::start(move |request| respond(&expected, request, Some(100)));
+        let directory = tempfile::tempdir().unwrap();
+        let error = download(
+            &Client::new(),
+            &server.url("/a?sig=secret"),
+            &HeaderMap::new(),
+            directory.path(),
+            &hash,
+            None,
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(error, AssetStoreError::AssetStoreUnavailable(_)));
+        assert!(!error.to_string().contains("secret"), "{error}");
+    }
+
+    #[test]
+    fn discards_mismatching_content() {
+        let hash = content_hash(&content(5000));
+        let server = TestServer::start(|request| respond(&content(4000), request, None));
+        let directory = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            download(
+                &Client::new(),
+                &server.url("/a"),
+                &HeaderMap::new(),
+                directory.path(),
+                &hash,
+                None
+            ),
+            Err(AssetStoreError::AssetHashMismatch { .. })
+        ));
+        assert!(leftovers(directory.path()).is_empty());
+    }
+
+    #[test]
+    fn removes_parts_of_other_splits() {
+        let expected = content(5000);
+        let hash = content_hash(&expected);
+        let served = expected.clone();
+        let server = TestServer::start(move |request| respond(&served, request, None));
+        let directory = tempfile::tempdir().unwrap();
+        let key = hash.replace(':', "-");
+        for part in ["0-2999", "3000-4999"] {
+            std::fs::write(directory.path().join(format!("{key}.{part}.part")), "stale").unwrap();
+        }
+        let unrelated = directory.path().join("unrelated.part");
+        std::fs::write(&unrelated, "unrelated").unwrap();
+
+        let file = download(
+            &Client::new(),
+            &server.url("/a"),
+            &HeaderMap::new(),
+            directory.path(),
+            &hash,
+            None,
+        );
+        assert_eq!(read(file.unwrap()), expected);
+        assert_eq!(leftovers(directory.path()), ["unrelated.part"]);
+    }
+}
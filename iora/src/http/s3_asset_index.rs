@@ -0,0 +1,690 @@
+use crate::http::{HttpClient, S3Bucket, S3PresignedUrlLocatorFactory};
+use crate::{
+    format_content_hash, AssetDescriptor, AssetIndex, AssetQuery, AsyncAssetIndex, HashAlgorithm,
+    ListAssetsError, SemVer,
+};
+use async_trait::async_trait;
+use quick_xml::de::from_str;
+use reqwest::header::HeaderMap;
+use reqwest::{Method, StatusCode};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+/// Guards against listing an unexpectedly large bucket forever.
+const DEFAULT_MAX_PAGES: usize = 100;
+const DEFAULT_PRESIGNED_URL_EXPIRY: Duration = Duration::from_secs(24 * 60 * 60);
+const METADATA_HEADER_PREFIX: &str = "x-amz-meta-";
+
+/// Where the index reads an object's name, version and content hash from. Either way the
+/// keys are `name`, `version` and one of `sha512`, `sha256` or `sha1`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum S3AttributeSource {
+    /// User-defined object metadata, i.e. the `x-amz-meta-*` headers.
+    #[default]
+    ObjectMetadata,
+    /// Object tags.
+    ObjectTags,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+struct Object {
+    key: String,
+    size: usize,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+struct ListBucketResult {
+    #[serde(default)]
+    contents: Vec<Object>,
+    #[serde(default)]
+    is_truncated: bool,
+    #[serde(default)]
+    next_continuation_token: Option<String>,
+}
+
+impl ListBucketResult {
+    /// The token to pass to the next ListObjectsV2 call, if the listing isn't complete.
+    fn continuation(&self) -> Option<&str> {
+        self.next_continuation_token
+            .as_deref()
+            .filter(|token| self.is_truncated && !token.trim().is_empty())
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+struct Error {
+    code: String,
+    #[serde(default)]
+    message: String,
+}
+
+impl From<Error> for ListAssetsError {
+    fn from(e: Error) -> Self {
+        match e.code.as_str() {
+            "AccessDenied" | "InvalidAccessKeyId" | "SignatureDoesNotMatch" | "ExpiredToken" => {
+                ListAssetsError::AssetIndexAccessDenied(Some(e.message))
+            }
+            "NoSuchBucket" => ListAssetsError::AssetIndexNotFound(Some(e.message)),
+            "InternalError" | "ServiceUnavailable" | "SlowDown" => {
+                ListAssetsError::AssetIndexUnavailable(e.message)
+            }
+            _ => ListAssetsError::AssetIndexInternalError(format!(
+                "Storage error '{}'. Details: {}",
+                e.code, e.message
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+enum ListObjectsResponse {
+    ListBucketResult(ListBucketResult),
+    Error(Error),
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+struct Tag {
+    key: String,
+    value: String,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct TagSet {
+    #[serde(rename = "Tag", default)]
+    tags: Vec<Tag>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+struct Tagging {
+    tag_set: TagSet,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+enum TaggingResponse {
+    Tagging(Tagging),
+    Error(Error),
+}
+
+/// The name, version and hashes recorded on an object.
+type Attributes = HashMap<String, String>;
+
+/// Lists assets stored in a bucket of an S3-compatible storage service. Listing objects
+/// doesn't return their metadata or tags, so each listed object that could match the
+/// query takes another request to describe it. Objects without a name, version and hash
+/// are skipped.
+///
+/// Descriptors locate assets with a presigned URL, followed by the object's `s3://` URI
+/// for stores that hold credentials of their own.
+pub struct S3AssetIndex {
+    bucket: S3Bucket,
+    locator_factory: S3PresignedUrlLocatorFactory,
+    attribute_source: S3AttributeSource,
+    page_size: Option<u32>,
+    max_pages: usize,
+    prefix_filtering: bool,
+    http_client: HttpClient,
+}
+
+impl S3AssetIndex {
+    pub fn new(bucket: S3Bucket) -> Self {
+        S3AssetIndex {
+            locator_factory: S3PresignedUrlLocatorFactory::new(
+                bucket.clone(),
+                DEFAULT_PRESIGNED_URL_EXPIRY,
+            ),
+            bucket,
+            attribute_source: S3AttributeSource::default(),
+            page_size: None,
+            max_pages: DEFAULT_MAX_PAGES,
+            prefix_filtering: true,
+            http_client: HttpClient::default(),
+        }
+    }
+
+    pub fn with_http_client(mut self, http_client: HttpClient) -> Self {
+        self.http_client = http_client;
+        self
+    }
+
+    /// Sets where the name, version and hash of objects are read from. Object metadata is
+    /// used by default.
+    pub fn with_attribute_source(mut self, attribute_source: S3AttributeSource) -> Self {
+        self.attribute_source = attribute_source;
+        self
+    }
+
+    /// Sets how long the presigned URLs in descriptors stay valid. The default is a day.
+    pub fn with_presigned_url_expiry(mut self, expires_in: Duration) -> Self {
+        self.locator_factory = S3PresignedUrlLocatorFactory::new(self.bucket.clone(), expires_in);
+        self
+    }
+
+    /// Sets the number of objects requested per ListObjectsV2 call. The service default
+    /// is 1000.
+    pub fn with_page_size(mut self, page_size: u32) -> Self {
+        self.page_size = Some(page_size);
+        self
+    }
+
+    /// Sets the maximum number of ListObjectsV2 calls made for a single query. Listings
+    /// that need more pages fail rather than returning truncated results. Since objects
+    /// are described one request at a time, this also bounds those requests to the page
+    /// limit times the page size.
+    pub fn with_max_pages(mut self, max_pages: usize) -> Self {
+        self.max_pages = max_pages;
+        self
+    }
+
+    /// Enables or disables prefix filtering. Prefix filtering relies on objects being laid
+    /// out as `name/version/...`: the listing is narrowed down on the server, and objects
+    /// whose keys can't match the query aren't described. It is enabled by default.
+    pub fn with_prefix_filtering(mut self, prefix_filtering: bool) -> Self {
+        self.prefix_filtering = prefix_filtering;
+        self
+    }
+
+    fn object_prefix(&self, query: &AssetQuery) -> Option<String> {
+        if !self.prefix_filtering {
+            return None;
+        }
+        query.layout_prefix()
+    }
+
+    /// Whether the object with this key needs to be described to evaluate the query.
+    fn may_match(&self, query: &AssetQuery, key: &str) -> bool {
+        !self.prefix_filtering || query.may_match_layout_path(key)
+    }
+
+    fn list_objects_url(&self, prefix: Option<&str>, token: Option<&str>) -> reqwest::Url {
+        let page_size = self.page_size.map(|page_size| page_size.to_string());
+        let params = [
+            Some(("list-type", "2")),
+            page_size
+                .as_deref()
+                .map(|page_size| ("max-keys", page_size)),
+            prefix.map(|prefix| ("prefix", prefix)),
+            token.map(|token| ("continuation-token", token)),
+        ];
+        self.bucket
+            .url(None, &params.into_iter().flatten().collect::<Vec<_>>())
+    }
+
+    /// The request that reads the attributes of an object.
+    fn attributes_request(&self, key: &str) -> (Method, reqwest::Url) {
+        match self.attribute_source {
+            S3AttributeSource::ObjectMetadata => (Method::HEAD, self.bucket.url(Some(key), &[])),
+            S3AttributeSource::ObjectTags => {
+                (Method::GET, self.bucket.url(Some(key), &[("tagging", "")]))
+            }
+        }
+    }
+
+    /// Follows continuation tokens until the listing is exhausted, describing each listed
+    /// object and evaluating the query against it.
+    fn collect_pages<L, A>(
+        &self,
+        query: &AssetQuery,
+        mut fetch_page: L,
+        mut fetch_attributes: A,
+    ) -> Result<Vec<AssetDescriptor>, ListAssetsError>
+    where
+        L: FnMut(Option<&str>) -> Result<ListObjectsResponse, ListAssetsError>,
+        A: FnMut(&str) -> Result<Option<Attributes>, ListAssetsError>,
+    {
+        let mut descriptors = vec![];
+        let mut token: Option<String> = None;
+        for _ in 0..self.max_pages {
+            let page = into_result(fetch_page(token.as_deref())?)?;
+            for object in page.contents.iter() {
+                if !self.may_match(query, &object.key) {
+                    continue;
+                }
+                if let Some(attributes) = fetch_attributes(&object.key)? {
+                    descriptors.extend(self.evaluate_object(query, object, &attributes));
+                }
+            }
+            token = page.continuation().map(|token| token.to_owned());
+            if token.is_none() {
+                return Ok(descriptors);
+            }
+        }
+        Err(self.too_many_pages())
+    }
+
+    fn evaluate_object(
+        &self,
+        query: &AssetQuery,
+        object: &Object,
+        attributes: &Attributes,
+    ) -> Option<AssetDescriptor> {
+        let name = attributes.get("name")?;
+        let version = attributes.get("version")?;
+        let content_hash = content_hash(attributes)?;
+        let mut locators = vec![self.locator_factory.get_locator(&object.key)];
+        locators.extend(
+            self.bucket
+                .object_uri(&object.key)
+                .map(crate::AssetLocator::new),
+        );
+        let descriptor = AssetDescriptor::new(
+            name,
+            SemVer::from_str(version).unwrap_or_default(),
+            &content_hash,
+            object.size,
+            locators,
+        );
+        Some(descriptor).filter(|descriptor| descriptor.matches_query(query))
+    }
+
+    fn too_many_pages(&self) -> ListAssetsError {
+        ListAssetsError::AssetIndexInternalError(format!(
+            "The listing didn't complete within {} pages.",
+            self.max_pages
+        ))
+    }
+
+    fn send(
+        &self,
+        client: &reqwest::blocking::Client,
+        method: Method,
+        url: reqwest::Url,
+    ) -> Result<reqwest::blocking::Response, ListAssetsError> {
+        let headers = self
+            .bucket
+            .authorization_headers(method.as_str(), &url, SystemTime::now());
+        headers
+            .into_iter()
+            .fold(client.request(method, url), |request, (name, value)| {
+                request.header(name, value)
+            })
+            .send()
+            .map_err(|_| no_response())
+    }
+
+    async fn send_async(
+        &self,
+        client: &reqwest::Client,
+        method: Method,
+        url: reqwest::Url,
+    ) -> Result<reqwest::Response, ListAssetsError> {
+        let headers = self
+            .bucket
+            .authorization_headers(method.as_str(), &url, SystemTime::now());
+        headers
+            .into_iter()
+            .fold(client.request(method, url), |request, (name, value)| {
+                request.header(name, value)
+            })
+            .send()
+            .await
+            .map_err(|_| no_response())
+    }
+
+    fn parse_attributes(
+        &self,
+        status: StatusCode,
+        headers: &HeaderMap,
+        body: &str,
+    ) -> Result<Option<Attributes>, ListAssetsError> {
+        // The object may have been deleted since it was listed.
+        if status == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        match self.attribute_source {
+            S3AttributeSource::ObjectMetadata => metadata_from_headers(status, headers).map(Some),
+            S3AttributeSource::ObjectTags => match parse_xml::<TaggingResponse>(body)? {
+                TaggingResponse::Tagging(tagging) => Ok(Some(
+                    tagging
+                        .tag_set
+                        .tags
+                        .into_iter()
+                        .map(|tag| (tag.key.to_ascii_lowercase(), tag.value))
+                        .collect(),
+                )),
+                TaggingResponse::Error(e) => Err(e.into()),
+            },
+        }
+    }
+}
+
+/// The strongest digest recorded on the object, tagged with its algorithm.
+fn content_hash(attributes: &Attributes) -> Option<String> {
+    [
+        (HashAlgorithm::Sha512, "sha512"),
+        (HashAlgorithm::Sha256, "sha256"),
+        (HashAlgorithm::Sha1, "sha1"),
+    ]
+    .into_iter()
+    .find_map(|(algorithm, key)| {
+        attributes
+            .get(key)
+            .map(|digest| format_content_hash(algorithm, digest))
+    })
+}
+
+/// HEAD responses have no body, so errors are told apart by their status.
+fn metadata_from_headers(
+    status: StatusCode,
+    headers: &HeaderMap,
+) -> Result<Attributes, ListAssetsError> {
+    match status {
+        StatusCode::FORBIDDEN | StatusCode::UNAUTHORIZED => {
+            return Err(ListAssetsError::AssetIndexAccessDenied(None))
+        }
+        status if status.is_server_error() => {
+            return Err(ListAssetsError::AssetIndexUnavailable(format!(
+                "Reading object metadata failed with status {status}."
+            )))
+        }
+        status if !status.is_success() => {
+            return Err(ListAssetsError::AssetIndexInternalError(format!(
+                "Reading object metadata failed with status {status}."
+            )))
+        }
+        _ => {}
+    }
+    Ok(headers
+        .iter()
+        .filter_map(|(name, value)| {
+            let key = name.as_str().strip_prefix(METADATA_HEADER_PREFIX)?;
+            Some((key.to_owned(), value.to_str().ok()?.to_owned()))
+        })
+        .collect())
+}
+
+fn into_result(page: ListObjectsResponse) -> Result<ListBucketResult, ListAssetsError> {
+    match page {
+        ListObjectsResponse::ListBucketResult(result) => Ok(result),
+        ListObjectsResponse::Error(e) => Err(e.into()),
+    }
+}
+
+fn parse_xml<'de, T: Deserialize<'de>>(response_text: &'de str) -> Result<T, ListAssetsError> {
+    from_str::<T>(response_text.trim_start_matches(|c| c != '<')).map_err(|parse_error| {
+        ListAssetsError::AssetIndexInternalError(format!(
+            "Failed to parse response. message: {} content: {}",
+            parse_error, response_text
+        ))
+    })
+}
+
+fn no_response() -> ListAssetsError {
+    ListAssetsError::AssetIndexUnavailable("No response from storage.".to_owned())
+}
+
+fn client_error(e: reqwest::Error) -> ListAssetsError {
+    ListAssetsError::MisconfiguredIndex(e.to_string())
+}
+
+fn empty_response() -> ListAssetsError {
+    ListAssetsError::AssetIndexInternalError("Storage response was empty.".to_owned())
+}
+
+impl AssetIndex for S3AssetIndex {
+    fn list_assets(&self, query: &AssetQuery) -> Result<Vec<AssetDescriptor>, ListAssetsError> {
+        let client = self.http_client.blocking().map_err(client_error)?;
+        let prefix = self.object_prefix(query);
+        self.collect_pages(
+            query,
+            |token| {
+                let url = self.list_objects_url(prefix.as_deref(), token);
+                let response = self.send(client, Method::GET, url)?;
+                parse_xml(&response.text().map_err(|_| empty_response())?)
+            },
+            |key| {
+                let (method, url) = self.attributes_request(key);
+                let response = self.send(client, method, url)?;
+                let (status, headers) = (response.status(), response.headers().clone());
+                let body = response.text().map_err(|_| empty_response())?;
+                self.parse_attributes(status, &headers, &body)
+            },
+        )
+    }
+}
+
+#[async_trait]
+impl AsyncAssetIndex for S3AssetIndex {
+    async fn list_assets(
+        &self,
+        query: &AssetQuery,
+    ) -> Result<Vec<AssetDescriptor>, ListAssetsError> {
+        let client = self.http_client.client().map_err(client_error)?;
+        let prefix = self.object_prefix(query);
+        let mut descriptors = vec![];
+        let mut token: Option<String> = None;
+        for _ in 0..self.max_pages {
+            let url = self.list_objects_url(prefix.as_deref(), token.as_deref());
+            let response = self.send_async(client, Method::GET, url).await?;
+            let page_text = response.text().await.map_err(|_| empty_response())?;
+            let page = into_result(parse_xml(&page_text)?)?;
+            for object in page.contents.iter() {
+                if !self.may_match(query, &object.key) {
+                    continue;
+                }
+                let (method, url) = self.attributes_request(&object.key);
+                let response = self.send_async(client, method, url).await?;
+                let (status, headers) = (response.status(), response.headers().clone());
+                let body = response.text().await.map_err(|_| empty_response())?;
+                if let Some(attributes) = self.parse_attributes(status, &headers, &body)? {
+                    descriptors.extend(self.evaluate_object(query, object, &attributes));
+                }
+            }
+            token = page.continuation().map(|token| token.to_owned());
+            if token.is_none() {
+                return Ok(descriptors);
+            }
+        }
+        Err(self.too_many_pages())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Attributes, ListObjectsResponse, S3AssetIndex, S3AttributeSource};
+    use crate::http::test_server::{TestResponse, TestServer};
+    use crate::http::{S3Bucket, S3Credentials};
+    use crate::{AssetIndex, AssetQuery, AsyncAssetIndex, ListAssetsError};
+    use quick_xml::de::from_str;
+    use reqwest::Url;
+
+    const PAGE_1: &str = include_str!("test_data/list_objects_page_1.xml");
+    const PAGE_2: &str = include_str!("test_data/list_objects_page_2.xml");
+    const TAGGING: &str = include_str!("test_data/object_tagging.xml");
+    const NO_SUCH_BUCKET: &str = include_str!("test_data/no_such_bucket.xml");
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    fn bucket(endpoint: &str) -> S3Bucket {
+        S3Bucket::new(
+            Url::parse(endpoint).unwrap(),
+            "us-east-1",
+            "assets",
+            S3Credentials::new("minioadmin", "minioadmin"),
+        )
+    }
+
+    fn recorded_page(token: Option<&str>) -> Result<ListObjectsResponse, ListAssetsError> {
+        let page = match token {
+            None => PAGE_1,
+            Some("1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=") => PAGE_2,
+            Some(t) => panic!("Unexpected continuation token {t}"),
+        };
+        Ok(from_str::<ListObjectsResponse>(page).unwrap())
+    }
+
+    /// Records the version in the key as the object's version, except for `.sig` files.
+    fn recorded_attributes(key: &str) -> Result<Option<Attributes>, ListAssetsError> {
+        let parts: Vec<_> = key.split('/').collect();
+        if key.ends_with(".sig") {
+            return Ok(Some(Attributes::new()));
+        }
+        Ok(Some(Attributes::from([
+            ("name".to_owned(), parts[0].to_owned()),
+            ("version".to_owned(), parts[1].to_owned()),
+            ("sha256".to_owned(), HELLO_SHA256.to_owned()),
+        ])))
+    }
+
+    #[test]
+    fn follows_continuation_tokens() {
+        let index = S3AssetIndex::new(bucket("https://s3.amazonaws.com")).with_page_size(2);
+        let query = AssetQuery::new_from_strings("simple_test", &None).unwrap();
+        let mut tokens = vec![];
+        let descriptors = index
+            .collect_pages(
+                &query,
+                |token| {
+                    tokens.push(token.map(|t| t.to_owned()));
+                    recorded_page(token)
+                },
+                recorded_attributes,
+            )
+            .unwrap();
+        assert_eq!(tokens.len(), 2);
+        let versions: Vec<_> = descriptors.iter().map(|d| d.version.to_string()).collect();
+        assert_eq!(versions, vec!["1.0.0", "2.0.0", "3.0.0"]);
+        assert_eq!(descriptors[0].size, 266);
+        assert_eq!(
+            descriptors[0].locators[1].url.as_str(),
+            "s3://assets/simple_test/1.0.0/asset.tar.gz"
+        );
+    }
+
+    #[test]
+    fn describes_only_objects_that_could_match() {
+        let index = S3AssetIndex::new(bucket("https://s3.amazonaws.com"));
+        let query = AssetQuery::new_from_strings("simple_test", &Some("2".to_owned())).unwrap();
+        let mut described = vec![];
+        let descriptors = index
+            .collect_pages(&query, recorded_page, |key| {
+                described.push(key.to_owned());
+                recorded_attributes(key)
+            })
+            .unwrap();
+        assert_eq!(described, vec!["simple_test/2.0.0/asset.tar.gz"]);
+        assert_eq!(descriptors.len(), 1);
+
+        let index = index.with_prefix_filtering(false);
+        let mut described = 0;
+        index
+            .collect_pages(&query, recorded_page, |key| {
+                described += 1;
+                recorded_attributes(key)
+            })
+            .unwrap();
+        assert_eq!(described, 4);
+    }
+
+    #[test]
+    fn page_limit_is_enforced() {
+        let index = S3AssetIndex::new(bucket("https://s3.amazonaws.com")).with_max_pages(1);
+        let query = AssetQuery::new_from_strings("simple_test", &None).unwrap();
+        assert!(matches!(
+            index.collect_pages(&query, recorded_page, recorded_attributes),
+            Err(ListAssetsError::AssetIndexInternalError(_))
+        ));
+    }
+
+    #[test]
+    fn list_objects_url_includes_paging_parameters() {
+        let index = S3AssetIndex::new(bucket("http://localhost:9000")).with_page_size(2);
+        assert_eq!(
+            index
+                .list_objects_url(Some("simple test/1."), Some("1ueGc+/="))
+                .as_str(),
+            "http://localhost:9000/assets?continuation-token=1ueGc%2B%2F%3D&list-type=2&max-keys=2&prefix=simple%20test%2F1."
+        );
+    }
+
+    #[test]
+    fn maps_storage_errors() {
+        let index = S3AssetIndex::new(bucket("https://s3.amazonaws.com"));
+        let query = AssetQuery::new_from_strings("simple_test", &None).unwrap();
+        let result = index.collect_pages(
+            &query,
+            |_| Ok(from_str::<ListObjectsResponse>(NO_SUCH_BUCKET).unwrap()),
+            recorded_attributes,
+        );
+        assert!(matches!(
+            result,
+            Err(ListAssetsError::AssetIndexNotFound(Some(_)))
+        ));
+    }
+
+    /// Answers like a MinIO server holding `hello/1.0.0/hello.txt`, and checks that every
+    /// request is signed.
+    fn start_storage() -> TestServer {
+        TestServer::start(|request| {
+            if !request
+                .header("authorization")
+                .is_some_and(|auth| auth.starts_with("AWS4-HMAC-SHA256 Credential=minioadmin/"))
+            {
+                return TestResponse::new(403, "");
+            }
+            match (request.method.as_str(), request.target.as_str()) {
+                ("GET", "/assets?list-type=2&prefix=hello%2F") => TestResponse::new(
+                    200,
+                    r#"<?xml version="1.0" encoding="UTF-8"?>
+                    <ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+                        <Name>assets</Name><Prefix>hello/</Prefix><KeyCount>1</KeyCount>
+                        <MaxKeys>1000</MaxKeys><IsTruncated>false</IsTruncated>
+                        <Contents><Key>hello/1.0.0/hello.txt</Key><Size>5</Size></Contents>
+                    </ListBucketResult>"#,
+                ),
+                ("HEAD", "/assets/hello/1.0.0/hello.txt") => TestResponse::new(200, "")
+                    .with_header("x-amz-meta-name", "hello")
+                    .with_header("x-amz-meta-version", "1.0.0")
+                    .with_header("x-amz-meta-sha256", HELLO_SHA256),
+                ("GET", "/assets/hello/1.0.0/hello.txt?tagging=") => {
+                    TestResponse::new(200, TAGGING)
+                }
+                _ => TestResponse::new(404, ""),
+            }
+        })
+    }
+
+    #[test]
+    fn lists_assets_from_a_local_storage_server() {
+        let server = start_storage();
+        let query = AssetQuery::new_from_strings("hello", &None).unwrap();
+        for source in [
+            S3AttributeSource::ObjectMetadata,
+            S3AttributeSource::ObjectTags,
+        ] {
+            let index =
+                S3AssetIndex::new(bucket(server.url("/").as_str())).with_attribute_source(source);
+            let descriptors = AssetIndex::list_assets(&index, &query).unwrap();
+            assert_eq!(descriptors.len(), 1, "{source:?}");
+            assert_eq!(descriptors[0].version.to_string(), "1.0.0");
+            assert_eq!(
+                descriptors[0].content_hash,
+                format!("sha256:{HELLO_SHA256}")
+            );
+            let presigned = &descriptors[0].locators[0].url;
+            assert_eq!(presigned.path(), "/assets/hello/1.0.0/hello.txt");
+            assert!(presigned
+                .query()
+                .is_some_and(|query| query.contains("X-Amz-Signature=")));
+        }
+    }
+
+    #[tokio::test]
+    async fn lists_assets_asynchronously() {
+        let server = start_storage();
+        let index = S3AssetIndex::new(bucket(server.url("/").as_str()));
+        let query = AssetQuery::new_from_strings("hello", &None).unwrap();
+        assert_eq!(
+            AsyncAssetIndex::list_assets(&index, &query)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,25 @@
+use crate::http::S3Bucket;
+use crate::AssetLocator;
+use std::time::{Duration, SystemTime};
+
+/// Locates objects with presigned URLs, so that clients can download assets straight from
+/// the bucket without credentials of their own. Presigned URLs stop working once they
+/// expire, so the expiry should outlast the time descriptors are cached for.
+pub struct S3PresignedUrlLocatorFactory {
+    bucket: S3Bucket,
+    expires_in: Duration,
+}
+
+impl S3PresignedUrlLocatorFactory {
+    pub fn new(bucket: S3Bucket, expires_in: Duration) -> Self {
+        S3PresignedUrlLocatorFactory { bucket, expires_in }
+    }
+
+    pub fn get_locator(&self, key: &str) -> AssetLocator {
+        let url = self.bucket.url(Some(key), &[]);
+        AssetLocator::new(
+            self.bucket
+                .presign(&url, self.expires_in, SystemTime::now()),
+        )
+    }
+}
@@ -0,0 +1,215 @@
+use crate::asset_store::is_transient_status;
+use crate::http::s3_bucket::AuthorizationHeaders;
+use crate::http::{HttpClient, S3Bucket};
+use crate::{
+    AssetLocator, AssetPayload, AssetStore, AssetStoreError, AsyncAssetPayload, AsyncAssetStore,
+    AsyncHashValidatingReader, HashValidatingReader, RetryPolicy,
+};
+use async_trait::async_trait;
+use futures_util::TryStreamExt;
+use reqwest::{Method, StatusCode, Url};
+use std::time::SystemTime;
+use tokio_util::io::StreamReader;
+
+/// Fetches assets located by `s3://bucket/key` URIs from a bucket of an S3-compatible
+/// storage service, signing each request with the bucket's credentials. Presigned URLs
+/// need no credentials and are fetched by [crate::http::HttpAsssetStore].
+pub struct S3AssetStore {
+    bucket: S3Bucket,
+    retry_policy: RetryPolicy,
+    http_client: HttpClient,
+}
+
+impl S3AssetStore {
+    pub fn new(bucket: S3Bucket) -> Self {
+        S3AssetStore {
+            bucket,
+            retry_policy: RetryPolicy::default(),
+            http_client: HttpClient::default(),
+        }
+    }
+
+    pub fn with_http_client(mut self, http_client: HttpClient) -> Self {
+        self.http_client = http_client;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// The signed request for the object a locator refers to.
+    fn object_request(
+        &self,
+        locator: &AssetLocator,
+    ) -> Result<(Url, AuthorizationHeaders), AssetStoreError> {
+        let key = match self.bucket.key_of(&locator.url) {
+            Some(key) => key,
+            None if locator.url.scheme() != "s3" => {
+                return Err(AssetStoreError::UnsupportedScheme(
+                    locator.url.scheme().to_owned(),
+                ))
+            }
+            None => return Err(AssetStoreError::NoSupportedLocator),
+        };
+        let url = self.bucket.url(Some(&key), &[]);
+        let headers =
+            self.bucket
+                .authorization_headers(Method::GET.as_str(), &url, SystemTime::now());
+        Ok((url, headers))
+    }
+}
+
+fn status_error(status: StatusCode) -> AssetStoreError {
+    let message = format!("Fetching the object failed with status {status}.");
+    if is_transient_status(status) {
+        AssetStoreError::AssetStoreUnavailable(message)
+    } else {
+        AssetStoreError::AssetStoreInternalError(message)
+    }
+}
+
+impl AssetStore for S3AssetStore {
+    fn supports_locator(&self, locator: &AssetLocator) -> bool {
+        self.bucket.key_of(&locator.url).is_some()
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy.clone()
+    }
+
+    fn fetch_by_locator(
+        &self,
+        locator: &AssetLocator,
+        expected_hash: &str,
+    ) -> Result<AssetPayload, AssetStoreError> {
+        let (url, headers) = self.object_request(locator)?;
+        let client = self.http_client.blocking()?;
+        let resp = headers
+            .into_iter()
+            .fold(client.get(url), |request, (name, value)| {
+                request.header(name, value)
+            })
+            .send()
+            .map_err(AssetStoreError::from)?;
+        if !resp.status().is_success() {
+            return Err(status_error(resp.status()));
+        }
+        Ok(AssetPayload::Stream(Box::new(HashValidatingReader::new(
+            resp,
+            expected_hash,
+        ))))
+    }
+}
+
+#[async_trait]
+impl AsyncAssetStore for S3AssetStore {
+    fn supports_locator(&self, locator: &AssetLocator) -> bool {
+        self.bucket.key_of(&locator.url).is_some()
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy.clone()
+    }
+
+    async fn fetch_by_locator(
+        &self,
+        locator: &AssetLocator,
+        expected_hash: &str,
+    ) -> Result<AsyncAssetPayload, AssetStoreError> {
+        let (url, headers) = self.object_request(locator)?;
+        let resp = headers
+            .into_iter()
+            .fold(
+                self.http_client.client()?.get(url),
+                |request, (name, value)| request.header(name, value),
+            )
+            .send()
+            .await
+            .map_err(AssetStoreError::from)?;
+        if !resp.status().is_success() {
+            return Err(status_error(resp.status()));
+        }
+        let body = resp.bytes_stream().map_err(std::io::Error::other);
+        Ok(AsyncAssetPayload::Stream(Box::pin(
+            AsyncHashValidatingReader::new(StreamReader::new(body), expected_hash),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::S3AssetStore;
+    use crate::http::test_server::{TestResponse, TestServer};
+    use crate::http::{S3Bucket, S3Credentials};
+    use crate::{AssetLocator, AssetStore, AssetStoreError, AsyncAssetStore};
+    use reqwest::Url;
+    use std::str::FromStr;
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    fn store(server: &TestServer) -> S3AssetStore {
+        S3AssetStore::new(S3Bucket::new(
+            server.url("/"),
+            "us-east-1",
+            "assets",
+            S3Credentials::new("minioadmin", "minioadmin"),
+        ))
+    }
+
+    fn locator(uri: &str) -> AssetLocator {
+        AssetLocator::new(Url::from_str(uri).unwrap())
+    }
+
+    fn start_storage() -> TestServer {
+        TestServer::start(|request| {
+            match (request.header("authorization"), request.target.as_str()) {
+                (None, _) => TestResponse::new(403, ""),
+                (Some(_), "/assets/hello%20world/1.0.0/hello.txt") => {
+                    TestResponse::new(200, "hello")
+                }
+                _ => TestResponse::new(404, ""),
+            }
+        })
+    }
+
+    #[test]
+    fn fetches_objects_in_its_bucket() {
+        let server = start_storage();
+        let store = store(&server);
+        assert!(!AssetStore::supports_locator(
+            &store,
+            &locator("s3://other/hello.txt")
+        ));
+        assert!(!AssetStore::supports_locator(
+            &store,
+            &locator("https://example.com/hello.txt")
+        ));
+
+        let payload = AssetStore::fetch_by_locator(
+            &store,
+            &locator("s3://assets/hello%20world/1.0.0/hello.txt"),
+            HELLO_SHA256,
+        )
+        .unwrap();
+        assert_eq!(payload.into_bytes().unwrap(), b"hello");
+        assert!(matches!(
+            AssetStore::fetch_by_locator(&store, &locator("s3://assets/missing"), HELLO_SHA256),
+            Err(AssetStoreError::AssetStoreInternalError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn fetches_objects_asynchronously() {
+        let server = start_storage();
+        let payload = AsyncAssetStore::fetch_by_locator(
+            &store(&server),
+            &locator("s3://assets/hello%20world/1.0.0/hello.txt"),
+            HELLO_SHA256,
+        )
+        .await
+        .unwrap();
+        assert_eq!(payload.into_bytes().await.unwrap(), b"hello");
+    }
+}
@@ -0,0 +1,133 @@
+use reqwest::Url;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+
+/// A request received by the [TestServer].
+#[derive(Clone, Debug)]
+pub(crate) struct TestRequest {
+    pub(crate) method: String,
+    /// The path and query, as sent.
+    pub(crate) target: String,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+}
+
+impl TestRequest {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A canned response of the [TestServer].
+pub(crate) struct TestResponse {
+    pub(crate) status: u16,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+}
+
+impl TestResponse {
+    pub(crate) fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        TestResponse {
+            status,
+            headers: vec![],
+            body: body.into(),
+        }
+    }
+
+    pub(crate) fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+}
+
+/// A minimal HTTP/1.1 server on a local port that answers each request with whatever the
+/// handler returns, standing in for the services the HTTP components talk to. Every
+/// connection serves a single request. The server runs until the test process exits.
+pub(crate) struct TestServer {
+    url: Url,
+}
+
+impl TestServer {
+    pub(crate) fn start<F>(handler: F) -> Self
+    where
+        F: Fn(&TestRequest) -> TestResponse + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let handler = Arc::new(handler);
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let handler = handler.clone();
+                std::thread::spawn(move || serve(stream, handler.as_ref()));
+            }
+        });
+        TestServer { url }
+    }
+
+    /// The server's URL, with the given path.
+    pub(crate) fn url(&self, path: &str) -> Url {
+        self.url.join(path).unwrap()
+    }
+}
+
+fn serve(stream: TcpStream, handler: &dyn Fn(&TestRequest) -> TestResponse) {
+    let mut reader = BufReader::new(stream);
+    let request = match read_request(&mut reader) {
+        Some(request) => request,
+        None => return,
+    };
+    let response = handler(&request);
+    let mut head = format!("HTTP/1.1 {} Test\r\n", response.status);
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    if !response
+        .headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+    {
+        head.push_str(&format!("content-length: {}\r\n", response.body.len()));
+    }
+    head.push_str("connection: close\r\n\r\n");
+    let mut stream = reader.into_inner();
+    let _ = stream.write_all(head.as_bytes());
+    if request.method != "HEAD" {
+        let _ = stream.write_all(&response.body);
+    }
+}
+
+fn read_request(reader: &mut BufReader<TcpStream>) -> Option<TestRequest> {
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_owned();
+    let target = parts.next()?.to_owned();
+    let mut headers = vec![];
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':')?;
+        headers.push((name.trim().to_owned(), value.trim().to_owned()));
+    }
+    let mut request = TestRequest {
+        method,
+        target,
+        headers,
+        body: vec![],
+    };
+    let length: usize = request
+        .header("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or_default();
+    request.body.resize(length, 0);
+    reader.read_exact(&mut request.body).ok()?;
+    Some(request)
+}
@@ -1,15 +1,29 @@
+mod adapters;
 mod asset_descriptor;
 mod asset_index;
+mod asset_publisher;
 mod asset_store;
 mod constraints;
+mod content_hash;
+mod failover;
 pub mod filesystem;
 pub mod http;
 pub mod memory;
 mod regexes;
+mod resolution;
 mod semver;
 
-pub use asset_descriptor::{AssetDescriptor, AssetLocator};
-pub use asset_index::{AssetIndex, ListAssetsError};
-pub use asset_store::{validate_hash, AssetPayload, AssetStore, AssetStoreError};
+pub use adapters::{AsyncAdapter, BlockingAdapter};
+pub use asset_descriptor::{redact_url, AssetDescriptor, AssetLocator};
+pub use asset_index::{AssetIndex, AsyncAssetIndex, Freshness, ListAssetsError};
+pub use asset_publisher::{validate_asset_name, AssetPublisher, PublishAssetError};
+pub use asset_store::{
+    validate_hash, AssetPayload, AssetStore, AssetStoreError, AsyncAssetPayload, AsyncAssetStore,
+    AsyncHashValidatingReader, HashValidatingReader,
+};
 pub use constraints::{AssetQuery, ConstraintParsingError, NameConstraint, VersionConstraint};
+pub use content_hash::{format_content_hash, parse_content_hash, ContentHasher, HashAlgorithm};
+pub use failover::{FailedAttempt, RetryPolicy};
+pub use resolution::{resolve_latest, resolve_latest_async, select_latest, ResolutionOptions};
+pub use reqwest::Url;
 pub use semver::{SemVer, SemVerParseEror};
@@ -1,33 +1,346 @@
-use crate::{AssetDescriptor, AssetIndex, AssetQuery, ListAssetsError};
-use std::cell::RefCell;
+use crate::{AssetDescriptor, AssetIndex, AssetQuery, AsyncAssetIndex, Freshness, ListAssetsError};
+use async_trait::async_trait;
 use std::collections::HashMap;
-use std::time::{Duration, SystemTime};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+type QueryResult = Result<Vec<AssetDescriptor>, ListAssetsError>;
 
 struct MemoryCacheEntry {
     descriptor: Vec<AssetDescriptor>,
-    last_modified: SystemTime,
+    size: usize,
+    last_modified: Instant,
+    last_used: Instant,
 }
 
-pub struct MemoryAssetIndexCache<TInnerIndex>
-where
-    TInnerIndex: AssetIndex,
-{
-    descriptors: RefCell<HashMap<AssetQuery, MemoryCacheEntry>>,
+/// A query sent to the inner index that other callers can wait on.
+#[derive(Default)]
+struct PendingQuery {
+    result: Mutex<Option<QueryResult>>,
+    completed: Condvar,
+    completed_async: Notify,
+}
+
+impl PendingQuery {
+    fn complete(&self, result: QueryResult) {
+        *lock(&self.result) = Some(result);
+        self.completed.notify_all();
+        self.completed_async.notify_waiters();
+    }
+
+    fn wait(&self) -> QueryResult {
+        let mut result = lock(&self.result);
+        loop {
+            match result.as_ref() {
+                Some(result) => return result.clone(),
+                None => {
+                    result = self
+                        .completed
+                        .wait(result)
+                        .unwrap_or_else(|e| e.into_inner())
+                }
+            }
+        }
+    }
+
+    async fn wait_async(&self) -> QueryResult {
+        loop {
+            // Registers for the notification before checking the result, so a completion in
+            // between isn't missed.
+            let notified = self.completed_async.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if let Some(result) = lock(&self.result).as_ref() {
+                return result.clone();
+            }
+            notified.await;
+        }
+    }
+}
+
+/// How a query is answered: from the cache, by waiting for an identical query that is
+/// already running, or by the caller sending it to the inner index.
+enum Lookup {
+    Cached(Vec<AssetDescriptor>),
+    Pending(Arc<PendingQuery>),
+    Leader(Arc<PendingQuery>),
+}
+
+/// Completes a pending query even if the inner index panics, so that waiting callers are
+/// released instead of blocking forever.
+struct PendingQueryGuard<'a, TInnerIndex> {
+    cache: &'a MemoryAssetIndexCache<TInnerIndex>,
+    query: &'a AssetQuery,
+    pending: Arc<PendingQuery>,
+    result: Option<QueryResult>,
+}
+
+impl<'a, TInnerIndex> Drop for PendingQueryGuard<'a, TInnerIndex> {
+    fn drop(&mut self) {
+        let result = self.result.take().unwrap_or_else(|| {
+            Err(ListAssetsError::AssetIndexInternalError(
+                "The query failed while other callers were waiting for it.".to_owned(),
+            ))
+        });
+        {
+            let mut state = lock(&self.cache.state);
+            state.pending.remove(self.query);
+            if let Ok(descriptors) = &result {
+                self.cache.insert(&mut state, self.query, descriptors);
+            }
+        }
+        self.pending.complete(result);
+    }
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<AssetQuery, MemoryCacheEntry>,
+    pending: HashMap<AssetQuery, Arc<PendingQuery>>,
+    total_size: usize,
+}
+
+/// Caches query results in memory. The cache can be shared between threads: concurrent
+/// callers with the same query wait for a single call to the inner index instead of each
+/// sending their own, and queries covered by a cached broader query are answered from its
+/// results. Entries expire after `max_age`, and the least recently used entries
+/// are evicted once the configured capacity is exceeded. Failed queries aren't cached.
+/// The cache is an async index when the inner index is.
+pub struct MemoryAssetIndexCache<TInnerIndex> {
+    state: Mutex<CacheState>,
     max_age: Duration,
+    stale_if_error: Duration,
+    offline: bool,
+    max_entries: Option<usize>,
+    max_bytes: Option<usize>,
     inner_index: TInnerIndex,
 }
 
-impl<TInnerIndex> MemoryAssetIndexCache<TInnerIndex>
-where
-    TInnerIndex: AssetIndex,
-{
+impl<TInnerIndex> MemoryAssetIndexCache<TInnerIndex> {
     pub fn new(max_age: Duration, inner_index: TInnerIndex) -> Self {
         MemoryAssetIndexCache {
-            descriptors: RefCell::new(HashMap::new()),
+            state: Mutex::new(CacheState::default()),
             max_age,
+            stale_if_error: Duration::ZERO,
+            offline: false,
+            max_entries: None,
+            max_bytes: None,
             inner_index,
         }
     }
+
+    /// Bounds the number of cached queries.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Bounds the approximate memory used by the cached descriptors.
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// When the inner index is unavailable, serves cached results that expired less than
+    /// `stale_if_error` ago instead of failing. Expired results are only kept until they
+    /// are evicted to make room for others.
+    pub fn with_stale_if_error(mut self, stale_if_error: Duration) -> Self {
+        self.stale_if_error = stale_if_error;
+        self
+    }
+
+    /// An offline cache never calls the inner index. It answers from the cache whatever the
+    /// age of the cached results, and fails for queries it holds no results for.
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    fn begin(&self, query: &AssetQuery) -> Lookup {
+        let mut state = lock(&self.state);
+        if let Some((descriptors, Freshness::Fresh)) =
+            self.cached(&mut state, query, Some(Duration::ZERO))
+        {
+            return Lookup::Cached(descriptors);
+        }
+        if let Some(pending) = state.pending.get(query) {
+            return Lookup::Pending(pending.clone());
+        }
+        let pending = Arc::new(PendingQuery::default());
+        state.pending.insert(query.clone(), pending.clone());
+        Lookup::Leader(pending)
+    }
+
+    /// Looks up the cached results for the query. When the query itself isn't cached, the
+    /// results are filtered from the most recent cached query that covers it. Results past
+    /// the max age are only returned if they expired less than `max_staleness` ago, or
+    /// whatever their age when `max_staleness` is `None`.
+    fn cached(
+        &self,
+        state: &mut CacheState,
+        query: &AssetQuery,
+        max_staleness: Option<Duration>,
+    ) -> Option<(Vec<AssetDescriptor>, Freshness)> {
+        let freshness = |entry: &MemoryCacheEntry| {
+            Freshness::of(entry.last_modified.elapsed(), self.max_age, max_staleness)
+        };
+        let cached_query = match state.entries.get(query) {
+            Some(entry) if freshness(entry).is_some() => query.clone(),
+            _ => state
+                .entries
+                .iter()
+                .filter(|(cached_query, entry)| {
+                    freshness(entry).is_some() && query.is_covered_by(cached_query)
+                })
+                .max_by_key(|(_, entry)| entry.last_modified)
+                .map(|(cached_query, _)| cached_query.clone())?,
+        };
+
+        let entry = state.entries.get_mut(&cached_query)?;
+        let freshness = freshness(entry)?;
+        entry.last_used = Instant::now();
+        let descriptors = if cached_query == *query {
+            entry.descriptor.clone()
+        } else {
+            entry
+                .descriptor
+                .iter()
+                .filter(|descriptor| descriptor.matches_query(query))
+                .cloned()
+                .collect()
+        };
+        Some((descriptors, freshness))
+    }
+
+    fn answer_offline(
+        &self,
+        query: &AssetQuery,
+    ) -> Result<(Vec<AssetDescriptor>, Freshness), ListAssetsError> {
+        self.cached(&mut lock(&self.state), query, None)
+            .ok_or_else(ListAssetsError::not_cached_offline)
+    }
+
+    /// Falls back to stale results when the inner index is unavailable.
+    fn fall_back(
+        &self,
+        query: &AssetQuery,
+        result: QueryResult,
+    ) -> Result<(Vec<AssetDescriptor>, Freshness), ListAssetsError> {
+        match result {
+            Ok(descriptors) => Ok((descriptors, Freshness::Fresh)),
+            Err(e) if e.is_outage() => self
+                .cached(&mut lock(&self.state), query, Some(self.stale_if_error))
+                .ok_or(e),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn insert(&self, state: &mut CacheState, query: &AssetQuery, descriptors: &[AssetDescriptor]) {
+        let now = Instant::now();
+        let size = approximate_size(query, descriptors);
+        if self.max_bytes.is_some_and(|max_bytes| size > max_bytes) {
+            return;
+        }
+        let previous = state.entries.insert(
+            query.clone(),
+            MemoryCacheEntry {
+                descriptor: descriptors.to_vec(),
+                size,
+                last_modified: now,
+                last_used: now,
+            },
+        );
+        state.total_size += size;
+        if let Some(previous) = previous {
+            state.total_size -= previous.size;
+        }
+
+        while self.is_over_capacity(state) {
+            let least_recently_used = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(query, _)| query.clone());
+            match least_recently_used.and_then(|query| state.entries.remove(&query)) {
+                Some(evicted) => state.total_size -= evicted.size,
+                None => break,
+            }
+        }
+    }
+
+    fn is_over_capacity(&self, state: &CacheState) -> bool {
+        self.max_entries
+            .is_some_and(|max_entries| state.entries.len() > max_entries)
+            || self
+                .max_bytes
+                .is_some_and(|max_bytes| state.total_size > max_bytes)
+    }
+}
+
+impl<TInnerIndex> MemoryAssetIndexCache<TInnerIndex>
+where
+    TInnerIndex: AssetIndex,
+{
+    /// Lists the assets like `list_assets`, and tells whether the results are fresh or were
+    /// served stale.
+    pub fn list_assets_with_freshness(
+        &self,
+        query: &AssetQuery,
+    ) -> Result<(Vec<AssetDescriptor>, Freshness), ListAssetsError> {
+        if self.offline {
+            return self.answer_offline(query);
+        }
+        let result = match self.begin(query) {
+            Lookup::Cached(descriptors) => return Ok((descriptors, Freshness::Fresh)),
+            Lookup::Pending(pending) => pending.wait(),
+            Lookup::Leader(pending) => {
+                let mut guard = PendingQueryGuard {
+                    cache: self,
+                    query,
+                    pending,
+                    result: None,
+                };
+                let result = self.inner_index.list_assets(query);
+                guard.result = Some(result.clone());
+                result
+            }
+        };
+        self.fall_back(query, result)
+    }
+}
+
+impl<TInnerIndex> MemoryAssetIndexCache<TInnerIndex>
+where
+    TInnerIndex: AsyncAssetIndex,
+{
+    /// The async counterpart of `list_assets_with_freshness`.
+    pub async fn list_assets_with_freshness_async(
+        &self,
+        query: &AssetQuery,
+    ) -> Result<(Vec<AssetDescriptor>, Freshness), ListAssetsError> {
+        if self.offline {
+            return self.answer_offline(query);
+        }
+        let result = match self.begin(query) {
+            Lookup::Cached(descriptors) => return Ok((descriptors, Freshness::Fresh)),
+            Lookup::Pending(pending) => pending.wait_async().await,
+            Lookup::Leader(pending) => {
+                // The guard also completes the query when this future is dropped before
+                // finishing.
+                let mut guard = PendingQueryGuard {
+                    cache: self,
+                    query,
+                    pending,
+                    result: None,
+                };
+                let result = self.inner_index.list_assets(query).await;
+                guard.result = Some(result.clone());
+                result
+            }
+        };
+        self.fall_back(query, result)
+    }
 }
 
 impl<TInnerIndex> AssetIndex for MemoryAssetIndexCache<TInnerIndex>
@@ -35,30 +348,204 @@ where
     TInnerIndex: AssetIndex,
 {
     fn list_assets(&self, query: &AssetQuery) -> Result<Vec<AssetDescriptor>, ListAssetsError> {
-        if let Some(entry) = self.descriptors.borrow().get(query) {
-            if SystemTime::now()
-                .duration_since(entry.last_modified)
-                .unwrap_or(self.max_age)
-                < self.max_age
-            {
-                return Ok(entry.descriptor.clone());
+        self.list_assets_with_freshness(query)
+            .map(|(descriptors, _)| descriptors)
+    }
+}
+
+#[async_trait]
+impl<TInnerIndex> AsyncAssetIndex for MemoryAssetIndexCache<TInnerIndex>
+where
+    TInnerIndex: AsyncAssetIndex,
+{
+    async fn list_assets(
+        &self,
+        query: &AssetQuery,
+    ) -> Result<Vec<AssetDescriptor>, ListAssetsError> {
+        self.list_assets_with_freshness_async(query)
+            .await
+            .map(|(descriptors, _)| descriptors)
+    }
+}
+
+/// A poisoned lock only means another caller panicked, and the cache state is still
+/// consistent, so the cache keeps working.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn approximate_size(query: &AssetQuery, descriptors: &[AssetDescriptor]) -> usize {
+    std::mem::size_of::<AssetQuery>()
+        + query.name_constraint.to_string().len()
+        + query
+            .version_constraint
+            .as_ref()
+            .map_or(0, |constraint| constraint.to_string().len())
+        + descriptors
+            .iter()
+            .map(|descriptor| {
+                std::mem::size_of::<AssetDescriptor>()
+                    + descriptor.name.len()
+                    + descriptor.content_hash.len()
+                    + descriptor
+                        .locators
+                        .iter()
+                        .map(|locator| locator.url.as_str().len())
+                        .sum::<usize>()
+            })
+            .sum::<usize>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryAssetIndexCache;
+    use crate::{AssetDescriptor, AssetIndex, AssetQuery, Freshness, ListAssetsError, SemVer};
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Barrier};
+    use std::time::Duration;
+
+    /// Answers every query with a single descriptor named after the query, and counts how
+    /// often it was asked.
+    #[derive(Default)]
+    struct CountingIndex {
+        calls: AtomicUsize,
+        delay: Duration,
+        fail: AtomicBool,
+    }
+
+    impl AssetIndex for CountingIndex {
+        fn list_assets(&self, query: &AssetQuery) -> Result<Vec<AssetDescriptor>, ListAssetsError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            std::thread::sleep(self.delay);
+            if self.fail.load(Ordering::SeqCst) {
+                return Err(ListAssetsError::AssetIndexUnavailable("down".to_owned()));
             }
+            Ok(vec![AssetDescriptor::new(
+                &query.name_constraint.to_string(),
+                SemVer::from_str("1.0.0").unwrap(),
+                "abc",
+                3,
+                vec![],
+            )])
         }
+    }
 
-        match self.inner_index.list_assets(query) {
-            Ok(results) => {
-                if let Ok(mut borrowed_descriptors) = self.descriptors.try_borrow_mut() {
-                    borrowed_descriptors.insert(
-                        query.clone(),
-                        MemoryCacheEntry {
-                            descriptor: results.clone(),
-                            last_modified: SystemTime::now(),
-                        },
-                    );
-                }
-                Ok(results)
-            }
-            Err(e) => Err(e),
+    fn query(name: &str) -> AssetQuery {
+        AssetQuery::new_from_strings(name, &None).unwrap()
+    }
+
+    /// Answers queries from a fixed set of assets.
+    #[derive(Default)]
+    struct CatalogIndex {
+        calls: AtomicUsize,
+    }
+
+    impl AssetIndex for CatalogIndex {
+        fn list_assets(&self, query: &AssetQuery) -> Result<Vec<AssetDescriptor>, ListAssetsError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok([
+                ("foo", "1.0.0"),
+                ("foo", "1.2.0"),
+                ("foo.bar", "1.2.0"),
+                ("bar", "1.0.0"),
+            ]
+            .into_iter()
+            .map(|(name, version)| {
+                AssetDescriptor::new(name, SemVer::from_str(version).unwrap(), "abc", 3, vec![])
+            })
+            .filter(|descriptor| descriptor.matches_query(query))
+            .collect())
+        }
+    }
+
+    #[test]
+    fn answers_covered_queries_from_broader_results() {
+        let cache = MemoryAssetIndexCache::new(Duration::from_secs(3600), CatalogIndex::default());
+        assert_eq!(cache.list_assets(&query("foo*")).unwrap().len(), 3);
+
+        let narrower = AssetQuery::new_from_strings("foo", &Some("1.2".to_owned())).unwrap();
+        let descriptors = cache.list_assets(&narrower).unwrap();
+        assert_eq!(descriptors.len(), 1);
+        assert_eq!(descriptors[0].version, SemVer::from_str("1.2.0").unwrap());
+        assert_eq!(cache.list_assets(&query("foo.bar")).unwrap().len(), 1);
+        assert_eq!(cache.inner_index.calls.load(Ordering::SeqCst), 1);
+
+        // Nothing cached covers this one.
+        assert_eq!(cache.list_assets(&query("bar")).unwrap().len(), 1);
+        assert_eq!(cache.inner_index.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn serves_fresh_entries_and_refreshes_expired_ones() {
+        let cache = MemoryAssetIndexCache::new(Duration::from_secs(3600), CountingIndex::default());
+        cache.list_assets(&query("a")).unwrap();
+        cache.list_assets(&query("a")).unwrap();
+        assert_eq!(cache.inner_index.calls.load(Ordering::SeqCst), 1);
+
+        let cache = MemoryAssetIndexCache::new(Duration::ZERO, CountingIndex::default());
+        cache.list_assets(&query("a")).unwrap();
+        cache.list_assets(&query("a")).unwrap();
+        assert_eq!(cache.inner_index.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn evicts_least_recently_used_entries() {
+        let cache = MemoryAssetIndexCache::new(Duration::from_secs(3600), CountingIndex::default())
+            .with_max_entries(2);
+        cache.list_assets(&query("a")).unwrap();
+        cache.list_assets(&query("b")).unwrap();
+        cache.list_assets(&query("a")).unwrap();
+        cache.list_assets(&query("c")).unwrap();
+        assert_eq!(cache.inner_index.calls.load(Ordering::SeqCst), 3);
+
+        // "b" was the least recently used, so it's the one that has to be fetched again.
+        cache.list_assets(&query("a")).unwrap();
+        cache.list_assets(&query("b")).unwrap();
+        assert_eq!(cache.inner_index.calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn serves_stale_entries_when_the_index_fails_or_the_cache_is_offline() {
+        let cache = MemoryAssetIndexCache::new(Duration::ZERO, CountingIndex::default())
+            .with_stale_if_error(Duration::from_secs(3600));
+        cache.list_assets(&query("a")).unwrap();
+        cache.inner_index.fail.store(true, Ordering::SeqCst);
+        let (descriptors, freshness) = cache.list_assets_with_freshness(&query("a")).unwrap();
+        assert_eq!(descriptors[0].name, "a");
+        assert!(matches!(freshness, Freshness::Stale(_)));
+        assert!(cache.list_assets(&query("b")).is_err());
+
+        let cache = cache.with_offline(true);
+        cache.inner_index.fail.store(false, Ordering::SeqCst);
+        assert!(cache.list_assets_with_freshness(&query("a")).is_ok());
+        assert!(cache.list_assets(&query("b")).is_err());
+        assert_eq!(cache.inner_index.calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn coalesces_concurrent_identical_queries() {
+        let cache = Arc::new(MemoryAssetIndexCache::new(
+            Duration::from_secs(3600),
+            CountingIndex {
+                delay: Duration::from_millis(200),
+                ..Default::default()
+            },
+        ));
+        let barrier = Arc::new(Barrier::new(8));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let cache = cache.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    cache.list_assets(&query("a")).unwrap()
+                })
+            })
+            .collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap()[0].name, "a");
         }
+        assert_eq!(cache.inner_index.calls.load(Ordering::SeqCst), 1);
     }
 }
@@ -0,0 +1,538 @@
+use crate::content_hash::{format_content_hash, parse_content_hash};
+use crate::{
+    validate_hash, AssetDescriptor, AssetLocator, AssetPayload, AssetStore, AssetStoreError,
+    AsyncAssetPayload, AsyncAssetStore, RetryPolicy,
+};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::io::{AsyncRead, ReadBuf};
+
+struct MemoryCacheEntry {
+    content: Arc<[u8]>,
+    last_used: Instant,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<String, MemoryCacheEntry>,
+    total_bytes: usize,
+}
+
+impl CacheState {
+    fn insert(&mut self, key: String, content: Arc<[u8]>, max_bytes: usize) {
+        let size = content.len();
+        let previous = self.entries.insert(
+            key,
+            MemoryCacheEntry {
+                content,
+                last_used: Instant::now(),
+            },
+        );
+        self.total_bytes += size;
+        if let Some(previous) = previous {
+            self.total_bytes -= previous.content.len();
+        }
+
+        while self.total_bytes > max_bytes {
+            let least_recently_used = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            match least_recently_used.and_then(|key| self.entries.remove(&key)) {
+                Some(evicted) => self.total_bytes -= evicted.content.len(),
+                None => break,
+            }
+        }
+    }
+}
+
+fn lock(state: &Mutex<CacheState>) -> MutexGuard<'_, CacheState> {
+    // A poisoned lock only means another caller panicked, and the cache state is still
+    // consistent, so the cache keeps working.
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Keeps asset content in memory, keyed by content hash, so that assets sharing content
+/// are only held once. The least recently used content is evicted once the cached content
+/// exceeds `max_bytes`.
+///
+/// Content fetched from the inner store is streamed to the caller and copied into memory
+/// as it is read. Once more than `max_bytes` have been read the copy is dropped and the
+/// rest of the asset is only streamed, so large assets never have to fit in memory. The
+/// cache is an async store when the inner store is.
+pub struct MemoryAssetStoreCache<TInnerStore> {
+    state: Arc<Mutex<CacheState>>,
+    max_bytes: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    inner_store: TInnerStore,
+}
+
+impl<TInnerStore> MemoryAssetStoreCache<TInnerStore> {
+    pub fn new(max_bytes: usize, inner_store: TInnerStore) -> Self {
+        MemoryAssetStoreCache {
+            state: Arc::new(Mutex::new(CacheState::default())),
+            max_bytes,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            inner_store,
+        }
+    }
+
+    /// The number of fetches served from memory.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// The number of fetches that went to the inner store.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// The size of the content currently held in memory.
+    pub fn cached_bytes(&self) -> usize {
+        lock(&self.state).total_bytes
+    }
+
+    fn fetch<F>(
+        &self,
+        content_hash: &str,
+        size_hint: Option<usize>,
+        fetch_from_inner_store: F,
+    ) -> Result<AssetPayload, AssetStoreError>
+    where
+        F: FnOnce() -> Result<AssetPayload, AssetStoreError>,
+    {
+        let key = cache_key(content_hash)?;
+        if let Some(content) = self.get(&key) {
+            return Ok(AssetPayload::Stream(Box::new(Cursor::new(content))));
+        }
+
+        match fetch_from_inner_store()? {
+            AssetPayload::Bytes(content) => {
+                let content = self.keep(key, content_hash, content)?;
+                Ok(AssetPayload::Stream(Box::new(Cursor::new(content))))
+            }
+            payload if self.is_too_large(size_hint) => Ok(payload),
+            AssetPayload::Stream(reader) => Ok(AssetPayload::Stream(Box::new(CachingReader::new(
+                reader,
+                self.tee(key, content_hash),
+            )))),
+        }
+    }
+
+    async fn fetch_async<F>(
+        &self,
+        content_hash: &str,
+        size_hint: Option<usize>,
+        fetch_from_inner_store: F,
+    ) -> Result<AsyncAssetPayload, AssetStoreError>
+    where
+        F: std::future::Future<Output = Result<AsyncAssetPayload, AssetStoreError>>,
+    {
+        let key = cache_key(content_hash)?;
+        if let Some(content) = self.get(&key) {
+            return Ok(AsyncAssetPayload::Stream(Box::pin(Cursor::new(content))));
+        }
+
+        match fetch_from_inner_store.await? {
+            AsyncAssetPayload::Bytes(content) => {
+                let content = self.keep(key, content_hash, content)?;
+                Ok(AsyncAssetPayload::Stream(Box::pin(Cursor::new(content))))
+            }
+            payload if self.is_too_large(size_hint) => Ok(payload),
+            AsyncAssetPayload::Stream(reader) => Ok(AsyncAssetPayload::Stream(Box::pin(
+                CachingReader::new(reader, self.tee(key, content_hash)),
+            ))),
+        }
+    }
+
+    /// The cached content. It's shared with the cache rather than copied.
+    fn get(&self, key: &str) -> Option<Arc<[u8]>> {
+        match lock(&self.state).entries.get_mut(key) {
+            Some(entry) => {
+                entry.last_used = Instant::now();
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(entry.content.clone())
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    fn is_too_large(&self, size_hint: Option<usize>) -> bool {
+        size_hint.is_some_and(|size| size > self.max_bytes)
+    }
+
+    fn keep(
+        &self,
+        key: String,
+        content_hash: &str,
+        content: Vec<u8>,
+    ) -> Result<Arc<[u8]>, AssetStoreError> {
+        // Byte payloads may not have been validated, and nothing unvalidated is kept around.
+        validate_hash(&content, content_hash)?;
+        let content: Arc<[u8]> = content.into();
+        if content.len() <= self.max_bytes {
+            lock(&self.state).insert(key, content.clone(), self.max_bytes);
+        }
+        Ok(content)
+    }
+
+    fn tee(&self, key: String, content_hash: &str) -> Tee {
+        Tee {
+            state: self.state.clone(),
+            max_bytes: self.max_bytes,
+            key,
+            content_hash: content_hash.to_owned(),
+            copy: Some(vec![]),
+        }
+    }
+}
+
+/// The copy of a streamed asset that is being read, which is cached once the end of the
+/// stream has been reached.
+struct Tee {
+    state: Arc<Mutex<CacheState>>,
+    max_bytes: usize,
+    key: String,
+    content_hash: String,
+    /// Dropped once the asset turns out to be too large, or the stream fails.
+    copy: Option<Vec<u8>>,
+}
+
+impl Tee {
+    fn record(&mut self, chunk: &[u8]) {
+        if chunk.is_empty() {
+            self.complete();
+            return;
+        }
+        match self.copy.as_mut() {
+            Some(copy) if copy.len() + chunk.len() <= self.max_bytes => {
+                copy.extend_from_slice(chunk)
+            }
+            _ => self.copy = None,
+        }
+    }
+
+    fn complete(&mut self) {
+        if let Some(content) = self.copy.take() {
+            // Streams are validated as they are read, this guards against those that aren't.
+            if validate_hash(&content, &self.content_hash).is_ok() {
+                let key = std::mem::take(&mut self.key);
+                lock(&self.state).insert(key, content.into(), self.max_bytes);
+            }
+        }
+    }
+
+    fn abandon(&mut self) {
+        self.copy = None;
+    }
+}
+
+/// Copies what is read from a stream into the cache.
+struct CachingReader<R> {
+    inner: R,
+    tee: Tee,
+}
+
+impl<R> CachingReader<R> {
+    fn new(inner: R, tee: Tee) -> Self {
+        CachingReader { inner, tee }
+    }
+}
+
+impl<R: Read> Read for CachingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.inner.read(buf) {
+            Ok(read) if read > 0 || !buf.is_empty() => {
+                self.tee.record(&buf[..read]);
+                Ok(read)
+            }
+            Ok(read) => Ok(read),
+            Err(e) => {
+                self.tee.abandon();
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for CachingReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        let wanted = buf.remaining() > 0;
+        match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Ready(Ok(())) => {
+                let chunk = &buf.filled()[filled..];
+                if !chunk.is_empty() || wanted {
+                    this.tee.record(chunk);
+                }
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(Err(e)) => {
+                this.tee.abandon();
+                Poll::Ready(Err(e))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Content is keyed by its normalized hash, so differently spelled hashes share an entry.
+fn cache_key(content_hash: &str) -> Result<String, AssetStoreError> {
+    let (algorithm, digest) = parse_content_hash(content_hash)?;
+    Ok(format_content_hash(algorithm, &digest.to_ascii_lowercase()))
+}
+
+impl<TInnerStore> AssetStore for MemoryAssetStoreCache<TInnerStore>
+where
+    TInnerStore: AssetStore,
+{
+    fn supports_locator(&self, locator: &AssetLocator) -> bool {
+        self.inner_store.supports_locator(locator)
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.inner_store.retry_policy()
+    }
+
+    fn fetch_by_locator(
+        &self,
+        locator: &AssetLocator,
+        expected_hash: &str,
+    ) -> Result<AssetPayload, AssetStoreError> {
+        self.fetch(expected_hash, None, || {
+            self.inner_store.fetch_by_locator(locator, expected_hash)
+        })
+    }
+
+    fn fetch_by_descriptor(
+        &self,
+        descriptor: &AssetDescriptor,
+    ) -> Result<AssetPayload, AssetStoreError> {
+        self.fetch(&descriptor.content_hash, Some(descriptor.size), || {
+            self.inner_store.fetch_by_descriptor(descriptor)
+        })
+    }
+}
+
+#[async_trait]
+impl<TInnerStore> AsyncAssetStore for MemoryAssetStoreCache<TInnerStore>
+where
+    TInnerStore: AsyncAssetStore,
+{
+    fn supports_locator(&self, locator: &AssetLocator) -> bool {
+        self.inner_store.supports_locator(locator)
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.inner_store.retry_policy()
+    }
+
+    async fn fetch_by_locator(
+        &self,
+        locator: &AssetLocator,
+        expected_hash: &str,
+    ) -> Result<AsyncAssetPayload, AssetStoreError> {
+        self.fetch_async(
+            expected_hash,
+            None,
+            self.inner_store.fetch_by_locator(locator, expected_hash),
+        )
+        .await
+    }
+
+    async fn fetch_by_descriptor(
+        &self,
+        descriptor: &AssetDescriptor,
+    ) -> Result<AsyncAssetPayload, AssetStoreError> {
+        self.fetch_async(
+            &descriptor.content_hash,
+            Some(descriptor.size),
+            self.inner_store.fetch_by_descriptor(descriptor),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryAssetStoreCache;
+    use crate::{
+        AssetDescriptor, AssetLocator, AssetPayload, AssetStore, AssetStoreError, AsyncAdapter,
+        AsyncAssetStore, HashValidatingReader, SemVer,
+    };
+    use std::io::{Cursor, Read};
+    use std::str::FromStr;
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+    const WORLD_SHA256: &str = "486ea46224d1bb4fb680f34f7c9ad96a8f24ec88be73ea8e5a6c65260e9cb8a7";
+
+    /// Serves "hello" or "world" depending on the requested hash.
+    struct WordStore {}
+
+    impl AssetStore for WordStore {
+        fn supports_locator(&self, _locator: &AssetLocator) -> bool {
+            true
+        }
+
+        fn fetch_by_locator(
+            &self,
+            _locator: &AssetLocator,
+            expected_hash: &str,
+        ) -> Result<AssetPayload, AssetStoreError> {
+            let content = if expected_hash.ends_with(HELLO_SHA256) {
+                "hello"
+            } else {
+                "world"
+            };
+            Ok(AssetPayload::Bytes(content.as_bytes().to_vec()))
+        }
+    }
+
+    /// Streams its content, like stores that fetch over the network.
+    struct StreamStore {
+        content: &'static str,
+    }
+
+    impl AssetStore for StreamStore {
+        fn supports_locator(&self, _locator: &AssetLocator) -> bool {
+            true
+        }
+
+        fn fetch_by_locator(
+            &self,
+            _locator: &AssetLocator,
+            expected_hash: &str,
+        ) -> Result<AssetPayload, AssetStoreError> {
+            Ok(AssetPayload::Stream(Box::new(HashValidatingReader::new(
+                Cursor::new(self.content.as_bytes()),
+                expected_hash,
+            ))))
+        }
+    }
+
+    fn descriptor(name: &str, content_hash: &str) -> AssetDescriptor {
+        AssetDescriptor::new(
+            name,
+            SemVer::from_str("1.0.0").unwrap(),
+            content_hash,
+            5,
+            vec![AssetLocator::new(
+                reqwest::Url::from_str("https://example.com/asset").unwrap(),
+            )],
+        )
+    }
+
+    fn fetch(cache: &MemoryAssetStoreCache<WordStore>, descriptor: &AssetDescriptor) -> Vec<u8> {
+        cache
+            .fetch_by_descriptor(descriptor)
+            .unwrap()
+            .into_bytes()
+            .unwrap()
+    }
+
+    #[test]
+    fn serves_shared_content_from_memory() {
+        let cache = MemoryAssetStoreCache::new(1024, WordStore {});
+        assert_eq!(fetch(&cache, &descriptor("a", HELLO_SHA256)), b"hello");
+        let tagged_hash = format!("sha256:{}", HELLO_SHA256.to_uppercase());
+        assert_eq!(fetch(&cache, &descriptor("b", &tagged_hash)), b"hello");
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+        assert_eq!(cache.cached_bytes(), 5);
+    }
+
+    #[test]
+    fn evicts_least_recently_used_content() {
+        let cache = MemoryAssetStoreCache::new(5, WordStore {});
+        fetch(&cache, &descriptor("a", HELLO_SHA256));
+        fetch(&cache, &descriptor("b", WORLD_SHA256));
+        fetch(&cache, &descriptor("a", HELLO_SHA256));
+        assert_eq!((cache.hits(), cache.misses()), (0, 3));
+        assert_eq!(cache.cached_bytes(), 5);
+    }
+
+    #[test]
+    fn rejects_content_that_doesnt_match_the_hash() {
+        let cache = MemoryAssetStoreCache::new(1024, WordStore {});
+        let mislabeled = descriptor("a", &HELLO_SHA256.replace('2', "3"));
+        assert!(matches!(
+            cache.fetch_by_descriptor(&mislabeled),
+            Err(AssetStoreError::AssetHashMismatch { .. })
+        ));
+        assert_eq!(cache.cached_bytes(), 0);
+    }
+
+    #[test]
+    fn caches_streams_only_up_to_the_limit() {
+        const HELLO_WORLD_SHA256: &str =
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+        let locator = descriptor("a", HELLO_SHA256).locators[0].clone();
+
+        let cache = MemoryAssetStoreCache::new(
+            5,
+            StreamStore {
+                content: "hello world",
+            },
+        );
+        let mut reader = cache
+            .fetch_by_locator(&locator, HELLO_WORLD_SHA256)
+            .unwrap()
+            .into_reader();
+        let mut first = [0; 4];
+        reader.read_exact(&mut first).unwrap();
+        assert_eq!(&first, b"hell");
+        let mut rest = vec![];
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"o world");
+        assert_eq!(cache.cached_bytes(), 0);
+
+        let cache = MemoryAssetStoreCache::new(5, StreamStore { content: "hello" });
+        let payload = cache.fetch_by_locator(&locator, HELLO_SHA256).unwrap();
+        // Nothing is cached before the stream has been read to the end.
+        assert_eq!(cache.cached_bytes(), 0);
+        assert_eq!(payload.into_bytes().unwrap(), b"hello");
+        assert_eq!(cache.cached_bytes(), 5);
+        let payload = cache.fetch_by_locator(&locator, HELLO_SHA256).unwrap();
+        assert_eq!(payload.into_bytes().unwrap(), b"hello");
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn caches_async_streams_only_up_to_the_limit() {
+        let locator = descriptor("a", HELLO_SHA256).locators[0].clone();
+        let store = |content: &'static str| AsyncAdapter::new(StreamStore { content });
+
+        let cache = MemoryAssetStoreCache::new(4, store("hello"));
+        let payload = AsyncAssetStore::fetch_by_locator(&cache, &locator, HELLO_SHA256)
+            .await
+            .unwrap();
+        assert_eq!(payload.into_bytes().await.unwrap(), b"hello");
+        assert_eq!(cache.cached_bytes(), 0);
+
+        let cache = MemoryAssetStoreCache::new(5, store("hello"));
+        for _ in 0..2 {
+            let payload = AsyncAssetStore::fetch_by_locator(&cache, &locator, HELLO_SHA256)
+                .await
+                .unwrap();
+            assert_eq!(payload.into_bytes().await.unwrap(), b"hello");
+        }
+        assert_eq!(cache.cached_bytes(), 5);
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+    }
+}
@@ -1,3 +1,5 @@
 mod memory_asset_index_cache;
+mod memory_asset_store_cache;
 
 pub use memory_asset_index_cache::MemoryAssetIndexCache;
+pub use memory_asset_store_cache::MemoryAssetStoreCache;
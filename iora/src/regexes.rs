@@ -27,7 +27,12 @@ pub(crate) fn parse_semver(
 pub(crate) fn parse_partial_version_constraint(s: &str) -> (Option<u32>, Option<u32>) {
     static PARTIAL_VERSION_CONSTRAINT_REGEX: OnceCell<Regex> = OnceCell::new();
     if let Some(captures) = PARTIAL_VERSION_CONSTRAINT_REGEX
-        .get_or_init(|| regex::Regex::new(r"^(?P<major>[0-9]+)(\.(?P<minor>[0-9]+))?$").unwrap())
+        .get_or_init(|| {
+            regex::Regex::new(
+                r"^(?P<major>[0-9]+)(?:\.(?P<minor>[0-9]+)|\.[xX*])?(?:\.[xX*])?$",
+            )
+            .unwrap()
+        })
         .captures(s)
     {
         (
@@ -39,6 +44,24 @@ pub(crate) fn parse_partial_version_constraint(s: &str) -> (Option<u32>, Option<
     }
 }
 
+pub(crate) fn parse_version_comparator(s: &str) -> (Option<String>, Option<String>) {
+    static VERSION_COMPARATOR_REGEX: OnceCell<Regex> = OnceCell::new();
+    if let Some(captures) = VERSION_COMPARATOR_REGEX
+        .get_or_init(|| {
+            regex::Regex::new(r"^(?P<operator>\^|~|>=|<=|>|<|=)?\s*(?P<version>[^\s^~<>=]+)$")
+                .unwrap()
+        })
+        .captures(s)
+    {
+        (
+            match_to_string(captures.name("operator")),
+            match_to_string(captures.name("version")),
+        )
+    } else {
+        (None, None)
+    }
+}
+
 pub(crate) fn parse_name_constraint(s: &str) -> (Option<String>, Option<String>, Option<String>) {
     static NAME_CONSTRAINT_REGEX: OnceCell<Regex> = OnceCell::new();
     if let Some(captures) = NAME_CONSTRAINT_REGEX
@@ -88,11 +111,29 @@ mod tests {
     fn parse_partial_version_constraint_test() {
         assert_eq!(parse_partial_version_constraint("12.34"), (Some(12), Some(34)));
         assert_eq!(parse_partial_version_constraint("12"), (Some(12), None));
+        assert_eq!(parse_partial_version_constraint("12.x"), (Some(12), None));
+        assert_eq!(parse_partial_version_constraint("12.*.X"), (Some(12), None));
+        assert_eq!(parse_partial_version_constraint("12.34.x"), (Some(12), Some(34)));
 
         assert_eq!(parse_partial_version_constraint("-12"), (None, None));
         assert_eq!(parse_partial_version_constraint(""), (None, None));
         assert_eq!(parse_partial_version_constraint("12.34.56"), (None, None));
         assert_eq!(parse_partial_version_constraint("v"), (None, None));
+        assert_eq!(parse_partial_version_constraint("12.x.34"), (None, None));
+        assert_eq!(parse_partial_version_constraint("x"), (None, None));
+    }
+
+    #[test]
+    fn parse_version_comparator_test() {
+        assert_eq!(parse_version_comparator("1.2"), (None, Some("1.2".to_owned())));
+        assert_eq!(parse_version_comparator("^1.2"), (Some("^".to_owned()), Some("1.2".to_owned())));
+        assert_eq!(parse_version_comparator(">= 1.2.3-beta"), (Some(">=".to_owned()), Some("1.2.3-beta".to_owned())));
+        assert_eq!(parse_version_comparator("<1"), (Some("<".to_owned()), Some("1".to_owned())));
+
+        assert_eq!(parse_version_comparator(""), (None, None));
+        assert_eq!(parse_version_comparator(">="), (None, None));
+        assert_eq!(parse_version_comparator(">>1"), (None, None));
+        assert_eq!(parse_version_comparator("1 2"), (None, None));
     }
 
     #[test]
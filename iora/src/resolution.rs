@@ -0,0 +1,105 @@
+use crate::{AssetDescriptor, AssetIndex, AssetQuery, AsyncAssetIndex, ListAssetsError};
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, Default)]
+pub struct ResolutionOptions {
+    /// Ignore versions with a prerelease component, e.g. 1.2.0-beta.
+    pub exclude_prereleases: bool,
+}
+
+/// Keeps only the highest version of each asset name, ordered by name.
+pub fn select_latest(
+    descriptors: Vec<AssetDescriptor>,
+    options: &ResolutionOptions,
+) -> Vec<AssetDescriptor> {
+    let mut latest: BTreeMap<String, AssetDescriptor> = BTreeMap::new();
+    for descriptor in descriptors {
+        if options.exclude_prereleases && descriptor.version.prerelease.is_some() {
+            continue;
+        }
+        match latest.get(&descriptor.name) {
+            Some(current) if current.version >= descriptor.version => {}
+            _ => {
+                latest.insert(descriptor.name.clone(), descriptor);
+            }
+        }
+    }
+    latest.into_values().collect()
+}
+
+/// Lists the assets matching the query and resolves each asset name to its highest
+/// matching version.
+pub fn resolve_latest(
+    index: &impl AssetIndex,
+    query: &AssetQuery,
+    options: &ResolutionOptions,
+) -> Result<Vec<AssetDescriptor>, ListAssetsError> {
+    Ok(select_latest(index.list_assets(query)?, options))
+}
+
+/// The async counterpart of `resolve_latest`.
+pub async fn resolve_latest_async(
+    index: &(impl AsyncAssetIndex + ?Sized),
+    query: &AssetQuery,
+    options: &ResolutionOptions,
+) -> Result<Vec<AssetDescriptor>, ListAssetsError> {
+    Ok(select_latest(index.list_assets(query).await?, options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{select_latest, ResolutionOptions};
+    use crate::{AssetDescriptor, SemVer};
+    use std::str::FromStr;
+
+    fn descriptor(name: &str, version: &str) -> AssetDescriptor {
+        AssetDescriptor::new(name, SemVer::from_str(version).unwrap(), "", 0, vec![])
+    }
+
+    fn summarize(descriptors: &[AssetDescriptor]) -> Vec<String> {
+        descriptors
+            .iter()
+            .map(|d| format!("{}@{}", d.name, d.version))
+            .collect()
+    }
+
+    #[test]
+    fn selects_highest_version_per_name() {
+        let descriptors = vec![
+            descriptor("b", "1.0.0"),
+            descriptor("a", "2.0.0"),
+            descriptor("a", "2.10.0"),
+            descriptor("a", "2.9.0"),
+            descriptor("b", "1.1.0-beta"),
+            descriptor("a", "2.10.0-rc.1"),
+        ];
+        assert_eq!(
+            summarize(&select_latest(
+                descriptors.clone(),
+                &ResolutionOptions::default()
+            )),
+            vec!["a@2.10.0", "b@1.1.0-beta"]
+        );
+        assert_eq!(
+            summarize(&select_latest(
+                descriptors,
+                &ResolutionOptions {
+                    exclude_prereleases: true
+                }
+            )),
+            vec!["a@2.10.0", "b@1.0.0"]
+        );
+    }
+
+    #[test]
+    fn only_prereleases_can_resolve_to_nothing() {
+        let descriptors = vec![descriptor("a", "1.0.0-alpha")];
+        assert!(select_latest(
+            descriptors,
+            &ResolutionOptions {
+                exclude_prereleases: true
+            }
+        )
+        .is_empty());
+    }
+}
@@ -28,11 +28,57 @@ impl SemVer {
             buildmetadata,
         }
     }
+
+    /// Compares precedence as defined by SemVer 2.0: a prerelease sorts below the
+    /// associated release, prerelease identifiers are compared one dot separated field at
+    /// a time (numerically when both are numeric) and build metadata is ignored.
+    pub fn cmp_precedence(&self, other: &Self) -> Ordering {
+        self.major
+            .cmp(&other.major)
+            .then(self.minor.cmp(&other.minor))
+            .then(self.patch.cmp(&other.patch))
+            .then_with(|| compare_prerelease(&self.prerelease, &other.prerelease))
+    }
+}
+
+fn compare_prerelease(left: &Option<String>, right: &Option<String>) -> Ordering {
+    match (left, right) {
+        (None, None) => Ordering::Equal,
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(left), Some(right)) => {
+            let mut left_identifiers = left.split('.');
+            let mut right_identifiers = right.split('.');
+            loop {
+                match (left_identifiers.next(), right_identifiers.next()) {
+                    (None, None) => return Ordering::Equal,
+                    (None, Some(_)) => return Ordering::Less,
+                    (Some(_), None) => return Ordering::Greater,
+                    (Some(l), Some(r)) => match compare_prerelease_identifier(l, r) {
+                        Ordering::Equal => continue,
+                        o => return o,
+                    },
+                }
+            }
+        }
+    }
+}
+
+fn compare_prerelease_identifier(left: &str, right: &str) -> Ordering {
+    let is_numeric = |s: &str| !s.is_empty() && s.bytes().all(|c| c.is_ascii_digit());
+    match (is_numeric(left), is_numeric(right)) {
+        // Numeric identifiers have no leading zeroes, so the longer one is the larger.
+        (true, true) => left.len().cmp(&right.len()).then(left.cmp(right)),
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+        (false, false) => left.cmp(right),
+    }
 }
 
-impl ToString for SemVer {
-    fn to_string(&self) -> String {
-        format!(
+impl std::fmt::Display for SemVer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
             "{}.{}.{}{}{}",
             self.major,
             self.minor,
@@ -97,24 +143,11 @@ impl PartialOrd for SemVer {
 }
 
 impl Ord for SemVer {
+    /// Orders by precedence, falling back to build metadata so that the ordering stays
+    /// consistent with `Eq`. Use `cmp_precedence` where build metadata must be ignored.
     fn cmp(&self, other: &Self) -> Ordering {
-        match (
-            self.major.cmp(&other.major),
-            self.minor.cmp(&other.minor),
-            self.patch.cmp(&other.patch),
-        ) {
-            (Ordering::Equal, Ordering::Equal, Ordering::Equal) => {
-                match (&self.prerelease, &other.prerelease) {
-                    (None, None) => Ordering::Equal,
-                    (Some(_), Some(_)) => self.prerelease.cmp(&other.prerelease),
-                    (Some(_), None) => Ordering::Greater,
-                    (None, Some(_)) => Ordering::Less,
-                }
-            }
-            (Ordering::Equal, Ordering::Equal, o) => o,
-            (Ordering::Equal, o, _) => o,
-            (o, _, _) => o,
-        }
+        self.cmp_precedence(other)
+            .then_with(|| self.buildmetadata.cmp(&other.buildmetadata))
     }
 
     fn max(self, other: Self) -> Self {
@@ -147,9 +180,11 @@ impl Ord for SemVer {
 #[cfg(test)]
 mod tests {
     use super::{SemVer, SemVerParseEror};
+    use std::cmp::Ordering;
     use std::str::FromStr;
 
     #[test]
+    #[allow(clippy::match_like_matches_macro)]
     fn from_str() {
         assert_eq!(
             SemVer::from_str("3.45.6").unwrap(),
@@ -196,6 +231,54 @@ mod tests {
         assert!(SemVer::from_str("3.45.7").unwrap() != SemVer::from_str("4.5.6").unwrap());
     }
 
+    #[test]
+    fn precedence() {
+        // Based on the examples from https://semver.org/#spec-item-11, in ascending order.
+        let ascending = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.2",
+            "1.0.0-alpha.10",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "2.0.0",
+            "2.1.0",
+            "2.1.1",
+        ];
+        for (i, lower) in ascending.iter().enumerate() {
+            let lower = SemVer::from_str(lower).unwrap();
+            for higher in ascending.iter().skip(i + 1) {
+                let higher = SemVer::from_str(higher).unwrap();
+                assert_eq!(lower.cmp_precedence(&higher), Ordering::Less, "{lower} < {higher}");
+                assert_eq!(higher.cmp_precedence(&lower), Ordering::Greater, "{higher} > {lower}");
+                assert!(lower < higher, "{lower} < {higher}");
+            }
+        }
+
+        // Build metadata doesn't affect precedence.
+        let table = [
+            ("1.0.0+20130313144700", "1.0.0", Ordering::Equal),
+            ("1.0.0-alpha+001", "1.0.0-alpha", Ordering::Equal),
+            ("1.0.0-beta+exp.sha.5114f85", "1.0.0-beta+other", Ordering::Equal),
+            ("1.0.0-alpha+001", "1.0.0", Ordering::Less),
+            ("1.0.0-1", "1.0.0-a", Ordering::Less),
+            ("1.0.0-99999999999999999999", "1.0.0-100000000000000000000", Ordering::Less),
+        ];
+        for (left, right, expected) in table {
+            assert_eq!(
+                SemVer::from_str(left)
+                    .unwrap()
+                    .cmp_precedence(&SemVer::from_str(right).unwrap()),
+                expected,
+                "{left} vs {right}"
+            );
+        }
+    }
+
     #[test]
     fn from_json() {
         let json_parsed: SemVer = serde_json::from_str(
@@ -0,0 +1,147 @@
+//! Checks that processes sharing a cache directory don't duplicate downloads or lose
+//! index updates. The test runs `stress_child` in several copies of this test binary.
+
+use iora::filesystem::{FilesystemAssetStoreCache, JsonFileAssetIndexCache};
+use iora::{
+    AssetDescriptor, AssetIndex, AssetLocator, AssetPayload, AssetQuery, AssetStore,
+    AssetStoreError, HashValidatingReader, ListAssetsError, NameConstraint, SemVer, Url,
+};
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::time::Duration;
+
+const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+const ROOT_VARIABLE: &str = "IORA_LOCK_STRESS_ROOT";
+const ID_VARIABLE: &str = "IORA_LOCK_STRESS_ID";
+const PROCESSES: usize = 8;
+
+/// Appends a line to a shared file for every download, so the parent process can count
+/// how often the asset was fetched across all child processes.
+struct CountingStore {
+    downloads: PathBuf,
+}
+
+impl AssetStore for CountingStore {
+    fn supports_locator(&self, _locator: &AssetLocator) -> bool {
+        true
+    }
+
+    fn fetch_by_locator(
+        &self,
+        _locator: &AssetLocator,
+        expected_hash: &str,
+    ) -> Result<AssetPayload, AssetStoreError> {
+        let mut downloads = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.downloads)?;
+        downloads.write_all(b"download\n")?;
+        // Give the other processes time to pile up behind the lock.
+        std::thread::sleep(Duration::from_millis(200));
+        Ok(AssetPayload::Stream(Box::new(HashValidatingReader::new(
+            "hello".as_bytes(),
+            expected_hash,
+        ))))
+    }
+}
+
+#[derive(Debug)]
+struct NamedIndex {}
+
+impl AssetIndex for NamedIndex {
+    fn list_assets(&self, query: &AssetQuery) -> Result<Vec<AssetDescriptor>, ListAssetsError> {
+        Ok(vec![descriptor(&query.name_constraint.to_string())])
+    }
+}
+
+#[derive(Debug)]
+struct UnavailableIndex {}
+
+impl AssetIndex for UnavailableIndex {
+    fn list_assets(&self, _query: &AssetQuery) -> Result<Vec<AssetDescriptor>, ListAssetsError> {
+        Err(ListAssetsError::AssetIndexNotFound(None))
+    }
+}
+
+fn descriptor(name: &str) -> AssetDescriptor {
+    AssetDescriptor::new(
+        name,
+        SemVer::from_str("1.0.0").unwrap(),
+        HELLO_SHA256,
+        5,
+        vec![AssetLocator::new(
+            Url::from_str("https://example.com/hello").unwrap(),
+        )],
+    )
+}
+
+fn query(id: usize) -> AssetQuery {
+    AssetQuery::new(NameConstraint::ExactMatch(format!("asset{id}")), None)
+}
+
+/// The work done by each process of `concurrent_processes_share_the_cache`.
+#[test]
+#[ignore = "run by concurrent_processes_share_the_cache"]
+fn stress_child() {
+    let root = PathBuf::from(std::env::var_os(ROOT_VARIABLE).unwrap());
+    let id: usize = std::env::var(ID_VARIABLE).unwrap().parse().unwrap();
+
+    let store = FilesystemAssetStoreCache::new(
+        &root.join("assets"),
+        CountingStore {
+            downloads: root.join("downloads"),
+        },
+    )
+    .unwrap();
+    let mut content = String::new();
+    store
+        .fetch_by_descriptor(&descriptor("hello"))
+        .unwrap()
+        .into_reader()
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!(content, "hello");
+
+    let index = JsonFileAssetIndexCache::new(
+        &root.join("index.json"),
+        Duration::from_secs(3600),
+        NamedIndex {},
+    );
+    index.list_assets(&query(id)).unwrap();
+}
+
+#[test]
+fn concurrent_processes_share_the_cache() {
+    let root = tempfile::tempdir().unwrap();
+    let children: Vec<_> = (0..PROCESSES)
+        .map(|id| {
+            Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", "stress_child", "--ignored"])
+                .env(ROOT_VARIABLE, root.path())
+                .env(ID_VARIABLE, id.to_string())
+                .stdout(Stdio::null())
+                .spawn()
+                .unwrap()
+        })
+        .collect();
+    for mut child in children {
+        assert!(child.wait().unwrap().success());
+    }
+
+    let downloads = std::fs::read_to_string(root.path().join("downloads")).unwrap();
+    assert_eq!(downloads.lines().count(), 1);
+
+    // Every process added its own entry, and none of the updates were lost.
+    let index = JsonFileAssetIndexCache::new(
+        &root.path().join("index.json"),
+        Duration::from_secs(3600),
+        UnavailableIndex {},
+    );
+    for id in 0..PROCESSES {
+        let descriptors = index.list_assets(&query(id)).unwrap();
+        assert_eq!(descriptors[0].name, format!("asset{id}"));
+    }
+}
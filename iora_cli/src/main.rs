@@ -1,12 +1,33 @@
 use clap::{Parser, Subcommand};
-use iora::{AssetQuery, AssetStoreError, ConstraintParsingError, ListAssetsError};
+use iora::filesystem::JsonFileAssetIndexCache;
+use iora::http::{HttpAssetIndex, HttpAsssetStore, HttpAuth, HttpClient, HttpClientConfig};
+use iora::{
+    AssetDescriptor, AssetPayload, AssetPublisher, AssetQuery, AssetStoreError,
+    ConstraintParsingError, Freshness, ListAssetsError, PublishAssetError, ResolutionOptions,
+    SemVer,
+};
 use std::fs;
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::time::Duration;
 use tracing_subscriber::{filter, prelude::*};
 
 use thiserror::Error;
 
+type Catalog = JsonFileAssetIndexCache<HttpAssetIndex>;
+
+const INDEX_URL: &str = "http://localhost:3000";
+
+/// Where interrupted downloads are kept within the cache, so that they resume.
+const DOWNLOADS_FOLDER_NAME: &str = ".downloads";
+
+const CREDENTIALS_HELP: &str = "\
+Credentials for the index are read from the environment, never from the command line:
+  IORA_CREDENTIALS_FILE                   A JSON credentials file
+  IORA_AUTH_TOKEN                         A bearer token
+  IORA_AUTH_USERNAME, IORA_AUTH_PASSWORD  Basic authentication
+  IORA_AUTH_HEADER                        A header such as 'X-Api-Key: secret'";
+
 #[derive(Error, Debug)]
 enum IoraCliError {
     #[error("Unsupported asset query parameters: {0}")]
@@ -17,8 +38,12 @@ enum IoraCliError {
     FetchError(AssetStoreError),
     #[error("No matching asset available for downloading.")]
     FetchErrorNoMatchingAsset,
-    #[error("Query parameters matched multiple assets.")]
+    #[error("Query parameters matched multiple asset names.")]
     FetchErrorTooManyMatchingAssets,
+    #[error("Unsupported publish parameters: {0}")]
+    PublishArgumentError(String),
+    #[error("Error occurred while publishing asset: {0}")]
+    PublishError(PublishAssetError),
 }
 
 impl From<ConstraintParsingError> for IoraCliError {
@@ -39,9 +64,16 @@ impl From<AssetStoreError> for IoraCliError {
     }
 }
 
+impl From<PublishAssetError> for IoraCliError {
+    fn from(e: PublishAssetError) -> Self {
+        IoraCliError::PublishError(e)
+    }
+}
+
 #[derive(Parser, Debug)]
 #[command(name = "iora")]
 #[command(bin_name = "iora_cli")]
+#[command(after_help = CREDENTIALS_HELP)]
 struct IoraCli {
     #[command(subcommand)]
     command: IoraCommands,
@@ -51,6 +83,68 @@ struct IoraCli {
 
     #[arg(long)]
     verbose: bool,
+
+    /// Answer queries from the local cache only, without contacting the index.
+    #[arg(long)]
+    offline: bool,
+
+    /// How long past their expiry cached query results may be used while the index can't
+    /// be reached. Defaults to an hour; 0 always reports the outage.
+    #[arg(long, value_name = "SECONDS", default_value_t = 60 * 60)]
+    stale_if_error: u64,
+
+    /// Evict the least recently used assets from the local cache beyond this many bytes.
+    #[arg(long, value_name = "BYTES")]
+    cache_max_size: Option<u64>,
+
+    #[command(flatten)]
+    http: HttpClientArgs,
+}
+
+/// Settings of the HTTP client used to reach the index and the stores.
+#[derive(clap::Args, Debug)]
+struct HttpClientArgs {
+    /// How long establishing a connection may take.
+    #[arg(long, value_name = "SECONDS")]
+    connect_timeout: Option<u64>,
+
+    /// How long a whole request may take, including the download of an asset.
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<u64>,
+
+    /// Send all requests through this proxy. Otherwise HTTP_PROXY, HTTPS_PROXY and
+    /// NO_PROXY are honoured.
+    #[arg(long, value_name = "URL")]
+    proxy: Option<String>,
+
+    /// Trust the CA certificates in this PEM or DER file, in addition to the system's.
+    #[arg(long, value_name = "FILE")]
+    ca_certificate: Vec<PathBuf>,
+
+    #[arg(long, value_name = "USER_AGENT")]
+    user_agent: Option<String>,
+}
+
+impl HttpClientArgs {
+    fn config(&self) -> HttpClientConfig {
+        let mut config = HttpClientConfig::new();
+        if let Some(seconds) = self.connect_timeout {
+            config = config.with_connect_timeout(Duration::from_secs(seconds));
+        }
+        if let Some(seconds) = self.timeout {
+            config = config.with_timeout(Duration::from_secs(seconds));
+        }
+        if let Some(proxy) = &self.proxy {
+            config = config.with_proxy(proxy);
+        }
+        for path in self.ca_certificate.iter() {
+            config = config.with_ca_certificate(path);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            config = config.with_user_agent(user_agent);
+        }
+        config
+    }
 }
 
 #[derive(Debug, Subcommand)]
@@ -59,6 +153,8 @@ enum IoraCommands {
     Find(Find),
     #[command(arg_required_else_help = true)]
     Fetch(Fetch),
+    #[command(arg_required_else_help = true)]
+    Publish(Publish),
 }
 
 #[derive(clap::Args, Debug)]
@@ -67,15 +163,15 @@ struct Find {
     /// A pattern describing the range of asset names of interest.
     #[arg(short, long, value_name = "NAME_CONSTRAINT", required = true)]
     name: String,
-    /// A pattern describing the range of asset versions of interest.
+    /// A version requirement such as '1.2.3', '^1.2', '>=1.0 <2.0' or '1.x || 3.x'.
     #[arg(short, long, value_name = "VERSION_CONSTRAINT", required = false)]
     version: Option<String>,
 }
 
 impl Find {
-    fn run(&self, catalog: &impl iora::AssetIndex) -> Result<(), IoraCliError> {
+    fn run(&self, catalog: &Catalog) -> Result<(), IoraCliError> {
         let query = AssetQuery::new_from_strings(&self.name, &self.version)?;
-        let results = catalog.list_assets(&query)?;
+        let results = list_assets(catalog, &query)?;
         print_asset_descriptor_table(&results);
         Ok(())
     }
@@ -87,19 +183,23 @@ struct Fetch {
     /// A pattern describing the range of asset names of interest.
     #[arg(short, long, value_name = "NAME_CONSTRAINT", required = true)]
     name: String,
-    /// A pattern describing the range of asset versions of interest.
+    /// A version requirement such as '1.2.3', '^1.2', '>=1.0 <2.0' or '1.x || 3.x'.
     #[arg(short, long, value_name = "VERSION_CONSTRAINT", required = false)]
     version: Option<String>,
+    /// Don't consider prerelease versions when picking the latest matching version.
+    #[arg(long)]
+    exclude_prereleases: bool,
 }
 
 impl Fetch {
-    fn run(
-        &self,
-        catalog: &impl iora::AssetIndex,
-        store: &impl iora::AssetStore,
-    ) -> Result<(), IoraCliError> {
+    fn run(&self, catalog: &Catalog, store: &impl iora::AssetStore) -> Result<(), IoraCliError> {
         let query = AssetQuery::new_from_strings(&self.name, &self.version)?;
-        let results = catalog.list_assets(&query)?;
+        let results = iora::select_latest(
+            list_assets(catalog, &query)?,
+            &ResolutionOptions {
+                exclude_prereleases: self.exclude_prereleases,
+            },
+        );
         if results.is_empty() {
             Err(IoraCliError::FetchErrorNoMatchingAsset)
         } else if results.len() > 1 {
@@ -111,6 +211,101 @@ impl Fetch {
     }
 }
 
+#[derive(clap::Args, Debug)]
+#[command(about = "Publish a new version of a package.")]
+struct Publish {
+    /// The name of the asset.
+    #[arg(short, long, value_name = "NAME", required = true)]
+    name: String,
+    /// The version being published, e.g. '1.2.3'.
+    #[arg(short, long, value_name = "VERSION", required = true)]
+    version: String,
+    /// The file holding the asset content.
+    #[arg(short, long, value_name = "FILE", required = true)]
+    file: PathBuf,
+    /// Publish to a directory laid out as name/version/asset.
+    #[arg(
+        long,
+        value_name = "DIRECTORY",
+        required_unless_present = "azure_storage_account"
+    )]
+    directory: Option<PathBuf>,
+    /// Publish to an Azure Blob Storage account. The SAS token is read from the
+    /// IORA_AZURE_SAS_TOKEN environment variable.
+    #[arg(
+        long,
+        value_name = "ACCOUNT",
+        conflicts_with = "directory",
+        requires = "azure_container"
+    )]
+    azure_storage_account: Option<String>,
+    /// The blob container to publish to.
+    #[arg(long, value_name = "CONTAINER", requires = "azure_storage_account")]
+    azure_container: Option<String>,
+}
+
+impl Publish {
+    fn run(&self, http_client: &HttpClient) -> Result<(), IoraCliError> {
+        let version = SemVer::from_str(&self.version).map_err(|_| {
+            IoraCliError::PublishArgumentError(format!("'{}' isn't a valid version.", self.version))
+        })?;
+        let file = fs::File::open(&self.file).map_err(|e| {
+            IoraCliError::PublishArgumentError(format!(
+                "Can't open '{}': {}",
+                self.file.display(),
+                e
+            ))
+        })?;
+        let payload = AssetPayload::Stream(Box::new(file));
+
+        let descriptor = match (
+            &self.directory,
+            &self.azure_storage_account,
+            &self.azure_container,
+        ) {
+            (Some(directory), _, _) => {
+                let directory = std::env::current_dir()
+                    .map_err(|e| IoraCliError::PublishArgumentError(e.to_string()))?
+                    .join(directory);
+                iora::filesystem::FilesystemAssetPublisher::new(&directory)?
+                    .publish(&self.name, &version, payload)?
+            }
+            (None, Some(account), Some(container)) => {
+                let sas = std::env::var("IORA_AZURE_SAS_TOKEN").map_err(|_| {
+                    IoraCliError::PublishArgumentError(
+                        "IORA_AZURE_SAS_TOKEN must be set to publish to Azure.".to_owned(),
+                    )
+                })?;
+                iora::http::AzureBlobAssetPublisher::new(account, container, &sas)
+                    .with_http_client(http_client.clone())
+                    .publish(&self.name, &version, payload)?
+            }
+            _ => {
+                return Err(IoraCliError::PublishArgumentError(
+                    "A publishing destination is required.".to_owned(),
+                ))
+            }
+        };
+        print_asset_descriptor_table(&vec![descriptor]);
+        Ok(())
+    }
+}
+
+/// Lists the assets, warning when the results come from the cache past their expiry.
+fn list_assets(
+    catalog: &Catalog,
+    query: &AssetQuery,
+) -> Result<Vec<AssetDescriptor>, IoraCliError> {
+    let (results, freshness) = catalog.list_assets_with_freshness(query)?;
+    if let Freshness::Stale(age) = freshness {
+        eprintln!(
+            "Warning: the asset index couldn't be queried, showing cached results from {} seconds ago.",
+            age.as_secs()
+        );
+    }
+    Ok(results)
+}
+
 fn print_asset_descriptor_table(descriptors: &Vec<iora::AssetDescriptor>) {
     println!("{0: <32} {1: <32} {2: <32}", "Name", "Version", "Hash");
     for ad in descriptors {
@@ -137,6 +332,30 @@ fn main() {
             .init();
     }
 
+    let http_client = match HttpClient::new(&args.http.config()) {
+        Ok(http_client) => http_client,
+        Err(e) => {
+            println!("Error: {}", e);
+            return;
+        }
+    };
+
+    let auth = match HttpAuth::from_env() {
+        Ok(auth) => auth,
+        Err(e) => {
+            println!("Error: {}", e);
+            return;
+        }
+    };
+    let mut index = HttpAssetIndex::new(INDEX_URL).with_http_client(http_client.clone());
+    let mut asset_store = HttpAsssetStore::new().with_http_client(http_client.clone());
+    if let Some(auth) = auth {
+        // The index serves asset content too, so the store authenticates to it as well.
+        let origin = iora::Url::parse(INDEX_URL).unwrap();
+        index = index.with_auth(auth.clone());
+        asset_store = asset_store.with_auth(&origin, auth);
+    }
+
     let mut cache_path = std::env::current_dir().unwrap();
     cache_path.push(PathBuf::from(".cache"));
     if !cache_path.as_path().exists() {
@@ -145,25 +364,30 @@ fn main() {
             return;
         }
     }
-    let catalog = iora::filesystem::JsonFileAssetIndexCache::new(
+    let asset_store = asset_store.with_download_directory(&cache_path.join(DOWNLOADS_FOLDER_NAME));
+    let catalog = JsonFileAssetIndexCache::new(
         &cache_path.join(PathBuf::from("descriptors.json")),
         Duration::from_nanos(1),
-        iora::http::HttpAssetIndex::new("http://localhost:3000"),
-    );
-    let store = match iora::filesystem::FilesystemAssetStoreCache::new(
-        &cache_path,
-        iora::http::HttpAsssetStore {},
-    ) {
+        index,
+    )
+    .with_stale_if_error(Duration::from_secs(args.stale_if_error))
+    .with_offline(args.offline);
+    let mut store = match iora::filesystem::FilesystemAssetStoreCache::new(&cache_path, asset_store)
+    {
         Ok(store) => store,
         Err(e) => {
             print!("Could not configure the asset store: {}", e);
             return;
         }
     };
+    if let Some(max_size_bytes) = args.cache_max_size {
+        store = store.with_max_size_bytes(max_size_bytes);
+    }
 
     let command_result = match args.command {
         IoraCommands::Find(f) => f.run(&catalog),
         IoraCommands::Fetch(f) => f.run(&catalog, &store),
+        IoraCommands::Publish(p) => p.run(&http_client),
     };
     match command_result {
         Ok(()) => {}
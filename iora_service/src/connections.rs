@@ -1,24 +1,144 @@
 use axum::async_trait;
+use axum::http::header::{HeaderName, HOST};
+use axum::http::HeaderMap;
 use bb8::ManageConnection;
 use thiserror::Error;
 
-use iora::http::AzureBlobAssetIndex;
+use crate::settings::{AssetIndex, AssetIndexCache};
+use iora::filesystem::FilesystemAssetStoreCache;
+use iora::http::{
+    AzureBlobAssetIndex, HttpAsssetStore, HttpClient, S3AssetIndex, S3AttributeSource, S3Bucket,
+    S3Credentials,
+};
+use iora::memory::{MemoryAssetIndexCache, MemoryAssetStoreCache};
+use iora::{AssetDescriptor, AssetLocator, AssetQuery, AsyncAssetIndex, ListAssetsError};
+use std::path::Path;
+use std::sync::Arc;
+
+pub type ServiceAssetIndex = MemoryAssetIndexCache<Box<dyn AsyncAssetIndex>>;
+pub type ServiceAssetStore = MemoryAssetStoreCache<FilesystemAssetStoreCache<HttpAsssetStore>>;
+
+/// Set by reverse proxies that terminate TLS, to tell the service which scheme the client
+/// used.
+const FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
 
 pub struct IoraServiceState {
-    pub asset_index_connection_pool: bb8::Pool<AssetIndexConnectionManager>,
+    pub asset_index: Arc<ServiceAssetIndex>,
+    pub asset_store: Arc<ServiceAssetStore>,
+    pub public_url: Option<String>,
 }
 
 impl IoraServiceState {
     pub async fn new(
         asset_index_connection_type: AssetIndexConnectionType,
+        asset_index_cache: &AssetIndexCache,
+        asset_store_cache_path: &Path,
+        asset_store_memory_cache_max_bytes: usize,
+        asset_store_max_size_bytes: Option<u64>,
+        public_url: Option<String>,
+        http_client: HttpClient,
     ) -> Result<Self, AssetIndexConnectionError> {
-        Ok(IoraServiceState {
-            asset_index_connection_pool: bb8::Pool::builder()
-                .build(AssetIndexConnectionManager {
-                    asset_index_connection_type,
-                })
-                .await?,
-        })
+        let pool = bb8::Pool::builder()
+            .build(AssetIndexConnectionManager {
+                asset_index_connection_type,
+                http_client: http_client.clone(),
+            })
+            .await?;
+        let mut cache = FilesystemAssetStoreCache::new(
+            asset_store_cache_path,
+            HttpAsssetStore::new().with_http_client(http_client),
+        )
+        .map_err(|e| AssetIndexConnectionError::MisconfiguredStore(e.to_string()))?;
+        if let Some(max_size_bytes) = asset_store_max_size_bytes {
+            cache = cache.with_max_size_bytes(max_size_bytes);
+        }
+        let pooled_index: Box<dyn AsyncAssetIndex> = Box::new(PooledAssetIndex { pool });
+        Ok(IoraServiceState::from_parts(
+            MemoryAssetIndexCache::new(asset_index_cache.max_age(), pooled_index)
+                .with_max_entries(asset_index_cache.max_entries)
+                .with_stale_if_error(asset_index_cache.stale_if_error())
+                .with_offline(asset_index_cache.offline),
+            MemoryAssetStoreCache::new(asset_store_memory_cache_max_bytes, cache),
+            public_url,
+        ))
+    }
+
+    pub fn from_parts(
+        asset_index: ServiceAssetIndex,
+        asset_store: ServiceAssetStore,
+        public_url: Option<String>,
+    ) -> Self {
+        IoraServiceState {
+            asset_index: Arc::new(asset_index),
+            asset_store: Arc::new(asset_store),
+            public_url: public_url.map(|url| url.trim_end_matches('/').to_owned()),
+        }
+    }
+
+    /// The URL clients reach the service at: the configured public URL, or else the one the
+    /// request was sent to, going by its `Host` and `X-Forwarded-Proto` headers.
+    fn public_url(&self, headers: &HeaderMap) -> Option<String> {
+        if let Some(public_url) = &self.public_url {
+            return Some(public_url.clone());
+        }
+        let host = headers.get(HOST)?.to_str().ok()?;
+        let scheme = match headers.get(FORWARDED_PROTO).map(|proto| proto.to_str()) {
+            Some(Ok("https")) => "https",
+            _ => "http",
+        };
+        Some(format!("{scheme}://{host}"))
+    }
+
+    /// Replaces the locators with the service's content endpoint, so that clients never see
+    /// the backing store's URLs or credentials. Should the service's URL be unknown, because
+    /// none is configured and the request has no `Host` header, the locators are dropped.
+    pub fn route_through_service(
+        &self,
+        descriptors: Vec<AssetDescriptor>,
+        headers: &HeaderMap,
+    ) -> Vec<AssetDescriptor> {
+        let public_url = self.public_url(headers);
+        descriptors
+            .into_iter()
+            .map(|mut descriptor| {
+                let mut locator = public_url
+                    .as_deref()
+                    .and_then(|url| url.parse().ok())
+                    .map(AssetLocator::new);
+                if let Some(locator) = locator.as_mut() {
+                    if let Ok(mut segments) = locator.url.path_segments_mut() {
+                        segments.pop_if_empty().extend([
+                            "assets",
+                            &descriptor.name,
+                            &descriptor.version.to_string(),
+                            "content",
+                        ]);
+                    }
+                }
+                descriptor.locators = locator.into_iter().collect();
+                descriptor
+            })
+            .collect()
+    }
+}
+
+/// Queries an index borrowed from the connection pool.
+pub struct PooledAssetIndex {
+    pool: bb8::Pool<AssetIndexConnectionManager>,
+}
+
+#[async_trait]
+impl AsyncAssetIndex for PooledAssetIndex {
+    async fn list_assets(
+        &self,
+        query: &AssetQuery,
+    ) -> Result<Vec<AssetDescriptor>, ListAssetsError> {
+        let index = self
+            .pool
+            .get()
+            .await
+            .map_err(|e| ListAssetsError::AssetIndexUnavailable(e.to_string()))?;
+        index.list_assets(query).await
     }
 }
 
@@ -28,18 +148,63 @@ pub enum AssetIndexConnectionType {
         blob_container_name: String,
         sas_token: String,
     },
+    S3AssetIndex {
+        endpoint: String,
+        region: String,
+        bucket: String,
+        access_key_id: String,
+        secret_access_key: String,
+        use_object_tags: bool,
+    },
+}
+
+impl From<AssetIndex> for AssetIndexConnectionType {
+    fn from(settings: AssetIndex) -> Self {
+        match settings {
+            AssetIndex::S3 {
+                endpoint,
+                region,
+                bucket,
+                access_key_id,
+                secret_access_key,
+                use_object_tags,
+            } => AssetIndexConnectionType::S3AssetIndex {
+                endpoint,
+                region,
+                bucket,
+                access_key_id,
+                secret_access_key,
+                use_object_tags,
+            },
+            AssetIndex::AzureBlob {
+                storage_account_name,
+                blob_container_name,
+                blob_sas_token,
+            } => AssetIndexConnectionType::AzureBlobAssetIndex {
+                storage_account_name,
+                blob_container_name,
+                sas_token: blob_sas_token,
+            },
+        }
+    }
 }
 
 pub struct AssetIndexConnectionManager {
     pub asset_index_connection_type: AssetIndexConnectionType,
+    pub http_client: HttpClient,
 }
 
 #[derive(Error, Debug)]
-pub enum AssetIndexConnectionError {}
+pub enum AssetIndexConnectionError {
+    #[error("The asset store was not configured properly. Details: {0}")]
+    MisconfiguredStore(String),
+    #[error("The asset index was not configured properly. Details: {0}")]
+    MisconfiguredIndex(String),
+}
 
 #[async_trait]
 impl ManageConnection for AssetIndexConnectionManager {
-    type Connection = iora::http::AzureBlobAssetIndex;
+    type Connection = Box<dyn AsyncAssetIndex>;
     type Error = AssetIndexConnectionError;
 
     async fn connect(&self) -> Result<Self::Connection, Self::Error> {
@@ -48,11 +213,37 @@ impl ManageConnection for AssetIndexConnectionManager {
                 storage_account_name,
                 blob_container_name,
                 sas_token,
-            } => Ok(AzureBlobAssetIndex::new(
-                storage_account_name,
-                blob_container_name,
-                sas_token,
+            } => Ok(Box::new(
+                AzureBlobAssetIndex::new(storage_account_name, blob_container_name, sas_token)
+                    .with_http_client(self.http_client.clone()),
             )),
+            AssetIndexConnectionType::S3AssetIndex {
+                endpoint,
+                region,
+                bucket,
+                access_key_id,
+                secret_access_key,
+                use_object_tags,
+            } => {
+                let endpoint = endpoint
+                    .parse()
+                    .map_err(|e| AssetIndexConnectionError::MisconfiguredIndex(format!("{e}")))?;
+                let attribute_source = if *use_object_tags {
+                    S3AttributeSource::ObjectTags
+                } else {
+                    S3AttributeSource::ObjectMetadata
+                };
+                Ok(Box::new(
+                    S3AssetIndex::new(S3Bucket::new(
+                        endpoint,
+                        region,
+                        bucket,
+                        S3Credentials::new(access_key_id, secret_access_key),
+                    ))
+                    .with_attribute_source(attribute_source)
+                    .with_http_client(self.http_client.clone()),
+                ))
+            }
         }
     }
 
@@ -0,0 +1,128 @@
+use crate::list_assets::ListAssetsServiceError;
+use crate::IoraServiceState;
+use axum::body::StreamBody;
+use axum::extract::{Extension, Path};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use iora::{
+    AssetDescriptor, AssetQuery, AssetStoreError, AsyncAssetIndex, AsyncAssetStore, NameConstraint,
+    SemVer, VersionConstraint,
+};
+use serde_json::json;
+use std::str::FromStr;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio_util::io::ReaderStream;
+use tracing::{event, Level};
+
+#[derive(Error, Debug)]
+pub enum FetchAssetServiceError {
+    #[error(
+        "The provided version was malformed: '{0}'. A full version such as '1.2.3' is required."
+    )]
+    MalformedVersion(String),
+    #[error("No asset named '{name}' with version '{version}' was found.")]
+    AssetNotFound { name: String, version: String },
+    #[error(transparent)]
+    ListAssetsError(#[from] ListAssetsServiceError),
+    #[error("The asset's content didn't match its hash. Expected: {expected} Actual: {actual}")]
+    AssetHashMismatch { expected: String, actual: String },
+    /// The details are only logged, as they can name the locators the content came from.
+    #[error("Failed to retrieve the asset content.")]
+    AssetStoreError(String),
+}
+
+impl From<AssetStoreError> for FetchAssetServiceError {
+    fn from(e: AssetStoreError) -> Self {
+        match e {
+            AssetStoreError::AssetHashMismatch { expected, actual } => {
+                Self::AssetHashMismatch { expected, actual }
+            }
+            e => {
+                // Store errors already leave the query out of any locator URL they contain.
+                let details = e.to_string();
+                event!(
+                    Level::ERROR,
+                    error = details.as_str(),
+                    "fetching asset content failed"
+                );
+                Self::AssetStoreError(details)
+            }
+        }
+    }
+}
+
+impl IntoResponse for FetchAssetServiceError {
+    fn into_response(self) -> Response {
+        let message = self.to_string();
+        let mapping = match self {
+            FetchAssetServiceError::ListAssetsError(e) => return e.into_response(),
+            FetchAssetServiceError::MalformedVersion(_) => {
+                (StatusCode::BAD_REQUEST, "MalformedVersion")
+            }
+            FetchAssetServiceError::AssetNotFound { .. } => {
+                (StatusCode::NOT_FOUND, "AssetNotFound")
+            }
+            FetchAssetServiceError::AssetHashMismatch { .. } => {
+                (StatusCode::BAD_GATEWAY, "AssetHashMismatch")
+            }
+            FetchAssetServiceError::AssetStoreError(_) => {
+                (StatusCode::BAD_GATEWAY, "AssetStoreError")
+            }
+        };
+        (
+            mapping.0,
+            json!({ "code": mapping.1, "message": message}).to_string(),
+        )
+            .into_response()
+    }
+}
+
+/// Streams the content of a single asset version through the service.
+///
+/// The service's store is a filesystem cache, which checks cached and downloaded content
+/// against the descriptor's hash before returning it. A corrupt asset therefore fails the
+/// request, or is fetched again, before the headers are sent, and `Content-Length` can be
+/// taken from the descriptor.
+pub async fn fetch_asset_content(
+    Path((name, version)): Path<(String, String)>,
+    Extension(state): Extension<Arc<IoraServiceState>>,
+) -> Result<Response, FetchAssetServiceError> {
+    let version = SemVer::from_str(&version)
+        .map_err(|_| FetchAssetServiceError::MalformedVersion(version.clone()))?;
+    let query = AssetQuery::new(
+        NameConstraint::ExactMatch(name.clone()),
+        Some(VersionConstraint::ExactMatch(version.clone())),
+    );
+    let descriptor = state
+        .asset_index
+        .list_assets(&query)
+        .await
+        .map_err(ListAssetsServiceError::from)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| FetchAssetServiceError::AssetNotFound {
+            name,
+            version: version.to_string(),
+        })?;
+
+    let reader = state
+        .asset_store
+        .fetch_by_descriptor(&descriptor)
+        .await?
+        .into_reader();
+
+    Ok((
+        content_headers(&descriptor),
+        StreamBody::new(ReaderStream::new(reader)),
+    )
+        .into_response())
+}
+
+fn content_headers(descriptor: &AssetDescriptor) -> [(header::HeaderName, String); 3] {
+    [
+        (header::CONTENT_TYPE, "application/octet-stream".to_owned()),
+        (header::CONTENT_LENGTH, descriptor.size.to_string()),
+        (header::ETAG, format!("\"{}\"", descriptor.content_hash)),
+    ]
+}
@@ -1,16 +1,23 @@
 use crate::IoraServiceState;
-use axum::http::StatusCode;
+use axum::http::header::{HeaderName, AGE};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
 use axum::response::IntoResponse;
 use axum::{extract::Extension, extract::Query, response::Json};
-use iora::{AssetIndex, AssetQuery, ConstraintParsingError, ListAssetsError};
+use iora::{AssetQuery, ConstraintParsingError, Freshness, ListAssetsError, ResolutionOptions};
 use serde_json::json;
 use std::sync::Arc;
 use thiserror::Error;
 
+/// Tells clients whether the listing is fresh, or was served stale from the cache because
+/// the index is unavailable or the service is offline.
+const INDEX_FRESHNESS: HeaderName = HeaderName::from_static("x-iora-index-freshness");
+
 #[derive(Error, Debug)]
 pub enum ListAssetsServiceError {
     #[error("Asset index is missing or unavailable. {0:?}")]
     AssetIndexNotFound(Option<String>),
+    #[error("Asset index is temporarily unavailable. Details: {0}")]
+    AssetIndexUnavailable(String),
     #[error("Asset index refused access. {0:?}")]
     AssetIndexAccessDenied(Option<String>),
     #[error("Failed to execute the query. Details: {0}")]
@@ -46,6 +53,7 @@ impl From<ListAssetsError> for ListAssetsServiceError {
     fn from(e: ListAssetsError) -> Self {
         match e {
             ListAssetsError::AssetIndexNotFound(s) => Self::AssetIndexNotFound(s),
+            ListAssetsError::AssetIndexUnavailable(s) => Self::AssetIndexUnavailable(s),
             ListAssetsError::AssetIndexAccessDenied(s) => Self::AssetIndexAccessDenied(s),
             ListAssetsError::BadQuery { query, details } => Self::BadQuery { query, details },
             ListAssetsError::AssetIndexInternalError(s) => Self::AssetIndexInternalError(s),
@@ -67,6 +75,7 @@ impl IntoResponse for ListAssetsServiceError {
             ListAssetsServiceError::MalformedVersionConstraint(_) => (StatusCode::BAD_REQUEST, "MalformedVersionConstraint".to_owned()),
             ListAssetsServiceError::AssetIndexAccessDenied(_) => (StatusCode::INTERNAL_SERVER_ERROR, "AssetIndexAccessDenied".to_owned()),
             ListAssetsServiceError::AssetIndexNotFound(_) => (StatusCode::INTERNAL_SERVER_ERROR, "AssetIndexNotFound".to_owned()),
+            ListAssetsServiceError::AssetIndexUnavailable(_) => (StatusCode::SERVICE_UNAVAILABLE, "AssetIndexUnavailable".to_owned()),
             ListAssetsServiceError::AssetIndexInternalError(_) => (StatusCode::INTERNAL_SERVER_ERROR, "AssetIndexInternalError".to_owned()),
         };
         (mapping.0, json!({ "code": mapping.1, "message": message}).to_string()).into_response()
@@ -81,16 +90,58 @@ pub struct ListAssetParameters {
 
 pub async fn list_assets(
     Query(q): Query<ListAssetParameters>,
+    request_headers: HeaderMap,
     Extension(state): Extension<Arc<IoraServiceState>>,
-) -> Result<Json<Vec<iora::AssetDescriptor>>, ListAssetsServiceError> {
-    let catalog = state.asset_index_connection_pool.get().await;
-    let query = AssetQuery::new_from_strings(&q.name, &q.version);
-    match (catalog, query) {
-        (Ok(catalog), Ok(query)) => match catalog.list_assets(&query) {
-            Ok(result) => Ok(Json::from(result)),
-            Err(list_error) => Err(list_error.into()),
-        },
-        (Err(_), _) => Err(ListAssetsServiceError::AssetIndexNotFound(None)),
-        (_, Err(e)) => Err(e.into()),
+) -> Result<(HeaderMap, Json<Vec<iora::AssetDescriptor>>), ListAssetsServiceError> {
+    let query = AssetQuery::new_from_strings(&q.name, &q.version)?;
+    let (result, freshness) = state
+        .asset_index
+        .list_assets_with_freshness_async(&query)
+        .await?;
+    Ok((
+        freshness_headers(freshness),
+        Json::from(state.route_through_service(result, &request_headers)),
+    ))
+}
+
+fn freshness_headers(freshness: Freshness) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    match freshness {
+        Freshness::Fresh => {
+            headers.insert(INDEX_FRESHNESS, HeaderValue::from_static("fresh"));
+        }
+        Freshness::Stale(age) => {
+            headers.insert(INDEX_FRESHNESS, HeaderValue::from_static("stale"));
+            headers.insert(AGE, HeaderValue::from(age.as_secs()));
+        }
     }
+    headers
+}
+
+#[derive(serde::Deserialize)]
+pub struct ListLatestAssetParameters {
+    name: String,
+    version: Option<String>,
+    #[serde(default)]
+    exclude_prereleases: bool,
+}
+
+pub async fn list_latest_assets(
+    Query(q): Query<ListLatestAssetParameters>,
+    request_headers: HeaderMap,
+    Extension(state): Extension<Arc<IoraServiceState>>,
+) -> Result<(HeaderMap, Json<Vec<iora::AssetDescriptor>>), ListAssetsServiceError> {
+    let query = AssetQuery::new_from_strings(&q.name, &q.version)?;
+    let options = ResolutionOptions {
+        exclude_prereleases: q.exclude_prereleases,
+    };
+    let (result, freshness) = state
+        .asset_index
+        .list_assets_with_freshness_async(&query)
+        .await?;
+    let result = iora::select_latest(result, &options);
+    Ok((
+        freshness_headers(freshness),
+        Json::from(state.route_through_service(result, &request_headers)),
+    ))
 }
@@ -1,9 +1,11 @@
 mod connections;
+mod fetch_asset;
 mod list_assets;
 mod settings;
 
-use connections::{AssetIndexConnectionType, IoraServiceState};
-use list_assets::list_assets;
+use connections::IoraServiceState;
+use fetch_asset::fetch_asset_content;
+use list_assets::{list_assets, list_latest_assets};
 use settings::{Settings, IoraServiceParameters};
 
 use axum::{extract::Extension, routing::get, Router};
@@ -15,16 +17,23 @@ use clap::Parser;
 #[tokio::main]
 async fn main() {
     let args = IoraServiceParameters::parse();
+    tracing_subscriber::fmt()
+        .with_ansi(false)
+        .with_max_level(tracing::Level::WARN)
+        .init();
     let settings = Settings::new(&args).unwrap();
+    let http_client = iora::http::HttpClient::new(&settings.http_client.config()).unwrap();
     let state = Arc::new(
         IoraServiceState::new(
-            AssetIndexConnectionType::AzureBlobAssetIndex {
-                storage_account_name: settings.asset_index.storage_account_name,
-                blob_container_name: settings.asset_index.blob_container_name,
-                sas_token: settings.asset_index.blob_sas_token }).await.unwrap());
-    let app = Router::new()
-        .route("/assets", get(list_assets))
-        .layer(Extension(state));
+            settings.asset_index.into(),
+            &settings.asset_index_cache,
+            &settings.asset_store.cache_path()
+                .expect("The asset store cache path couldn't be resolved."),
+            settings.asset_store.memory_cache_max_bytes,
+            settings.asset_store.max_size_bytes,
+            settings.service.public_url,
+            http_client).await.unwrap());
+    let app = app(state);
     let addr = SocketAddr::from(([0, 0, 0, 0], settings.service.port));
     println!("Listening on {}", addr);
     axum::Server::bind(&addr)
@@ -32,3 +41,172 @@ async fn main() {
         .await
         .unwrap();
 }
+
+fn app(state: Arc<IoraServiceState>) -> Router {
+    Router::new()
+        .route("/assets", get(list_assets))
+        .route("/assets/latest", get(list_latest_assets))
+        .route("/assets/:name/:version/content", get(fetch_asset_content))
+        .layer(Extension(state))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::app;
+    use crate::connections::IoraServiceState;
+    use axum::async_trait;
+    use axum::body::{Body, HttpBody};
+    use axum::http::{Request, StatusCode};
+    use axum::response::Response;
+    use iora::filesystem::FilesystemAssetStoreCache;
+    use iora::http::HttpAsssetStore;
+    use iora::memory::{MemoryAssetIndexCache, MemoryAssetStoreCache};
+    use iora::{
+        AssetDescriptor, AssetLocator, AssetQuery, AsyncAssetIndex, ListAssetsError, SemVer,
+    };
+    use std::path::Path;
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    const SAS_URL: &str = "https://account.blob.core.windows.net/assets/hello?sig=secret";
+
+    /// Lists a single asset, located at a URL carrying a SAS token.
+    struct SingleAssetIndex {
+        url: String,
+    }
+
+    #[async_trait]
+    impl AsyncAssetIndex for SingleAssetIndex {
+        async fn list_assets(
+            &self,
+            _query: &AssetQuery,
+        ) -> Result<Vec<AssetDescriptor>, ListAssetsError> {
+            Ok(vec![AssetDescriptor::new(
+                "hello",
+                SemVer::from_str("1.0.0").unwrap(),
+                HELLO_SHA256,
+                5,
+                vec![AssetLocator::new(self.url.parse().unwrap())],
+            )])
+        }
+    }
+
+    /// A service whose store already holds the asset, so that no request leaves the test.
+    fn state(cache_path: &Path, public_url: Option<&str>) -> Arc<IoraServiceState> {
+        let asset_folder = cache_path.join("hello/1.0.0");
+        std::fs::create_dir_all(&asset_folder).unwrap();
+        std::fs::write(asset_folder.join("asset"), "hello").unwrap();
+        uncached_state(cache_path, SAS_URL, public_url)
+    }
+
+    fn uncached_state(
+        cache_path: &Path,
+        url: &str,
+        public_url: Option<&str>,
+    ) -> Arc<IoraServiceState> {
+        let index: Box<dyn AsyncAssetIndex> = Box::new(SingleAssetIndex {
+            url: url.to_owned(),
+        });
+        Arc::new(IoraServiceState::from_parts(
+            MemoryAssetIndexCache::new(Duration::from_secs(60), index),
+            MemoryAssetStoreCache::new(
+                1024,
+                FilesystemAssetStoreCache::new(cache_path, HttpAsssetStore::new()).unwrap(),
+            ),
+            public_url.map(str::to_owned),
+        ))
+    }
+
+    async fn get(state: Arc<IoraServiceState>, uri: &str, host: Option<&str>) -> Response {
+        let mut request = Request::get(uri);
+        if let Some(host) = host {
+            request = request.header("host", host);
+        }
+        app(state)
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn body_of(response: Response) -> Vec<u8> {
+        let mut body = response.into_body();
+        let mut bytes = vec![];
+        while let Some(chunk) = body.data().await {
+            bytes.extend_from_slice(&chunk.unwrap());
+        }
+        bytes
+    }
+
+    async fn listed_locators(response: Response) -> Vec<String> {
+        assert_eq!(response.status(), StatusCode::OK);
+        let descriptors: serde_json::Value =
+            serde_json::from_slice(&body_of(response).await).unwrap();
+        descriptors[0]["locators"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|locator| locator["url"].as_str().unwrap().to_owned())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn serves_asset_content() {
+        let cache = tempfile::tempdir().unwrap();
+        let service = state(cache.path(), None);
+        let response = get(service.clone(), "/assets/hello/1.0.0/content", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_of(response).await, b"hello");
+
+        let response = get(service, "/assets/hello/1.0/content", None).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn keeps_store_failures_out_of_responses() {
+        let unreachable = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+        let cache = tempfile::tempdir().unwrap();
+        let service = uncached_state(
+            cache.path(),
+            &format!("http://{unreachable}/hello?sig=secret"),
+            None,
+        );
+        let response = get(service, "/assets/hello/1.0.0/content", None).await;
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        let body = String::from_utf8(body_of(response).await).unwrap();
+        assert!(body.contains("AssetStoreError"), "{body}");
+        assert!(!body.contains("secret"), "{body}");
+        assert!(!body.contains(&unreachable.to_string()), "{body}");
+    }
+
+    #[tokio::test]
+    async fn listings_point_at_the_service() {
+        let cache = tempfile::tempdir().unwrap();
+        let configured = state(cache.path(), Some("https://assets.example.com/iora/"));
+        let response = get(configured, "/assets?name=hello", Some("internal:8080")).await;
+        assert_eq!(
+            listed_locators(response).await,
+            vec!["https://assets.example.com/iora/assets/hello/1.0.0/content"]
+        );
+
+        let unconfigured = state(cache.path(), None);
+        let response = get(
+            unconfigured.clone(),
+            "/assets/latest?name=hello",
+            Some("iora:8080"),
+        )
+        .await;
+        assert_eq!(
+            listed_locators(response).await,
+            vec!["http://iora:8080/assets/hello/1.0.0/content"]
+        );
+        let response = get(unconfigured, "/assets?name=hello", None).await;
+        assert!(listed_locators(response).await.is_empty());
+    }
+}
@@ -1,5 +1,8 @@
 use config::{Config, ConfigError, Environment, File};
+use iora::http::HttpClientConfig;
 use serde::Deserialize;
+use std::path::PathBuf;
+use std::time::Duration;
 
 use clap::Parser;
 
@@ -11,22 +14,122 @@ pub struct IoraServiceParameters {
     pub port: u16,
 }
 
+/// The index the service serves assets from, selected by its `kind`: an S3-compatible
+/// bucket (`s3`) or an Azure blob container (`azure_blob`).
 #[derive(Debug, Deserialize)]
-pub struct AssetIndex {
-    pub storage_account_name: String,
-    pub blob_container_name: String,
-    pub blob_sas_token: String
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AssetIndex {
+    S3 {
+        endpoint: String,
+        region: String,
+        bucket: String,
+        access_key_id: String,
+        secret_access_key: String,
+        /// Read asset names, versions and hashes from object tags instead of metadata.
+        #[serde(default)]
+        use_object_tags: bool
+    },
+    AzureBlob {
+        storage_account_name: String,
+        blob_container_name: String,
+        blob_sas_token: String
+    },
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AssetIndexCache {
+    pub max_age_seconds: u64,
+    pub max_entries: usize,
+    /// How long past their max age cached results are served while the index is down.
+    pub stale_if_error_seconds: u64,
+    /// Answer only from the cache, without ever querying the index.
+    pub offline: bool,
+}
+
+impl AssetIndexCache {
+    pub fn max_age(&self) -> Duration {
+        Duration::from_secs(self.max_age_seconds)
+    }
+
+    pub fn stale_if_error(&self) -> Duration {
+        Duration::from_secs(self.stale_if_error_seconds)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AssetStore {
+    pub cache_path: PathBuf,
+    /// Hot assets are also kept in memory, up to this many bytes.
+    pub memory_cache_max_bytes: usize,
+    /// Least recently used assets are evicted from the disk cache beyond this many bytes.
+    /// The disk cache is unbounded by default.
+    pub max_size_bytes: Option<u64>,
+}
+
+impl AssetStore {
+    /// The cache requires an absolute path, so relative paths are resolved against the
+    /// working directory.
+    pub fn cache_path(&self) -> std::io::Result<PathBuf> {
+        if self.cache_path.is_absolute() {
+            Ok(self.cache_path.clone())
+        } else {
+            Ok(std::env::current_dir()?.join(&self.cache_path))
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Service {
     pub port: u16,
+    /// The URL clients use to reach the service, which listed descriptors point at instead
+    /// of at the backing store. Defaults to the URL each request was sent to.
+    pub public_url: Option<String>,
+}
+
+/// The HTTP client shared by the index and the store. Every setting is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct HttpClient {
+    pub connect_timeout_seconds: Option<u64>,
+    /// How long a whole request may take, including the download of an asset.
+    pub timeout_seconds: Option<u64>,
+    /// Overrides the HTTP_PROXY, HTTPS_PROXY and NO_PROXY environment variables.
+    pub proxy: Option<String>,
+    /// PEM or DER files with CA certificates trusted in addition to the system's.
+    pub ca_certificates: Vec<PathBuf>,
+    pub user_agent: Option<String>,
+}
+
+impl HttpClient {
+    pub fn config(&self) -> HttpClientConfig {
+        let mut config = HttpClientConfig::new();
+        if let Some(seconds) = self.connect_timeout_seconds {
+            config = config.with_connect_timeout(Duration::from_secs(seconds));
+        }
+        if let Some(seconds) = self.timeout_seconds {
+            config = config.with_timeout(Duration::from_secs(seconds));
+        }
+        if let Some(proxy) = &self.proxy {
+            config = config.with_proxy(proxy);
+        }
+        for path in self.ca_certificates.iter() {
+            config = config.with_ca_certificate(path);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            config = config.with_user_agent(user_agent);
+        }
+        config
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Settings {
     pub asset_index: AssetIndex,
-    pub service: Service
+    pub asset_index_cache: AssetIndexCache,
+    pub asset_store: AssetStore,
+    pub service: Service,
+    #[serde(default)]
+    pub http_client: HttpClient
 }
 
 impl Settings {
@@ -40,4 +143,51 @@ impl Settings {
             .build()?;
         s.try_deserialize()
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AssetIndex;
+    use config::{Config, File, FileFormat};
+
+    fn asset_index(toml: &str) -> Result<AssetIndex, config::ConfigError> {
+        Config::builder()
+            .add_source(File::from_str(toml, FileFormat::Toml))
+            .build()?
+            .try_deserialize()
+    }
+
+    #[test]
+    fn selects_the_asset_index_by_kind() {
+        let s3 = r#"
+            kind = "s3"
+            endpoint = "http://localhost:9000"
+            region = "us-east-1"
+            bucket = "assets"
+            access_key_id = "key"
+            secret_access_key = "secret"
+        "#;
+        assert!(matches!(
+            asset_index(s3),
+            Ok(AssetIndex::S3 {
+                use_object_tags: false,
+                ..
+            })
+        ));
+        let azure_blob = r#"
+            kind = "azure_blob"
+            storage_account_name = "ioratest"
+            blob_container_name = "assets"
+            blob_sas_token = ""
+        "#;
+        assert!(matches!(
+            asset_index(azure_blob),
+            Ok(AssetIndex::AzureBlob { .. })
+        ));
+
+        // A missing setting is reported instead of selecting another kind of index.
+        let incomplete = s3.replace(r#"region = "us-east-1""#, "");
+        assert!(asset_index(&incomplete).unwrap_err().to_string().contains("region"));
+        assert!(asset_index(r#"bucket = "assets""#).is_err());
+    }
 }
\ No newline at end of file